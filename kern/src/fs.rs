@@ -78,6 +78,19 @@ impl FileSystem {
             }
         };
         info!("filesystem: vfat init succeed");
+        let (clean, hard_error) = vfat.lock(|vfat| (vfat.was_clean(), vfat.had_hard_error()));
+        if !clean || hard_error {
+            info!("filesystem: volume was not cleanly unmounted or saw a disk error, checking");
+            match vfat.lock(|vfat| vfat.check(false)) {
+                Ok(problems) => {
+                    for problem in problems.iter() {
+                        info!("filesystem: {}", problem);
+                    }
+                    info!("filesystem: check found {} problem(s)", problems.len());
+                }
+                Err(e) => info!("filesystem: check failed: {:?}", e),
+            }
+        }
         *self.0.lock() = Some(vfat);
         info!("filesystem: init succeed");
     }
@@ -90,7 +90,12 @@ impl BlockDevice for Sd {
         }
     }
 
+    /// Writing to the SD card is not supported by `libsd`.
+    ///
+    /// # Errors
+    ///
+    /// Always returns an error of kind `PermissionDenied`.
     fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("SD card and file system are read only")
+        ioerr!(PermissionDenied, "Sd::write_sector: the SD card is read only")
     }
 }
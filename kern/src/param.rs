@@ -21,6 +21,11 @@ const_assert_eq!(
 pub const USER_STACK_BASE: usize = core::usize::MAX & PAGE_MASK; //0xffff_ffff_ffff_0000
 pub const USER_MAX_VM_SIZE: usize = 0x4000_0000;
 const_assert_eq!(USER_IMG_BASE.wrapping_add(USER_MAX_VM_SIZE), 0);
+/// The most bytes of the user stack the arguments and the environment of a
+/// program may take.
+pub const USER_ARG_MAX: usize = PAGE_SIZE / 4;
+/// The most pages the segments of a program may map, i.e. 16MiB.
+pub const USER_IMAGE_MAX_PAGES: usize = 256;
 
 pub const KERN_STACK_BASE: usize = 0x80_000;
 pub const KERN_STACK_ALIGN: usize = PAGE_ALIGN;
@@ -3,10 +3,13 @@ mod scheduler;
 mod stack;
 mod state;
 mod context;
+mod file;
+mod elf;
 
-pub use self::process::{Id, Process, Priority};
+pub use self::process::{Id, Process, Priority, INIT_PID};
 pub use self::scheduler::GlobalScheduler;
 pub use self::stack::Stack;
 pub use self::state::State;
 pub use self::context::Context;
+pub use self::file::OpenFile;
 pub use crate::param::TICK;
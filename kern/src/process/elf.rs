@@ -0,0 +1,122 @@
+use kernel_api::{OsError, OsResult};
+
+/// The size of the ELF64 file header.
+pub const EHDR_SIZE: usize = 64;
+/// The size of an ELF64 program header.
+pub const PHDR_SIZE: usize = 56;
+
+/// `p_type` of a loadable segment.
+pub const PT_LOAD: u32 = 1;
+
+// `p_flags` of a segment
+
+pub const PF_X: u32 = 0x1;
+pub const PF_W: u32 = 0x2;
+pub const PF_R: u32 = 0x4;
+
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_AARCH64: u16 = 183;
+
+fn u16_at(buf: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([buf[i], buf[i + 1]])
+}
+
+fn u32_at(buf: &[u8], i: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buf[i..i + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn u64_at(buf: &[u8], i: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buf[i..i + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// The fields of an ELF64 file header the loader uses.
+#[derive(Debug, Clone, Copy)]
+pub struct ElfHeader {
+    /// The address of the entry point.
+    pub entry: u64,
+    /// The file offset of the program header table.
+    pub phoff: u64,
+    /// The number of program headers.
+    pub phnum: u16,
+}
+
+impl ElfHeader {
+    /// Parses the file header at the start of `buf`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if `buf` is shorter than `EHDR_SIZE` or does
+    /// not hold the header of a little endian ELF64 executable for AArch64.
+    pub fn parse(buf: &[u8]) -> OsResult<ElfHeader> {
+        if buf.len() < EHDR_SIZE
+            || &buf[0..4] != b"\x7fELF"
+            || buf[4] != ELFCLASS64
+            || buf[5] != ELFDATA2LSB
+            || buf[6] != EV_CURRENT
+            || u16_at(buf, 16) != ET_EXEC
+            || u16_at(buf, 18) != EM_AARCH64
+            || u32_at(buf, 20) != EV_CURRENT as u32
+            || u16_at(buf, 54) as usize != PHDR_SIZE
+        {
+            return Err(OsError::InvalidArgument);
+        }
+        Ok(ElfHeader {
+            entry: u64_at(buf, 24),
+            phoff: u64_at(buf, 32),
+            phnum: u16_at(buf, 56),
+        })
+    }
+}
+
+/// The fields of an ELF64 program header the loader uses.
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub flags: u32,
+    /// The file offset of the contents of the segment.
+    pub offset: u64,
+    pub vaddr: u64,
+    /// The size of the contents of the segment in the file.
+    pub filesz: u64,
+    /// The size of the segment in memory. The bytes past `filesz` are zeros.
+    pub memsz: u64,
+}
+
+impl ProgramHeader {
+    /// Parses the program header at the start of `buf`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if `buf` is shorter than `PHDR_SIZE`, or if
+    /// the header describes a segment larger in the file than in memory.
+    pub fn parse(buf: &[u8]) -> OsResult<ProgramHeader> {
+        if buf.len() < PHDR_SIZE {
+            return Err(OsError::InvalidArgument);
+        }
+        let header = ProgramHeader {
+            p_type: u32_at(buf, 0),
+            flags: u32_at(buf, 4),
+            offset: u64_at(buf, 8),
+            vaddr: u64_at(buf, 16),
+            filesz: u64_at(buf, 32),
+            memsz: u64_at(buf, 40),
+        };
+        if header.filesz > header.memsz {
+            return Err(OsError::InvalidArgument);
+        }
+        Ok(header)
+    }
+
+    /// Returns the address one past the end of the segment in memory, or
+    /// `None` if it overflows.
+    pub fn end(&self) -> Option<u64> {
+        self.vaddr.checked_add(self.memsz)
+    }
+}
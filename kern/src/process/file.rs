@@ -0,0 +1,249 @@
+use core::iter::Peekable;
+use shim::io::{self, Read, Seek, SeekFrom, Write};
+use shim::path::{Component, PathBuf};
+
+use fat32::traits::{Dir as _, Entry as _, File, FileSystem, Metadata as _};
+use fat32::vfat::{DirIter, Entry};
+use kernel_api::*;
+
+use crate::fs::PiVFatHandle;
+use crate::process::Process;
+use crate::FILESYSTEM;
+
+/// A file or directory opened by a process, as kept in its open file table.
+#[derive(Debug)]
+pub struct OpenFile {
+    /// The opened entry. Its position is the offset of the descriptor.
+    pub entry: Entry<PiVFatHandle>,
+    /// The `O_*` flags the entry was opened with.
+    pub flags: u64,
+    /// The entries of a directory `read_dir()` has yet to return, read on its
+    /// first call.
+    pub entries: Option<Peekable<DirIter<PiVFatHandle>>>,
+}
+
+impl OpenFile {
+    fn readable(&self) -> bool {
+        self.flags & O_ACCMODE != O_WRONLY
+    }
+
+    fn writable(&self) -> bool {
+        self.flags & O_ACCMODE != O_RDONLY
+    }
+}
+
+impl Process {
+    /// Returns the absolute path of `path`, relative to the working directory
+    /// unless it is absolute, with `.` and `..` components resolved.
+    pub fn resolve(&self, path: &str) -> PathBuf {
+        let mut resolved = PathBuf::from("/");
+        for component in self.cwd.join(path).components() {
+            match component {
+                Component::Normal(name) => resolved.push(name),
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                _ => {}
+            }
+        }
+        resolved
+    }
+
+    /// Changes the working directory to the directory at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if `path` is not a directory, and the error
+    /// of the file system if opening it fails.
+    pub fn chdir(&mut self, path: &str) -> OsResult<()> {
+        let path = self.resolve(path);
+        match FILESYSTEM.open(&path)? {
+            Entry::Dir(_) => {
+                self.cwd = path;
+                Ok(())
+            }
+            Entry::File(_) => Err(OsError::InvalidArgument),
+        }
+    }
+
+    /// Opens the entry at `path`, relative to the working directory of the
+    /// process, with the `O_*` flags `flags` and returns its descriptor: the
+    /// lowest free slot of the open file table.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TooManyOpenFiles` if every slot is in use, `InvalidArgument`
+    /// if the access mode is invalid or a directory is opened for writing,
+    /// and the error of the file system if opening or creating the entry
+    /// fails.
+    pub fn open(&mut self, path: &str, flags: u64) -> OsResult<u64> {
+        let fd = self.open_file_table.iter().position(Option::is_none).ok_or(OsError::TooManyOpenFiles)?;
+        if flags & O_ACCMODE == O_ACCMODE {
+            return Err(OsError::InvalidArgument);
+        }
+        let path = self.resolve(path);
+        let entry = match FILESYSTEM.open(&path) {
+            Ok(entry) => entry,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound && flags & O_CREAT != 0 => {
+                Entry::File(FILESYSTEM.create_file(&path)?)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mut file = OpenFile { entry, flags, entries: None };
+        let writable = file.writable();
+        match file.entry {
+            Entry::File(ref mut f) if writable && flags & O_TRUNC != 0 => f.set_len(0)?,
+            Entry::Dir(_) if writable => return Err(OsError::InvalidArgument),
+            _ => {}
+        }
+        self.open_file_table[fd] = Some(file);
+        Ok(fd as u64)
+    }
+
+    /// Returns the open file `fd`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidFile` if `fd` is not an open descriptor.
+    pub fn open_file(&mut self, fd: u64) -> OsResult<&mut OpenFile> {
+        match self.open_file_table.get_mut(fd as usize) {
+            Some(Some(file)) => Ok(file),
+            _ => Err(OsError::InvalidFile),
+        }
+    }
+
+    /// Reads from the regular file `fd` into `buf` and returns the number of
+    /// bytes read.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidFile` if `fd` is not open, `NoAccess` if it was opened
+    /// write-only and `InvalidArgument` if it is a directory.
+    pub fn read_file(&mut self, fd: u64, buf: &mut [u8]) -> OsResult<usize> {
+        let file = self.open_file(fd)?;
+        if !file.readable() {
+            return Err(OsError::NoAccess);
+        }
+        match file.entry {
+            Entry::File(ref mut f) => Ok(f.read(buf)?),
+            Entry::Dir(_) => Err(OsError::InvalidArgument),
+        }
+    }
+
+    /// Writes `buf` to the regular file `fd`, at its end if it was opened
+    /// with `O_APPEND`, and returns the number of bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidFile` if `fd` is not open and `NoAccess` if it was
+    /// opened read-only.
+    pub fn write_file(&mut self, fd: u64, buf: &[u8]) -> OsResult<usize> {
+        let file = self.open_file(fd)?;
+        if !file.writable() {
+            return Err(OsError::NoAccess);
+        }
+        let append = file.flags & O_APPEND != 0;
+        match file.entry {
+            Entry::File(ref mut f) => {
+                if append {
+                    f.seek(SeekFrom::End(0))?;
+                }
+                Ok(f.write(buf)?)
+            }
+            Entry::Dir(_) => Err(OsError::InvalidArgument),
+        }
+    }
+
+    /// Writes the records of the next entries of the directory `fd` to `buf`
+    /// in the layout of `kernel_api::Dirent`, as many as fit, and returns the
+    /// number of bytes written. Returns 0 once every entry has been read.
+    ///
+    /// The entries are those the directory holds on the first call: later
+    /// changes to it are not seen through `fd`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidFile` if `fd` is not open, and `InvalidArgument` if it
+    /// is a regular file or `buf` cannot hold the record of the next entry.
+    pub fn read_dir(&mut self, fd: u64, buf: &mut [u8]) -> OsResult<usize> {
+        let file = self.open_file(fd)?;
+        let dir = match file.entry {
+            Entry::Dir(ref dir) => dir,
+            Entry::File(_) => return Err(OsError::InvalidArgument),
+        };
+        if file.entries.is_none() {
+            file.entries = Some(dir.entries()?.peekable());
+        }
+        let entries = file.entries.as_mut().unwrap();
+        let mut written = 0;
+        // an entry is only consumed once its record fits in `buf`
+        while let Some(entry) = entries.peek() {
+            let (kind, size) = match entry {
+                Entry::File(ref f) => (DIRENT_FILE, f.size()),
+                Entry::Dir(_) => (DIRENT_DIR, 0),
+            };
+            let metadata = entry.metadata();
+            let flags = if metadata.read_only() { DIRENT_READ_ONLY } else { 0 }
+                | if metadata.hidden() { DIRENT_HIDDEN } else { 0 };
+            let name = entry.name();
+            let mut dirent = Dirent::new(name, kind, flags, size);
+            dirent.created = metadata.created_timestamp.to_unix().as_secs();
+            dirent.modified = metadata.modified_timestamp.to_unix().as_secs();
+            dirent.accessed = metadata.accessed_timestamp.to_unix().as_secs();
+
+            let record = match buf.get_mut(written..written + dirent.record_len as usize) {
+                Some(record) => record,
+                None if written == 0 => return Err(OsError::InvalidArgument),
+                None => break,
+            };
+            record[..DIRENT_SIZE].copy_from_slice(&dirent.to_bytes());
+            record[DIRENT_SIZE..DIRENT_SIZE + name.len()].copy_from_slice(name.as_bytes());
+            for byte in record[DIRENT_SIZE + name.len()..].iter_mut() {
+                *byte = 0;
+            }
+            written += record.len();
+            entries.next();
+        }
+        Ok(written)
+    }
+
+    /// Moves the offset of the regular file `fd` to `pos` and returns the new
+    /// offset.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidFile` if `fd` is not open, `InvalidArgument` if it is
+    /// a directory and `IoErrorInvalidInput` if the offset would be negative.
+    pub fn seek_file(&mut self, fd: u64, pos: SeekFrom) -> OsResult<u64> {
+        match self.open_file(fd)?.entry {
+            Entry::File(ref mut f) => Ok(f.seek(pos)?),
+            Entry::Dir(_) => Err(OsError::InvalidArgument),
+        }
+    }
+
+    /// Closes `fd`, first writing the changes made through it to the disk.
+    /// The slot is freed even if that fails.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidFile` if `fd` is not open, or the error of the flush.
+    pub fn close(&mut self, fd: u64) -> OsResult<()> {
+        self.open_file(fd)?;
+        let mut file = self.open_file_table[fd as usize].take().unwrap();
+        let writable = file.writable();
+        match file.entry {
+            Entry::File(ref mut f) if writable => Ok(f.flush()?),
+            _ => Ok(()),
+        }
+    }
+
+    /// Closes every open file, as `close()` does. Errors are ignored since
+    /// there is no one left to report them to when a process exits.
+    pub fn close_all(&mut self) {
+        for fd in 0..self.open_file_table.len() {
+            if self.open_file_table[fd].is_some() {
+                let _ = self.close(fd as u64);
+            }
+        }
+    }
+}
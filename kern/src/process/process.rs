@@ -10,20 +10,24 @@ use aarch64;
 use smoltcp::socket::SocketHandle;
 
 use crate::{VMM, FILESYSTEM, param::*};
-use crate::process::{Stack, State, Context};
+use crate::process::{Stack, State, Context, OpenFile};
 use crate::traps::TrapFrame;
 use crate::vm::*;
 use kernel_api::{OsError, OsResult};
 
 use fat32::traits::FileSystem;
 use fat32::traits::File;
-use crate::fs::PiVFatHandle;
 
 use core::fmt::{self, Debug};
 
 /// Type alias for the type of a process ID.
 pub type Id = u64;
 
+/// The ID of the first process, which adopts the children of the processes
+/// that exit before them and reaps them. It is never given to another
+/// process.
+pub const INIT_PID: Id = 0;
+
 #[derive(Debug, Copy, Clone)]
 pub enum Priority {
     Low = 0,
@@ -71,8 +75,8 @@ pub struct Process {
     pub stack: Stack,
     /// The page table describing the Virtual Memory of the process.
     pub vmap: Option<Box<UserPageTable>>,
-    /// The open file table of the process.
-    pub open_file_table: [Option<fat32::vfat::Entry<PiVFatHandle>>; 16],
+    /// The open file table of the process, indexed by file descriptor.
+    pub open_file_table: [Option<OpenFile>; 16],
     /// The current working directory of the process.
     pub cwd: PathBuf,
     /// The scheduling state of the process.
@@ -81,6 +85,12 @@ pub struct Process {
     pub next_tick_time: Option<core::time::Duration>,
     /// The priority of the process.
     pub priority: Priority,
+    /// The id of the parent process, `None` for a process loaded by the
+    /// kernel.
+    pub parent: Option<Id>,
+    /// Set when a child of the process becomes a zombie, to wake the process
+    /// from `waitpid`.
+    pub child_exited: bool,
     // Lab 5 2.C
     // Socket handles held by the current process
     // pub sockets: Vec<SocketHandle>,
@@ -121,6 +131,8 @@ impl Process {
                 open_file_table: Default::default(),
                 next_tick_time: None,
                 priority: Priority::Low,
+                parent: None,
+                child_exited: false,
             })
         } else {
             Err(OsError::NoMemory)
@@ -128,53 +140,234 @@ impl Process {
     }
 
     /// Loads a program stored in the given path by calling `do_load()` method.
-    /// Sets trapframe `context` corresponding to its page table.
-    /// `sp` - the address of stack top
-    /// `elr` - the address of image base.
-    /// `ttbr0` - the base address of kernel page table
-    /// `ttbr1` - the base address of user page table
-    /// `spsr` - `F`, `A`, `D` bit should be set.
+    /// Sets trapframe `context` corresponding to its page table by calling
+    /// `init_image()`, with the path as the only argument and an empty
+    /// environment.
     ///
     /// Returns Os Error if do_load fails.
     pub fn load<P: AsRef<Path>>(pn: P) -> OsResult<Process> {
-        use crate::VMM;
-        use crate::console::kprintln;
-
-        let mut p = Process::do_load(pn)?;
+        let (mut p, entry) = Process::do_load(pn)?;
         info!("process: user program load succeed");
-        p.trap_frame.sp_els = Self::get_stack_top().as_u64();
-        p.trap_frame.elr_elx = Self::get_image_base().as_u64();
-        p.trap_frame.ttbr0_el1 = VMM.get_baddr().as_u64();
-        p.trap_frame.ttbr1_el1 = p.vmap.as_ref().unwrap().get_baddr().as_u64();
-        p.trap_frame.spsr_elx = 0b11_0100_0000;
+        let name = p.name.clone();
+        Self::init_image(&mut p.trap_frame, p.vmap.as_mut().unwrap(), entry, &[name.as_str()], &[])?;
         Ok(p)
     }
 
-    /// Creates a process and open a file with given path.
-    /// Allocates one page for stack with read/write permission, and N pages with read/write/execute
-    /// permission to load file's contents.
-    fn do_load<P: AsRef<Path>>(pn: P) -> OsResult<Process> {
-        // use crate::console::kprintln;
-        let mut f = FILESYSTEM.open_file(pn.as_ref().clone())?;
-        let mut process = Self::new(pn.as_ref().clone().to_str().unwrap(), false)?;
-
-        // assign memory page for code
-        let mut code_vaddr = Self::get_image_base();
-        while !f.is_end() {
-            use io::Read;
-            let page = process.vmap.as_mut().expect("user process should have vmap").alloc(code_vaddr, PagePerm::RWX);
-            let read_size = f.read(page)?;
-            code_vaddr += read_size.into();
+    /// Creates a process and loads the program at the given path into its
+    /// page table with `load_image()`. Returns the process and the entry
+    /// address of the program.
+    fn do_load<P: AsRef<Path>>(pn: P) -> OsResult<(Process, VirtualAddr)> {
+        let name = pn.as_ref().to_str().ok_or(OsError::InvalidArgument)?;
+        let mut process = Self::new(name, false)?;
+        let entry = Self::load_image(process.vmap.as_mut().unwrap(), pn.as_ref())?;
+        Ok((process, entry))
+    }
+
+    /// Opens the ELF64 AArch64 executable at the given path, maps each of its
+    /// `PT_LOAD` segments into `vmap` at its virtual address and returns its
+    /// entry address.
+    ///
+    /// A page gets the permissions of the flags of the segments it holds,
+    /// and the bytes of a segment past its contents in the file, such as
+    /// `.bss`, are zeros. One more page with read/write permission is
+    /// allocated for heap after the highest segment. The stack is left to
+    /// `init_image()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if the file is not an ELF64 AArch64
+    /// executable, if a header is malformed or lies outside of the file, if
+    /// a segment lies outside of the user image area, or if the entry address
+    /// is not in an executable segment. Returns `NoMemory` if the segments
+    /// span more than `USER_IMAGE_MAX_PAGES` pages. Nothing is mapped then.
+    fn load_image(vmap: &mut UserPageTable, pn: &Path) -> OsResult<VirtualAddr> {
+        use alloc::collections::BTreeMap;
+        use io::{Read, Seek, SeekFrom};
+        use crate::process::elf::*;
+
+        let mut f = FILESYSTEM.open_file(pn)?;
+        let size = f.size();
+        let mut read_at = |offset: u64, buf: &mut [u8]| -> OsResult<()> {
+            match offset.checked_add(buf.len() as u64) {
+                Some(end) if end <= size => {
+                    f.seek(SeekFrom::Start(offset))?;
+                    Ok(f.read_exact(buf)?)
+                }
+                _ => Err(OsError::InvalidArgument),
+            }
+        };
+
+        let mut buf = [0u8; EHDR_SIZE];
+        read_at(0, &mut buf)?;
+        let header = ElfHeader::parse(&buf)?;
+        let mut segments = Vec::new();
+        for i in 0..header.phnum as u64 {
+            let mut buf = [0u8; PHDR_SIZE];
+            let offset = header.phoff.checked_add(i * PHDR_SIZE as u64).ok_or(OsError::InvalidArgument)?;
+            read_at(offset, &mut buf)?;
+            let segment = ProgramHeader::parse(&buf)?;
+            if segment.p_type == PT_LOAD && segment.memsz > 0 {
+                segments.push(segment);
+            }
+        }
+
+        // the segments must lie between the image base and the stack
+        let image = Self::get_image_base().as_u64()..Self::get_stack_base().as_u64();
+        let mut image_end = image.start;
+        for segment in segments.iter() {
+            match segment.end() {
+                Some(end) if segment.vaddr >= image.start && end <= image.end => {
+                    image_end = image_end.max(end);
+                }
+                _ => return Err(OsError::InvalidArgument),
+            }
+        }
+        let executable = segments.iter().any(|segment| {
+            segment.flags & PF_X != 0 && segment.vaddr <= header.entry && header.entry < segment.end().unwrap()
+        });
+        if !executable {
+            return Err(OsError::InvalidArgument);
+        }
+        let heap = crate::allocator::util::align_up(image_end as usize, PAGE_SIZE) as u64;
+        if heap >= image.end {
+            return Err(OsError::InvalidArgument);
+        }
+
+        // bound the memory taken from the allocator before mapping anything;
+        // pages shared by segments are counted once per segment
+        let page_size = PAGE_SIZE as u64;
+        let pages_num: u64 = segments.iter()
+            .map(|segment| {
+                let first = segment.vaddr & PAGE_MASK as u64;
+                (segment.end().unwrap() - first + page_size - 1) / page_size
+            })
+            .sum();
+        if pages_num > USER_IMAGE_MAX_PAGES as u64 {
+            return Err(OsError::NoMemory);
+        }
+
+        // segments may share a page: collect the flags of each page first
+        let mut pages = BTreeMap::new();
+        for segment in segments.iter() {
+            let mut page = segment.vaddr & PAGE_MASK as u64;
+            while page < segment.end().unwrap() {
+                *pages.entry(page).or_insert(0) |= segment.flags;
+                page += PAGE_SIZE as u64;
+            }
+        }
+
+        for (&page, &flags) in pages.iter() {
+            let perm = match (flags & PF_W != 0, flags & PF_X != 0) {
+                (true, true) => PagePerm::RWX,
+                (true, false) => PagePerm::RW,
+                (false, true) => PagePerm::RX,
+                (false, false) => PagePerm::RO,
+            };
+            let buf = vmap.alloc(page.into(), perm);
+            let page_end = page + PAGE_SIZE as u64;
+            for segment in segments.iter() {
+                let start = segment.vaddr.max(page);
+                let end = (segment.vaddr + segment.filesz).min(page_end);
+                if start < end {
+                    let offset = segment.offset.checked_add(start - segment.vaddr).ok_or(OsError::InvalidArgument)?;
+                    read_at(offset, &mut buf[(start - page) as usize..(end - page) as usize])?;
+                }
+            }
         }
 
         // assign heap memory
-        code_vaddr = crate::allocator::util::align_up(code_vaddr.as_usize(), PAGE_SIZE).into();
-        process.vmap.as_mut().expect("user process should have vmap").alloc(code_vaddr, PagePerm::RWX);
+        vmap.alloc(heap.into(), PagePerm::RW);
+        Ok(header.entry.into())
+    }
 
-        // stack segment
-        let stack_vaddr = Self::get_stack_base();
-        process.vmap.as_mut().unwrap().alloc(stack_vaddr, PagePerm::RW);
-        Ok(process)
+    /// Allocates one page for stack with read/write permission in `vmap`,
+    /// lays `args` and `env` out at its top as described in
+    /// `kernel_api::env`, and resets `tf` to enter the image loaded in
+    /// `vmap`:
+    /// `sp` - below the arguments
+    /// `elr` - `entry`, the entry address of the image.
+    /// `ttbr0` - the base address of kernel page table
+    /// `ttbr1` - the base address of user page table
+    /// `spsr` - `F`, `A`, `D` bit should be set.
+    /// `x0`, `x1`, `x2` - `argc`, `argv` and `envp`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if the arguments and the environment take
+    /// more than `USER_ARG_MAX` bytes. `tf` is left unchanged then.
+    fn init_image(tf: &mut TrapFrame, vmap: &mut UserPageTable, entry: VirtualAddr, args: &[&str], env: &[&str]) -> OsResult<()> {
+        use crate::VMM;
+
+        let strings_len: usize = args.iter().chain(env).map(|s| s.len() + 1).sum();
+        let ptrs_len = (1 + args.len() + 1 + env.len() + 1) * 8;
+        let top = Self::get_stack_top().as_usize() - Self::get_stack_base().as_usize();
+        let strings = top.checked_sub(strings_len).ok_or(OsError::InvalidArgument)?;
+        let sp = strings.checked_sub(ptrs_len).ok_or(OsError::InvalidArgument)? & !(16 - 1);
+        if top - sp > USER_ARG_MAX {
+            return Err(OsError::InvalidArgument);
+        }
+
+        let stack = vmap.alloc(Self::get_stack_base(), PagePerm::RW);
+        let base = Self::get_stack_base().as_u64();
+        let mut ptr = sp;
+        let mut push = |value: u64| {
+            stack[ptr..ptr + 8].copy_from_slice(&value.to_le_bytes());
+            ptr += 8;
+        };
+        let mut string = base + strings as u64;
+        push(args.len() as u64);
+        for s in args {
+            push(string);
+            string += s.len() as u64 + 1;
+        }
+        push(0);
+        for s in env {
+            push(string);
+            string += s.len() as u64 + 1;
+        }
+        push(0);
+
+        let mut string = strings;
+        for s in args.iter().chain(env) {
+            stack[string..string + s.len()].copy_from_slice(s.as_bytes());
+            stack[string + s.len()] = 0;
+            string += s.len() + 1;
+        }
+
+        *tf = TrapFrame::default();
+        tf.sp_els = base + sp as u64;
+        tf.elr_elx = entry.as_u64();
+        tf.ttbr0_el1 = VMM.get_baddr().as_u64();
+        tf.ttbr1_el1 = vmap.get_baddr().as_u64();
+        tf.spsr_elx = 0b11_0100_0000;
+        tf.x[0] = args.len() as u64;
+        tf.x[1] = base + sp as u64 + 8;
+        tf.x[2] = base + sp as u64 + 8 * (args.len() as u64 + 2);
+        Ok(())
+    }
+
+    /// Replaces the image of the process with the program at `path`,
+    /// relative to the working directory unless it is absolute, started with
+    /// the arguments `args` and the environment `env`. `tf` is reset to
+    /// enter the new image. The open files and the working directory are
+    /// kept.
+    ///
+    /// The strings of `args` and `env` may live in the old image: they are
+    /// copied before it is released.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `load_image()` or `init_image()`, in which case
+    /// the process and `tf` are left unchanged.
+    pub fn exec(&mut self, path: &str, args: &[&str], env: &[&str], tf: &mut TrapFrame) -> OsResult<()> {
+        let path = self.resolve(path);
+        let mut vmap = Box::new(UserPageTable::new());
+        let entry = Self::load_image(&mut vmap, &path)?;
+        Self::init_image(tf, &mut vmap, entry, args, env)?;
+        tf.tpidr_els = self.pid;
+        self.vmap = Some(vmap);
+        self.name = path.to_str().unwrap().to_string();
+        Ok(())
     }
 
     /// Returns the highest `VirtualAddr` that is supported by this system.
@@ -219,7 +412,7 @@ impl Process {
             State::Ready | State::Running => return true,
             State::Start => panic!("thread just started should not reach here"),
             State::Waiting(_) => {}
-            State::Dead => return false,
+            State::Zombie(_) | State::Dead => return false,
         }
         // handle waiting state process
         let mut state = core::mem::replace(&mut self.state, State::Ready);
@@ -242,18 +435,29 @@ impl Process {
         }
     }
 
+    pub fn is_zombie(&self) -> bool {
+        match self.state {
+            State::Zombie(_) => true,
+            _ => false,
+        }
+    }
+
     /// Create a new process, copying the parent.
     pub fn fork(&mut self) -> OsResult<Process> {
         let mut p = Process::new("", false)?;
+        p.parent = Some(self.pid);
         p.cwd = self.cwd.clone();
         p.vmap.as_mut().unwrap().from(self.vmap.as_ref().unwrap());
         Ok(p)
     }
 
-    /// Write data to buf begin from vaddr.
-    pub fn write_vbuf(&self, data: &str, vaddr: VirtualAddr, size: usize) {
-        let mut paddr = self.vmap.as_ref().unwrap().get_kaddr(vaddr);
-        unsafe { core::ptr::copy(data.as_ptr(), paddr.as_mut_ptr(), size); }
+    /// Writes the working directory to `buf`, truncated to its length, and
+    /// returns the full length of the working directory.
+    pub fn getcwd(&self, buf: &mut [u8]) -> usize {
+        let wd = self.cwd.to_str().unwrap().as_bytes();
+        let len = wd.len().min(buf.len());
+        buf[..len].copy_from_slice(&wd[..len]);
+        wd.len()
     }
 }
 
@@ -19,7 +19,7 @@ use smoltcp::time::Instant;
 use crate::console::{kprintln, kprint};
 use crate::VMM;
 use crate::GlobalIrq;
-use crate::process::{Id, Process, State, Context, Priority};
+use crate::process::{Id, Process, State, Context, Priority, INIT_PID};
 use crate::mutex::Mutex;
 use crate::net::uspi::TKernelTimerHandle;
 use crate::param::*;
@@ -92,6 +92,19 @@ impl GlobalScheduler {
         self.critical(|scheduler| scheduler.kill(tf))
     }
 
+    /// Terminates the currently running process with the exit code `code`.
+    /// For more details, see the documentation on `Scheduler::exit()`.
+    pub fn exit(&self, code: i32, tf: &mut TrapFrame) -> ! {
+        self.critical(|scheduler| scheduler.exit(code, tf));
+        unreachable!()
+    }
+
+    /// Reaps a zombie child of the currently running process. For more
+    /// details, see the documentation on `Scheduler::reap()`.
+    pub fn reap(&self, pid: Option<Id>) -> OsResult<Option<(Id, i32)>> {
+        self.critical(|scheduler| scheduler.reap(pid))
+    }
+
     pub fn running_process_name(&self) -> String {
         self.critical(|scheduler| scheduler.running_process.as_ref().unwrap().name.clone())
     }
@@ -116,14 +129,12 @@ impl GlobalScheduler {
     //     })
     // }
 
-    // TODO: refactor it to check validitiy of buf
-    pub fn getcwd(&self, buf: u64, size: usize) {
-        self.critical(|scheduler| {
-            // let i = scheduler.running_thread();
-            let p = scheduler.running_process.as_ref().unwrap();
-            let wd = p.cwd.to_str().unwrap();
-            p.write_vbuf(wd, buf.into(), wd.len().min(size));
-        })
+    /// Calls `f` with the currently running process and returns its result.
+    pub fn with_running_process<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Process) -> R,
+    {
+        self.critical(|scheduler| f(scheduler.running_process.as_mut().unwrap()))
     }
 
     pub fn load<P: AsRef<shim::path::Path>>(&self, pn: P, priority: Option<Priority>) {
@@ -290,17 +301,14 @@ impl Scheduler {
         thread_context_ptr = &(*cur_thread.context) as *const Context as u64;
 
         match cur_thread.state {
-            State::Ready | State::Waiting(_) => {
+            State::Ready | State::Waiting(_) | State::Zombie(_) => {
                 let running_process = self.running_process.take().unwrap();
                 trace!("process {} schedule out", running_process.pid);
                 self.processes[running_process.priority as usize].push_back(running_process);
             },
             State::Dead => {
-                // reclaim id
                 let id = cur_thread.pid;
-                if self.last_id.unwrap() == id {
-                    self.last_id = id.checked_sub(1);
-                }
+                self.release_id(id);
                 info!("process {} dead", id);
             }
             State::Start | State::Running => unreachable!(),
@@ -405,14 +413,112 @@ impl Scheduler {
         panic!("Invalid TrapFrame");
     }
 
-    /// Kills currently running process by scheduling out the current process
-    /// as `Dead` state. The dead process's resource will be recycled by scheduler thread.
+    /// Kills currently running process by exiting it with the code -1.
     fn kill(&mut self, tf: &mut TrapFrame) -> ! {
-        // schedule out the current running process
-        self.schedule_out(State::Dead, tf);
+        self.exit(-1, tf)
+    }
+
+    /// Makes the id `id` of a reclaimed process available again. `INIT_PID`
+    /// is never reused, so that no later process adopts orphans in its place.
+    fn release_id(&mut self, id: Id) {
+        if id != INIT_PID && self.last_id == Some(id) {
+            self.last_id = id.checked_sub(1);
+        }
+    }
+
+    /// Returns the process `id` waiting in the queues.
+    fn queued_process_mut(&mut self, id: Id) -> Option<&mut Process> {
+        self.processes.iter_mut().flat_map(|queue| queue.iter_mut()).find(|p| p.pid == id)
+    }
+
+    /// Terminates the currently running process with the exit code `code`.
+    ///
+    /// Its open files are closed and its children are handed over to the
+    /// first process, `INIT_PID`. The process is scheduled out as a zombie
+    /// holding `code` until its parent reaps it with `reap()`, or as `Dead`
+    /// if it has no parent to do so; its resources are recycled then.
+    fn exit(&mut self, code: i32, tf: &mut TrapFrame) -> ! {
+        let process = self.running_process.as_mut().unwrap();
+        process.close_all();
+        let (pid, parent) = (process.pid, process.parent);
+
+        // reparent the children, which are never reaped if the first process
+        // is gone
+        let init = if pid != INIT_PID && self.queued_process_mut(INIT_PID).is_some() {
+            Some(INIT_PID)
+        } else {
+            None
+        };
+        let mut zombie_reparented = false;
+        for child in self.processes.iter_mut().flat_map(|queue| queue.iter_mut()) {
+            if child.parent != Some(pid) {
+                continue;
+            }
+            child.parent = init;
+            if child.is_zombie() {
+                match init {
+                    Some(_) => zombie_reparented = true,
+                    None => child.state = State::Dead,
+                }
+            }
+        }
+        if let Some(init) = init.and_then(|id| self.queued_process_mut(id)) {
+            init.child_exited |= zombie_reparented;
+        }
+
+        let state = match parent.and_then(|id| self.queued_process_mut(id)) {
+            Some(parent) => {
+                parent.child_exited = true;
+                State::Zombie(code)
+            }
+            None => State::Dead,
+        };
+        info!("process {} exited with code {}", pid, code);
+        self.schedule_out(state, tf);
         unreachable!()
     }
 
+    /// Reaps the zombie child `pid` of the currently running process, or any
+    /// zombie child if `pid` is `None`, and returns its id and exit code.
+    /// Returns `None` if the matching children are all alive.
+    ///
+    /// Clears the `child_exited` flag of the running process.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoChild` if the running process has no matching child.
+    fn reap(&mut self, pid: Option<Id>) -> OsResult<Option<(Id, i32)>> {
+        let parent = self.running_process.as_mut().unwrap();
+        parent.child_exited = false;
+        let parent = parent.pid;
+
+        let mut found = false;
+        let mut zombie = None;
+        for (i, queue) in self.processes.iter().enumerate() {
+            for (j, p) in queue.iter().enumerate() {
+                if p.parent != Some(parent) || pid.map_or(false, |pid| pid != p.pid) {
+                    continue;
+                }
+                found = true;
+                match p.state {
+                    State::Zombie(code) if zombie.is_none() => zombie = Some((i, j, code)),
+                    _ => {}
+                }
+            }
+        }
+
+        match zombie {
+            Some((i, j, code)) => {
+                let child = self.processes[i].remove(j).unwrap();
+                self.release_id(child.pid);
+                info!("process {} reaped", child.pid);
+                Ok(Some((child.pid, code)))
+            }
+            None if found => Ok(None),
+            None => Err(OsError::NoChild),
+        }
+    }
+
     /// Fork current running process and add the new process into queue.
     fn fork(&mut self, tf: &TrapFrame) -> OsResult<Id> {
         let mut fork_process = self.running_process.as_mut().unwrap().fork()?;
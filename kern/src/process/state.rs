@@ -21,6 +21,9 @@ pub enum State {
     Waiting(EventPollFn),
     /// The process is currently running.
     Running,
+    /// The process has exited with the code and is kept until its parent
+    /// reaps it.
+    Zombie(i32),
     /// The process is currently dead (ready to be reclaimed).
     Dead,
 }
@@ -32,6 +35,7 @@ impl fmt::Debug for State {
             State::Ready => write!(f, "State::Ready"),
             State::Running => write!(f, "State::Running"),
             State::Waiting(_) => write!(f, "State::Waiting"),
+            State::Zombie(code) => write!(f, "State::Zombie({})", code),
             State::Dead => write!(f, "State::Dead"),
         }
     }
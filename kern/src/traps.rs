@@ -64,6 +64,13 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
                     trace!("syscall {} triggered", syscall_num);
                     handle_syscall(syscall_num, tf);
                 },
+                // e.g. a write to a read-only page: the process would fault
+                // again on return, so it is terminated for its parent to reap
+                DataAbort { .. } | InstructionAbort { .. } if info.source == Source::LowerAArch64 => {
+                    info!("{:?} at 0x{:x} from 0x{:x}, killing process {}",
+                        Syndrome::from(esr), unsafe { FAR_EL1.get() }, tf.elr_elx, tf.tpidr_els);
+                    crate::SCHEDULER.kill(tf);
+                },
                 other => {
                     trace!("exception happened: {:#?}", info);
                     trace!("sync exception captured in: 0x{:x}", unsafe { FAR_EL1.get() });
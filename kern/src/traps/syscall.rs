@@ -1,16 +1,17 @@
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 use core::time::Duration;
 
 use smoltcp::wire::{IpAddress, IpEndpoint};
 
 use crate::console::{kprint, CONSOLE, kprintln};
-use crate::param::USER_IMG_BASE;
 use crate::process::State;
 use crate::traps::TrapFrame;
 use crate::{ETHERNET, SCHEDULER};
 
 use pi::timer;
 use kernel_api::*;
+use shim::io::SeekFrom;
 
 /// Sleep for `ms` milliseconds.
 ///
@@ -55,12 +56,55 @@ pub fn sys_time(tf: &mut TrapFrame) {
     tf.x[7] = 1;
 }
 
-/// Kills the current process.
+/// Terminates the current process.
 ///
-/// This system call does not take paramer and does not return any value.
-pub fn sys_exit(tf: &mut TrapFrame) {
-    // TODO: may need to modify
-    SCHEDULER.switch(State::Dead, tf);
+/// This system call takes one parameter: the exit code, which the parent
+/// process receives from `waitpid`. It does not return.
+pub fn sys_exit(code: i32, tf: &mut TrapFrame) {
+    SCHEDULER.exit(code, tf);
+}
+
+/// Waits for a child of the current process to exit and reaps it.
+///
+/// This system call takes two parameters: the ID of the child to wait for, or
+/// `u64::MAX` for any child, and the options, `WNOHANG` or 0. Unless `WNOHANG`
+/// is set, the process is blocked until a matching child exits.
+///
+/// In addition to the usual status value, this system call returns three
+/// parameters: the ID of the reaped child, its exit code, and 1 if a child was
+/// reaped or 0 if `WNOHANG` is set and the matching children are all alive.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::NoChild`: The current process has no matching child.
+pub fn sys_waitpid(pid: u64, options: u64, tf: &mut TrapFrame) {
+    let pid = if pid == core::u64::MAX { None } else { Some(pid) };
+    loop {
+        match SCHEDULER.reap(pid) {
+            Ok(Some((id, code))) => {
+                tf.x[0] = id;
+                tf.x[1] = code as u64;
+                tf.x[2] = 1;
+                tf.x[7] = OsError::Ok as u64;
+                return;
+            }
+            Ok(None) if options & WNOHANG != 0 => {
+                tf.x[2] = 0;
+                tf.x[7] = OsError::Ok as u64;
+                return;
+            }
+            Ok(None) => {
+                // retry once a child has exited
+                let event = Box::new(|p: &mut crate::process::Process| p.child_exited);
+                SCHEDULER.switch(State::Waiting(event), tf);
+            }
+            Err(e) => {
+                tf.x[7] = e as u64;
+                return;
+            }
+        }
+    }
 }
 
 /// Writes to console.
@@ -105,6 +149,37 @@ pub fn sys_fork(tf: &mut TrapFrame) {
     }
 }
 
+/// Replaces the current process's program with another one.
+///
+/// This system call takes six parameters: the address and the length of the
+/// path of the program, the address of an array of (address, length) pairs of
+/// `u64` describing the arguments and the number of arguments, and the same
+/// for the environment variables. At most `EXEC_MAX_ARGS` arguments and
+/// variables are accepted.
+///
+/// On success, this system call does not return: the new program starts with
+/// its arguments and environment laid out as described in `kernel_api::env`.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: An address and length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: A string is not UTF-8 encoded, there are too many arguments or
+///   variables, or they do not fit on the stack.
+/// - `OsError::NoMemory`: The program maps more than `USER_IMAGE_MAX_PAGES` pages.
+/// - The error of the file system if the program cannot be read.
+pub fn sys_exec(va: usize, len: usize, args_va: usize, argc: usize, env_va: usize, envc: usize, tf: &mut TrapFrame) {
+    let result = unsafe { to_user_str(va, len) }.and_then(|path| {
+        let args = unsafe { to_user_strs(args_va, argc)? };
+        let env = unsafe { to_user_strs(env_va, envc)? };
+        SCHEDULER.with_running_process(|p| p.exec(path, &args, &env, tf))
+    });
+
+    if let Err(e) = result {
+        tf.x[7] = e as u64;
+    }
+}
+
 /// Yield current CPU time interval.
 pub fn sys_yield(tf: &mut TrapFrame) {
     SCHEDULER.switch(State::Ready, tf);
@@ -130,20 +205,238 @@ pub fn sys_read(tf: &mut TrapFrame) {
 ///
 /// This system call need two parameters: buf addr: VirtualAddr and size: usize
 ///
-pub fn sys_getcwd(vaddr: u64, size: usize, tf: &mut TrapFrame) {
-    // TODO: check virtualaddr
-    kprintln!("getcwd: 0x{:x}, size: {}", vaddr, size);
-    SCHEDULER.getcwd(vaddr, size);
-    // TODO: adjust return value
-    tf.x[7] = 1;
+/// In addition to the usual status value, this system call returns one
+/// parameter: the length of the working directory, which is truncated to
+/// `size` bytes in the buffer.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the size pair does not form a valid userspace slice.
+pub fn sys_getcwd(va: usize, size: usize, tf: &mut TrapFrame) {
+    let result = unsafe { to_user_slice_mut(va, size) }
+        .map(|buf| SCHEDULER.with_running_process(|p| p.getcwd(buf)));
+
+    match result {
+        Ok(len) => {
+            tf.x[0] = len as u64;
+            tf.x[7] = OsError::Ok as u64;
+        }
+        Err(e) => {
+            tf.x[7] = e as u64;
+        }
+    }
 }
 
-pub fn sys_open() {
+/// Changes the current process's working directory.
+///
+/// This system call takes the address of the path as the first parameter and
+/// the length of the path as the second parameter. A relative path is
+/// resolved against the current working directory.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The path is not UTF-8 encoded or is not a directory.
+/// - `OsError::NoEntry`: There is no entry at the path.
+pub fn sys_chdir(va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = unsafe { to_user_slice(va, len) }
+        .and_then(|slice| core::str::from_utf8(slice).map_err(|_| OsError::InvalidArgument))
+        .and_then(|path| SCHEDULER.with_running_process(|p| p.chdir(path)));
 
+    match result {
+        Ok(()) => tf.x[7] = OsError::Ok as u64,
+        Err(e) => tf.x[7] = e as u64,
+    }
 }
 
-pub fn sys_readfile(fd: u64, vaddr: u64, size: usize) {
+/// Opens a file or a directory.
+///
+/// This system call takes the address of the path as the first parameter, the
+/// length of the path as the second parameter and the `O_*` flags of
+/// `kernel_api` as the third parameter. A relative path is resolved against
+/// the current working directory.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the file descriptor of the opened entry.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The path is not UTF-8 encoded, the access mode is invalid or a directory is opened for writing.
+/// - `OsError::TooManyOpenFiles`: The open file table of the process is full.
+/// - `OsError::NoEntry`: There is no entry at the path and `O_CREAT` is not set.
+/// - The error of the file system if opening or creating the entry fails.
+pub fn sys_open(va: usize, len: usize, flags: u64, tf: &mut TrapFrame) {
+    let result = unsafe { to_user_slice(va, len) }
+        .and_then(|slice| core::str::from_utf8(slice).map_err(|_| OsError::InvalidArgument))
+        .and_then(|path| SCHEDULER.with_running_process(|p| p.open(path, flags)));
 
+    match result {
+        Ok(fd) => {
+            tf.x[0] = fd;
+            tf.x[7] = OsError::Ok as u64;
+        }
+        Err(e) => {
+            tf.x[7] = e as u64;
+        }
+    }
+}
+
+/// Reads from an open regular file.
+///
+/// This system call takes a file descriptor as the first parameter, the
+/// address of the buffer as the second parameter, and the length of the buffer
+/// as the third parameter.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes read, 0 at the end of the file.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidFile`: The descriptor is not open.
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::NoAccess`: The file was opened write-only.
+/// - `OsError::InvalidArgument`: The descriptor refers to a directory.
+/// - The error of the file system if reading fails.
+pub fn sys_readfile(fd: u64, va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = unsafe { to_user_slice_mut(va, len) }
+        .and_then(|buf| SCHEDULER.with_running_process(|p| p.read_file(fd, buf)));
+
+    match result {
+        Ok(read) => {
+            tf.x[0] = read as u64;
+            tf.x[7] = OsError::Ok as u64;
+        }
+        Err(e) => {
+            tf.x[7] = e as u64;
+        }
+    }
+}
+
+/// Writes to an open regular file.
+///
+/// This system call takes a file descriptor as the first parameter, the
+/// address of the buffer as the second parameter, and the length of the buffer
+/// as the third parameter. A file opened with `O_APPEND` is written at its
+/// end.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes written.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidFile`: The descriptor is not open.
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::NoAccess`: The file was opened read-only, or the disk is read-only.
+/// - `OsError::InvalidArgument`: The descriptor refers to a directory.
+/// - The error of the file system if writing fails.
+pub fn sys_writefile(fd: u64, va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = unsafe { to_user_slice(va, len) }
+        .and_then(|buf| SCHEDULER.with_running_process(|p| p.write_file(fd, buf)));
+
+    match result {
+        Ok(written) => {
+            tf.x[0] = written as u64;
+            tf.x[7] = OsError::Ok as u64;
+        }
+        Err(e) => {
+            tf.x[7] = e as u64;
+        }
+    }
+}
+
+/// Reads the entries of an open directory.
+///
+/// This system call takes a file descriptor as the first parameter, the
+/// address of the buffer as the second parameter, and the length of the buffer
+/// as the third parameter. As many of the next entries as fit are written to
+/// the buffer as records in the layout of `kernel_api::Dirent`.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes written, 0 once every entry has been read.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidFile`: The descriptor is not open.
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The descriptor refers to a regular file, or the buffer cannot hold the next record.
+/// - The error of the file system if reading the directory fails.
+pub fn sys_getdents(fd: u64, va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = unsafe { to_user_slice_mut(va, len) }
+        .and_then(|buf| SCHEDULER.with_running_process(|p| p.read_dir(fd, buf)));
+
+    match result {
+        Ok(written) => {
+            tf.x[0] = written as u64;
+            tf.x[7] = OsError::Ok as u64;
+        }
+        Err(e) => {
+            tf.x[7] = e as u64;
+        }
+    }
+}
+
+/// Moves the offset of an open regular file.
+///
+/// This system call takes a file descriptor as the first parameter, the
+/// signed offset as the second parameter and `SEEK_SET`, `SEEK_CUR` or
+/// `SEEK_END` of `kernel_api` as the third parameter.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the new offset from the start of the file.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidFile`: The descriptor is not open.
+/// - `OsError::InvalidArgument`: The descriptor refers to a directory, `whence` is invalid, or the offset is negative with `SEEK_SET`.
+/// - `OsError::IoErrorInvalidInput`: The new offset would be negative.
+pub fn sys_lseek(fd: u64, offset: i64, whence: u64, tf: &mut TrapFrame) {
+    let pos = match whence {
+        SEEK_SET if offset >= 0 => Ok(SeekFrom::Start(offset as u64)),
+        SEEK_CUR => Ok(SeekFrom::Current(offset)),
+        SEEK_END => Ok(SeekFrom::End(offset)),
+        _ => Err(OsError::InvalidArgument),
+    };
+    let result = pos.and_then(|pos| SCHEDULER.with_running_process(|p| p.seek_file(fd, pos)));
+
+    match result {
+        Ok(offset) => {
+            tf.x[0] = offset;
+            tf.x[7] = OsError::Ok as u64;
+        }
+        Err(e) => {
+            tf.x[7] = e as u64;
+        }
+    }
+}
+
+/// Closes an open file or directory, first writing the changes made through
+/// it to the disk.
+///
+/// This system call takes a file descriptor as the first parameter.
+///
+/// It only returns the usual status value. The descriptor is closed even if
+/// an error is returned.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidFile`: The descriptor is not open.
+/// - The error of the file system if writing the changes fails.
+pub fn sys_close(fd: u64, tf: &mut TrapFrame) {
+    match SCHEDULER.with_running_process(|p| p.close(fd)) {
+        Ok(()) => tf.x[7] = OsError::Ok as u64,
+        Err(e) => tf.x[7] = e as u64,
+    }
 }
 
 /// Creates a socket and saves the socket handle in the current process's
@@ -223,28 +516,66 @@ pub fn sys_sock_listen(sock_idx: usize, local_port: u16, tf: &mut TrapFrame) {
     unimplemented!("sys_sock_listen")
 }
 
+/// Returns `true` if the `len` bytes at `va` are mapped in the user address
+/// space of the running process, and writable by it if `write` is set.
+fn is_user_range(va: usize, len: usize, write: bool) -> bool {
+    SCHEDULER.with_running_process(|p| match p.vmap {
+        Some(ref vmap) => vmap.is_user_range(va.into(), len, write),
+        None => false,
+    })
+}
+
 /// Returns a slice from a virtual address and a legnth.
 ///
 /// # Errors
 /// This functions returns `Err(OsError::BadAddress)` if the slice is not entirely
-/// in userspace.
+/// in pages mapped in userspace.
 unsafe fn to_user_slice<'a>(va: usize, len: usize) -> OsResult<&'a [u8]> {
-    let overflow = va.checked_add(len).is_none();
-    if va >= USER_IMG_BASE && !overflow {
+    if is_user_range(va, len, false) {
         Ok(core::slice::from_raw_parts(va as *const u8, len))
     } else {
         Err(OsError::BadAddress)
     }
 }
 
+/// Returns a string from a virtual address and a length.
+///
+/// # Errors
+/// This functions returns `Err(OsError::BadAddress)` if the string is not
+/// entirely in userspace, and `Err(OsError::InvalidArgument)` if it is not
+/// UTF-8 encoded.
+unsafe fn to_user_str<'a>(va: usize, len: usize) -> OsResult<&'a str> {
+    core::str::from_utf8(to_user_slice(va, len)?).map_err(|_| OsError::InvalidArgument)
+}
+
+/// Returns the strings described by the array of `count` (address, length)
+/// pairs at a virtual address, as passed to `exec`.
+///
+/// # Errors
+/// This functions returns `Err(OsError::InvalidArgument)` if `count` exceeds
+/// `EXEC_MAX_ARGS`, and the errors of `to_user_str()`.
+unsafe fn to_user_strs<'a>(va: usize, count: usize) -> OsResult<Vec<&'a str>> {
+    if count > EXEC_MAX_ARGS {
+        return Err(OsError::InvalidArgument);
+    } else if count == 0 {
+        return Ok(Vec::new());
+    }
+    let pairs = to_user_slice(va, count * 16)?;
+    let u64_at = |i: usize| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&pairs[i..i + 8]);
+        u64::from_le_bytes(bytes) as usize
+    };
+    (0..count).map(|i| to_user_str(u64_at(16 * i), u64_at(16 * i + 8))).collect()
+}
+
 /// Returns a mutable slice from a virtual address and a legnth.
 ///
 /// # Errors
 /// This functions returns `Err(OsError::BadAddress)` if the slice is not entirely
-/// in userspace.
+/// in pages mapped in userspace with write permission.
 unsafe fn to_user_slice_mut<'a>(va: usize, len: usize) -> OsResult<&'a mut [u8]> {
-    let overflow = va.checked_add(len).is_none();
-    if va >= USER_IMG_BASE && !overflow {
+    if is_user_range(va, len, true) {
         Ok(core::slice::from_raw_parts_mut(va as *mut u8, len))
     } else {
         Err(OsError::BadAddress)
@@ -328,15 +659,25 @@ pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
     match num {
         NR_SLEEP => sys_sleep(tf.x[0] as u32, tf),
         NR_WRITE => sys_write(tf.x[0] as u8, tf),
-        NR_EXIT => sys_exit(tf),
+        NR_EXIT => sys_exit(tf.x[0] as i32, tf),
         NR_GETPID => sys_getpid(tf),
         NR_TIME => sys_time(tf),
         NR_FORK => sys_fork(tf),
         NR_YIELD => sys_yield(tf),
         NR_READ => sys_read(tf),
-        NR_GETCWD => sys_getcwd(tf.x[0], tf.x[1] as usize, tf),
+        NR_GETCWD => sys_getcwd(tf.x[0] as usize, tf.x[1] as usize, tf),
+        NR_CHCWD => sys_chdir(tf.x[0] as usize, tf.x[1] as usize, tf),
+        NR_GETDENTS => sys_getdents(tf.x[0], tf.x[1] as usize, tf.x[2] as usize, tf),
+        NR_EXEC => sys_exec(tf.x[0] as usize, tf.x[1] as usize, tf.x[2] as usize, tf.x[3] as usize,
+                            tf.x[4] as usize, tf.x[5] as usize, tf),
         NR_WRITE_STR => sys_write_str(tf.x[0] as usize, tf.x[1] as usize, tf),
         NR_GETPRIORITY => sys_getpriority(tf),
+        NR_OPEN => sys_open(tf.x[0] as usize, tf.x[1] as usize, tf.x[2], tf),
+        NR_CLOSE => sys_close(tf.x[0], tf),
+        NR_READFILE => sys_readfile(tf.x[0], tf.x[1] as usize, tf.x[2] as usize, tf),
+        NR_WRITEFILE => sys_writefile(tf.x[0], tf.x[1] as usize, tf.x[2] as usize, tf),
+        NR_LSEEK => sys_lseek(tf.x[0], tf.x[1] as i64, tf.x[2], tf),
+        NR_WAITPID => sys_waitpid(tf.x[0], tf.x[1], tf),
         _ => {
             kprintln!("unimplemented syscall");
             unreachable!()
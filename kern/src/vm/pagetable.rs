@@ -280,6 +280,7 @@ impl KernPageTable {
 pub enum PagePerm {
     RW,
     RO,
+    RX,
     RWX,
 }
 
@@ -292,8 +293,9 @@ impl UserPageTable {
         UserPageTable(PageTable::new(EntryPerm::USER_RW))
     }
 
-    /// Allocates a page and set an L3 entry translates given virtual address to the
-    /// physical address of the allocated page. Returns the allocated page.
+    /// Allocates a zeroed page and set an L3 entry translates given virtual address to the
+    /// physical address of the allocated page with the permission `perm` for user space.
+    /// The kernel can never execute the page. Returns the allocated page.
     ///
     /// # Panics
     /// Panics if the virtual address is lower than `USER_IMG_BASE`.
@@ -301,8 +303,7 @@ impl UserPageTable {
     /// Panics if allocator fails to allocate a page.
     ///
     /// TODO. use Result<T> and make it failurable
-    /// TODO. use perm properly
-    pub fn alloc(&mut self, va: VirtualAddr, _perm: PagePerm) -> &mut [u8] {
+    pub fn alloc(&mut self, va: VirtualAddr, perm: PagePerm) -> &mut [u8] {
         if va.as_usize() < USER_IMG_BASE {
             panic!("virtual address is lower than USER_IMG_BASE");
         }
@@ -320,11 +321,20 @@ impl UserPageTable {
         if physical_addr.is_null() {
             panic!("allocator fails to allocate a page");
         }
+        unsafe { core::ptr::write_bytes(physical_addr, 0, PAGE_SIZE) };
+        let (ap, uxn) = match perm {
+            PagePerm::RW => (EntryPerm::USER_RW, 1),
+            PagePerm::RO => (EntryPerm::USER_RO, 1),
+            PagePerm::RX => (EntryPerm::USER_RO, 0),
+            PagePerm::RWX => (EntryPerm::USER_RW, 0),
+        };
         let mut entry = RawL3Entry::new(0);
         entry.set(physical_addr as u64);
         entry.set_bit(RawL3Entry::AF);
         entry.set_value(EntrySh::ISh, RawL3Entry::SH);
-        entry.set_value(EntryPerm::USER_RW, RawL3Entry::AP);
+        entry.set_value(ap, RawL3Entry::AP);
+        entry.set_value(uxn, RawL3Entry::UXN);
+        entry.set_bit(RawL3Entry::PXN);
         // NS: don't care
         entry.set_value(EntryAttr::Mem, RawL3Entry::ATTR);
         entry.set_value(PageType::Page, RawL3Entry::TYPE);
@@ -360,6 +370,36 @@ impl UserPageTable {
         }
     }
 
+    /// Returns `true` if every page holding the `len` bytes at the user
+    /// virtual address `va` is mapped for user space, and writable from it
+    /// if `write` is set. Returns `false` if the bytes are not entirely in
+    /// the user address space.
+    pub fn is_user_range(&self, va: VirtualAddr, len: usize, write: bool) -> bool {
+        let start = match va.as_usize().checked_sub(USER_IMG_BASE) {
+            Some(start) => start,
+            None => return false,
+        };
+        let end = match start.checked_add(len) {
+            Some(end) if end <= USER_MAX_VM_SIZE => end,
+            _ => return false,
+        };
+        let mut page = start & PAGE_MASK;
+        while page < end {
+            let entry = &self.get_entry_l3(page.into()).0;
+            let ap = entry.get_value(RawL3Entry::AP);
+            let allowed = if write {
+                ap == EntryPerm::USER_RW
+            } else {
+                ap == EntryPerm::USER_RW || ap == EntryPerm::USER_RO
+            };
+            if entry.get_value(RawL3Entry::VALID) != EntryValid::Valid || !allowed {
+                return false;
+            }
+            page += PAGE_SIZE;
+        }
+        true
+    }
+
     pub fn get_kaddr(&self, vaddr: VirtualAddr) -> PhysicalAddr {
         kprintln!("0x{:x}", vaddr.as_u64());
         self.0.get_phyaddr((vaddr - USER_IMG_BASE.into()))
@@ -61,6 +61,8 @@ defbit!(
 defbit!(
     RawL3Entry,
     [
+        UXN[54 - 54],
+        PXN[53 - 53],
         ADDR[47 - 16],
         AF[10 - 10],
         SH[09 - 08],
@@ -202,6 +204,15 @@ impl fmt::Debug for RawL3Entry {
             }
         )?;
 
+        write!(
+            f,
+            "{}",
+            match self.get_value(RawL3Entry::UXN) {
+                0 => "X",
+                _ => "-",
+            }
+        )?;
+
         // NS    [05-05],
 
         write!(
@@ -0,0 +1,239 @@
+use structopt;
+use structopt_derive::StructOpt;
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use structopt::StructOpt;
+
+use fat32::traits::{Clock, Dir as _, Entry as _, FileSystem, Metadata as _};
+use fat32::vfat::{BiosParameterBlock, VFat, VFatHandle};
+use fat32::{partitions, MasterBootRecord, PartitionInfo};
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "Inspect and modify FAT images with the kernel's fat32 code.")]
+struct Opt {
+    #[structopt(help = "Path to the disk image", parse(from_os_str))]
+    image: PathBuf,
+
+    #[structopt(short = "p", long = "partition",
+                help = "Index of the partition to use (defaults to the first FAT partition)")]
+    partition: Option<usize>,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    #[structopt(name = "info", about = "Print the partition table, the EBPB and the volume information")]
+    Info,
+
+    #[structopt(name = "label", about = "Print or set the volume label")]
+    Label {
+        #[structopt(help = "New label, or an empty string to remove the label")]
+        label: Option<String>,
+    },
+
+    #[structopt(name = "ls", about = "List a directory")]
+    Ls {
+        #[structopt(short = "l", help = "Use the long listing format")]
+        long: bool,
+        #[structopt(short = "a", help = "Show hidden entries")]
+        all: bool,
+        #[structopt(help = "Directory in the image", default_value = "/")]
+        path: String,
+    },
+
+    #[structopt(name = "cat", about = "Print a file to stdout")]
+    Cat {
+        #[structopt(help = "File in the image")]
+        path: String,
+    },
+
+    #[structopt(name = "cp-in", about = "Copy a host file into the image")]
+    CpIn {
+        #[structopt(help = "Host file", parse(from_os_str))]
+        src: PathBuf,
+        #[structopt(help = "Destination in the image")]
+        dst: String,
+    },
+
+    #[structopt(name = "cp-out", about = "Copy a file out of the image")]
+    CpOut {
+        #[structopt(help = "File in the image")]
+        src: String,
+        #[structopt(help = "Host destination", parse(from_os_str))]
+        dst: PathBuf,
+    },
+
+    #[structopt(name = "mkdir", about = "Create a directory")]
+    Mkdir {
+        #[structopt(help = "Directory in the image")]
+        path: String,
+    },
+
+    #[structopt(name = "rm", about = "Remove a file or an empty directory")]
+    Rm {
+        #[structopt(help = "Entry in the image")]
+        path: String,
+    },
+}
+
+impl Command {
+    /// Returns `true` if the command modifies the image.
+    fn writes(&self) -> bool {
+        match self {
+            Command::CpIn { .. } | Command::Mkdir { .. } | Command::Rm { .. } => true,
+            Command::Label { label } => label.is_some(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Handle(Arc<Mutex<VFat<Handle>>>);
+
+impl fmt::Debug for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Handle")
+    }
+}
+
+impl VFatHandle for Handle {
+    fn new(val: VFat<Handle>) -> Self {
+        Handle(Arc::new(Mutex::new(val)))
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut VFat<Handle>) -> R) -> R {
+        f(&mut self.0.lock().expect("all okay"))
+    }
+}
+
+/// Stamps entries with the host's time. The standard library does not know
+/// the local time zone, so UTC is recorded.
+#[derive(Debug)]
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+    }
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    if let Err(e) = run(&opt) {
+        eprintln!("fat32-tool: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(opt: &Opt) -> io::Result<()> {
+    let mut image = OpenOptions::new()
+        .read(true)
+        .write(opt.command.writes())
+        .open(&opt.image)?;
+    let partitions = partitions(&mut image).map_err(fat_error)?;
+    let partition = select_partition(&partitions, opt.partition)?;
+
+    if let Command::Info = opt.command {
+        print_info(&mut image, &partitions, &partition)?;
+    }
+
+    let vfat = VFat::<Handle>::from_partition(image, &partition).map_err(fat_error)?;
+    // timestamps are only kept on images opened for writing: recording the
+    // access date would otherwise fail on the read-only handle
+    if opt.command.writes() {
+        vfat.lock(|vfat| vfat.set_clock(SystemClock));
+    }
+    match &opt.command {
+        Command::Info => {
+            println!("{:#?}", vfat.lock(|vfat| vfat.info())?);
+            let (clean, hard_error) = vfat.lock(|vfat| (vfat.was_clean(), vfat.had_hard_error()));
+            println!("cleanly unmounted: {}, disk error recorded: {}", clean, hard_error);
+        },
+        Command::Label { label: None } => {
+            let info = vfat.lock(|vfat| vfat.info())?;
+            println!("{}", info.label.unwrap_or_default());
+        },
+        Command::Label { label: Some(label) } => vfat.lock(|vfat| vfat.set_label(label))?,
+        Command::Ls { long, all, path } => ls(&vfat, path, *long, *all)?,
+        Command::Cat { path } => {
+            let mut file = vfat.open_file(path)?;
+            io::copy(&mut file, &mut io::stdout())?;
+        },
+        Command::CpIn { src, dst } => {
+            let mut file = vfat.create_file(dst)?;
+            io::copy(&mut File::open(src)?, &mut file)?;
+        },
+        Command::CpOut { src, dst } => {
+            let mut file = vfat.open_file(src)?;
+            io::copy(&mut file, &mut File::create(dst)?)?;
+        },
+        Command::Mkdir { path } => {
+            vfat.create_dir(path)?;
+        },
+        Command::Rm { path } => {
+            if vfat.open(path)?.is_dir() {
+                vfat.remove_dir(path)?;
+            } else {
+                vfat.remove_file(path)?;
+            }
+        },
+    }
+    // write back the sector cache and the FSInfo sector
+    if opt.command.writes() {
+        vfat.lock(|vfat| vfat.flush())?;
+    }
+    Ok(())
+}
+
+/// Returns partition `index` of `partitions`, or the first FAT partition if
+/// no index is given.
+fn select_partition(partitions: &[PartitionInfo], index: Option<usize>) -> io::Result<PartitionInfo> {
+    let partition = match index {
+        Some(index) => partitions.get(index),
+        None => partitions.iter().find(|partition| partition.is_fat()),
+    };
+    partition.cloned().ok_or(io::Error::new(io::ErrorKind::NotFound, "no such FAT partition"))
+}
+
+fn print_info(image: &mut File, partitions: &[PartitionInfo], partition: &PartitionInfo) -> io::Result<()> {
+    let mbr = MasterBootRecord::from(&mut *image).map_err(|e| fat_error(e.into()))?;
+    println!("{:#?}", mbr);
+    for (i, partition) in partitions.iter().enumerate() {
+        println!("partition {}: {:?}", i, partition);
+    }
+    let ebpb = BiosParameterBlock::from(&mut *image, partition.start).map_err(fat_error)?;
+    println!("{:#?}", ebpb);
+    Ok(())
+}
+
+fn ls(vfat: &Handle, path: &str, long: bool, all: bool) -> io::Result<()> {
+    for entry in vfat.open_dir(path)?.entries()? {
+        if entry.metadata().hidden() && !all {
+            continue;
+        }
+        if long {
+            println!("{}", entry);
+        } else if entry.is_dir() {
+            println!("{}/", entry.name());
+        } else {
+            println!("{}", entry.name());
+        }
+    }
+    Ok(())
+}
+
+fn fat_error(e: fat32::vfat::Error) -> io::Error {
+    match e {
+        fat32::vfat::Error::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)),
+    }
+}
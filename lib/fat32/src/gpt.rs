@@ -0,0 +1,215 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::{char, fmt, mem, ptr};
+use shim::const_assert_size;
+use shim::io;
+
+use crate::mbr::{self, MasterBootRecord};
+use crate::traits::BlockDevice;
+use crate::util::crc32;
+
+/// The GUID partition table (GPT) header.
+#[repr(C, packed)]
+pub struct GptHeader {
+    signature: [u8; 8],
+    revision: u32,
+    header_size: u32,
+    header_crc32: u32, // computed with this field zeroed
+    _1: u32,
+    pub current_lba: u64,
+    pub backup_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: [u8; 16],
+    pub entries_lba: u64, // start of the partition entry array
+    pub entries_num: u32,
+    pub entry_size: u32,
+    entries_crc32: u32,
+}
+
+impl fmt::Debug for GptHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GptHeader")
+         .field("current_lba", &{ self.current_lba })
+         .field("backup_lba", &{ self.backup_lba })
+         .field("first_usable_lba", &{ self.first_usable_lba })
+         .field("last_usable_lba", &{ self.last_usable_lba })
+         .field("entries_lba", &{ self.entries_lba })
+         .field("entries_num", &{ self.entries_num })
+         .field("entry_size", &{ self.entry_size })
+         .finish()
+    }
+}
+
+const_assert_size!(GptHeader, 92);
+
+/// An entry of the GPT partition entry array.
+#[repr(C, packed)]
+pub struct GptPartitionEntry {
+    pub type_guid: [u8; 16], // in on-disk (mixed endian) byte order
+    pub unique_guid: [u8; 16],
+    pub first_lba: u64,
+    pub last_lba: u64, // inclusive
+    pub attributes: u64,
+    name: [u16; 36], // UTF-16LE, NUL padded
+}
+
+impl fmt::Debug for GptPartitionEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GptPartitionEntry")
+         .field("type_guid", &self.type_guid)
+         .field("first_lba", &{ self.first_lba })
+         .field("last_lba", &{ self.last_lba })
+         .field("attributes", &{ self.attributes })
+         .field("name", &self.name())
+         .finish()
+    }
+}
+
+const_assert_size!(GptPartitionEntry, 128);
+
+impl GptPartitionEntry {
+    /// Returns `true` if the entry does not describe a partition.
+    pub fn is_unused(&self) -> bool {
+        self.type_guid == [0; 16]
+    }
+
+    /// Returns `true` if the entry describes a Microsoft basic data
+    /// partition, the type GPT uses for FAT volumes.
+    pub fn is_basic_data(&self) -> bool {
+        self.type_guid == BASIC_DATA_GUID
+    }
+
+    /// Returns the number of sectors of the partition, or `None` if its first
+    /// and last LBAs do not describe a range of sectors.
+    pub fn num_sectors(&self) -> Option<u64> {
+        let (first_lba, last_lba) = (self.first_lba, self.last_lba);
+        if last_lba < first_lba {
+            return None;
+        }
+        last_lba.checked_add(1).map(|end| end - first_lba)
+    }
+
+    /// Returns the name of the partition.
+    pub fn name(&self) -> String {
+        let name = self.name;
+        char::decode_utf16(name.iter().cloned().take_while(|&c| c != 0))
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// There was an error while reading the protective MBR.
+    Mbr(mbr::Error),
+    /// There was an I/O error while reading the GPT.
+    Io(io::Error),
+    /// The MBR has no protective partition, so the device has no GPT.
+    NoProtectiveMbr,
+    /// The GPT header signature or one of its sizes was invalid, or its
+    /// partition entry array is larger than 1MiB.
+    BadSignature,
+    /// The CRC32 of the GPT header did not match.
+    BadHeaderChecksum,
+    /// The CRC32 of the partition entry array did not match.
+    BadEntriesChecksum,
+    /// A used partition entry ends before it begins or at the last possible
+    /// LBA.
+    BadPartitionEntry,
+}
+
+/// The MBR partition type covering a GPT disk.
+pub const PROTECTIVE_PARTITION_TYPE: u8 = 0xEE;
+/// EBD0A0A2-B9E5-4433-87C0-68B6B72699C7 in on-disk byte order.
+pub const BASIC_DATA_GUID: [u8; 16] = [
+    0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44,
+    0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7,
+];
+
+const SIGNATURE: [u8; 8] = *b"EFI PART";
+const HEADER_LBA: u64 = 1;
+const HEADER_CRC32_OFFSET: usize = 16;
+/// The partition entry array is read whole: larger arrays are rejected
+/// rather than trusted to the header checksum.
+const MAX_ENTRIES_SIZE: u64 = 1 << 20;
+
+/// The GUID partition table (GPT).
+#[derive(Debug)]
+pub struct GuidPartitionTable {
+    pub header: GptHeader,
+    /// The used entries of the partition entry array, in order.
+    pub entries: Vec<GptPartitionEntry>,
+}
+
+impl GuidPartitionTable {
+    /// Reads and returns the GUID partition table (GPT) from `device`. If the
+    /// primary header at LBA 1 or its partition entry array is damaged, the
+    /// backup header in the last sector of the protective partition is used.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoProtectiveMbr` if the MBR has no partition of type `0xEE`.
+    /// Returns `BadSignature`, `BadHeaderChecksum`, `BadEntriesChecksum` or
+    /// `BadPartitionEntry` if the primary GPT is invalid in that way and no
+    /// valid backup exists.
+    /// Returns `Mbr(err)` or `Io(err)` if reading the device failed.
+    pub fn from<T: BlockDevice>(mut device: T) -> Result<GuidPartitionTable, Error> {
+        let mbr = MasterBootRecord::from(&mut device).map_err(Error::Mbr)?;
+        let protective = match mbr.iter().find(|entry| entry.partition_type == PROTECTIVE_PARTITION_TYPE) {
+            Some(entry) => entry,
+            None => return Err(Error::NoProtectiveMbr),
+        };
+        let backup_lba = (protective.relative_sector as u64 + protective.total_sectors_in_partition as u64)
+            .saturating_sub(1);
+
+        match Self::read_at(&mut device, HEADER_LBA) {
+            Err(Error::Io(e)) => Err(Error::Io(e)),
+            Err(e) => Self::read_at(&mut device, backup_lba).map_err(|_| e),
+            gpt => gpt,
+        }
+    }
+
+    /// Reads the GPT header at `lba` and the partition entry array it
+    /// describes, verifying both checksums.
+    fn read_at<T: BlockDevice>(device: &mut T, lba: u64) -> Result<GuidPartitionTable, Error> {
+        let sector_size = device.sector_size() as usize;
+        let mut buf = vec![0u8; sector_size];
+        device.read_sector(lba, &mut buf).map_err(Error::Io)?;
+        let header = unsafe { ptr::read_unaligned(buf.as_ptr() as *const GptHeader) };
+
+        let header_size = header.header_size as usize;
+        let entry_size = header.entry_size as usize;
+        if header.signature != SIGNATURE
+            || header_size < mem::size_of::<GptHeader>()
+            || header_size > sector_size
+            || entry_size < mem::size_of::<GptPartitionEntry>()
+            || entry_size % mem::size_of::<GptPartitionEntry>() != 0
+            || header.entries_num as u64 * entry_size as u64 > MAX_ENTRIES_SIZE
+        {
+            return Err(Error::BadSignature);
+        }
+        buf[HEADER_CRC32_OFFSET..HEADER_CRC32_OFFSET + 4].copy_from_slice(&[0; 4]);
+        if crc32(&buf[..header_size]) != header.header_crc32 {
+            return Err(Error::BadHeaderChecksum);
+        }
+
+        let entries_size = header.entries_num as usize * entry_size;
+        let mut entries_buf = vec![0u8; (entries_size + sector_size - 1) / sector_size * sector_size];
+        for (i, sector_buf) in entries_buf.chunks_mut(sector_size).enumerate() {
+            device.read_sector(header.entries_lba + i as u64, sector_buf).map_err(Error::Io)?;
+        }
+        if crc32(&entries_buf[..entries_size]) != header.entries_crc32 {
+            return Err(Error::BadEntriesChecksum);
+        }
+        let entries = entries_buf[..entries_size]
+            .chunks(entry_size)
+            .map(|bytes| unsafe { ptr::read_unaligned(bytes.as_ptr() as *const GptPartitionEntry) })
+            .filter(|entry| !entry.is_unused())
+            .collect::<Vec<GptPartitionEntry>>();
+        if entries.iter().any(|entry| entry.num_sectors().is_none()) {
+            return Err(Error::BadPartitionEntry);
+        }
+        Ok(GuidPartitionTable { header, entries })
+    }
+}
@@ -10,7 +10,9 @@ extern crate alloc;
 #[cfg(not(target_endian = "little"))]
 compile_error!("only little endian platforms supported");
 
+pub mod gpt;
 mod mbr;
+mod partition;
 #[cfg(test)]
 mod tests;
 pub mod util;
@@ -19,3 +21,4 @@ pub mod traits;
 pub mod vfat;
 
 pub use crate::mbr::*;
+pub use crate::partition::*;
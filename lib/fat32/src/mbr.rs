@@ -54,6 +54,29 @@ impl fmt::Debug for PartitionEntry {
 
 const_assert_size!(PartitionEntry, 16);
 
+impl PartitionEntry {
+    /// Returns a non-bootable entry for a partition of type `partition_type`
+    /// covering `total_sectors` sectors from sector `relative_sector`. The
+    /// CHS addresses are set to the values used by LBA-only partitions.
+    pub fn new(partition_type: u8, relative_sector: u32, total_sectors: u32) -> PartitionEntry {
+        let lba_only = CHS {
+            starting_head: 0xFE,
+            starting_sector: 0xFF,
+            starting_cylinder: 0xFF,
+        };
+        PartitionEntry {
+            boot_indicator: 0,
+            chs: lba_only,
+            partition_type,
+            ending_head: lba_only.starting_head,
+            ending_sector: lba_only.starting_sector,
+            ending_cylinder: lba_only.starting_cylinder,
+            relative_sector,
+            total_sectors_in_partition: total_sectors,
+        }
+    }
+}
+
 /// The master boot record (MBR).
 #[repr(C, packed)]
 pub struct MasterBootRecord {
@@ -121,6 +144,19 @@ impl MasterBootRecord {
         Ok(mbr)
     }
 
+    /// Returns an MBR whose partition table holds only `partition`.
+    pub fn new(partition: PartitionEntry) -> MasterBootRecord {
+        let mut mbr: MasterBootRecord = unsafe { mem::zeroed() };
+        mbr.partition_table[0] = partition;
+        mbr.magic = MAGIC;
+        mbr
+    }
+
+    /// Returns the on-disk representation of the MBR.
+    pub fn to_bytes(&self) -> [u8; 512] {
+        unsafe { mem::transmute_copy(self) }
+    }
+
     /// Return a iterator of partition entry
     pub fn iter(&self) -> Iter<'_, PartitionEntry>{
         self.partition_table.iter()
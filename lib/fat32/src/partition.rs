@@ -0,0 +1,59 @@
+use alloc::vec::Vec;
+
+use crate::gpt::{self, GuidPartitionTable};
+use crate::mbr::MasterBootRecord;
+use crate::traits::BlockDevice;
+use crate::vfat::Error;
+
+// FAT12, FAT16 (< 32MB, >= 32MB, LBA), FAT32 (CHS, LBA)
+const FAT_PARTITION_TYPES: [u8; 6] = [0x1, 0x4, 0x6, 0xE, 0xB, 0xC];
+
+/// The type of a partition as recorded in its partition table.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum PartitionKind {
+    /// An MBR partition of the given partition type.
+    Mbr(u8),
+    /// A GPT partition of the given type GUID, in on-disk byte order.
+    Gpt([u8; 16]),
+}
+
+/// A partition of a block device.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct PartitionInfo {
+    pub kind: PartitionKind,
+    pub start: u64, // first sector of the partition
+    pub num_sectors: u64,
+}
+
+impl PartitionInfo {
+    /// Returns `true` if the partition type is one used for FAT volumes.
+    pub fn is_fat(&self) -> bool {
+        match self.kind {
+            PartitionKind::Mbr(partition_type) => FAT_PARTITION_TYPES.contains(&partition_type),
+            PartitionKind::Gpt(type_guid) => type_guid == gpt::BASIC_DATA_GUID,
+        }
+    }
+}
+
+/// Returns the partitions of `device` in partition table order. The GPT is
+/// read if the MBR is a protective MBR; otherwise the four primary MBR
+/// entries are used. Empty entries are skipped.
+pub fn partitions<T: BlockDevice>(mut device: T) -> Result<Vec<PartitionInfo>, Error> {
+    let mbr = MasterBootRecord::from(&mut device)?;
+    if mbr.iter().any(|entry| entry.partition_type == gpt::PROTECTIVE_PARTITION_TYPE) {
+        let gpt = GuidPartitionTable::from(&mut device)?;
+        return gpt.entries.iter().map(|entry| Ok(PartitionInfo {
+            kind: PartitionKind::Gpt(entry.type_guid),
+            start: entry.first_lba,
+            num_sectors: entry.num_sectors().ok_or(Error::Gpt(gpt::Error::BadPartitionEntry))?,
+        })).collect();
+    }
+    Ok(mbr.iter()
+        .filter(|entry| entry.partition_type != 0)
+        .map(|entry| PartitionInfo {
+            kind: PartitionKind::Mbr(entry.partition_type),
+            start: entry.relative_sector as u64,
+            num_sectors: entry.total_sectors_in_partition as u64,
+        })
+        .collect())
+}
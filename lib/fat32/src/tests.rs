@@ -11,8 +11,13 @@ use crate::mbr;
 use crate::traits::*;
 use crate::vfat;
 
+mod devices;
+use self::devices::{Access, FaultyDevice, PowerLossDevice, RecordingDevice};
+
 use mbr::{MasterBootRecord, PartitionEntry, CHS};
-use vfat::{BiosParameterBlock, VFat, VFatHandle};
+use vfat::{BiosParameterBlock, CachedPartition, Partition, Problem, VFat, VFatHandle};
+use vfat::{Cluster, Status};
+use vfat::{format, FormatOptions};
 
 #[derive(Clone)]
 struct StdVFatHandle(Arc<Mutex<VFat<Self>>>);
@@ -91,6 +96,33 @@ macro vfat_from_resource($name:expr) {
     VFat::<StdVFatHandle>::from(resource!($name)).expect("failed to initialize VFAT from image")
 }
 
+/// An in-memory copy of a disk image that can be mounted several times, so
+/// that modifications can be checked after a remount.
+#[derive(Clone)]
+struct SharedImage(Arc<Mutex<Cursor<Vec<u8>>>>);
+
+impl BlockDevice for SharedImage {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.lock().expect("all okay").read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().expect("all okay").write_sector(n, buf)
+    }
+}
+
+macro image_from_resource($name:expr) {{
+    let mut data = Vec::new();
+    resource!($name)
+        .read_to_end(&mut data)
+        .expect("read resource data");
+    SharedImage(Arc::new(Mutex::new(Cursor::new(data))))
+}}
+
+macro vfat_from_image($image:expr) {
+    VFat::<StdVFatHandle>::from($image.clone()).expect("failed to initialize VFAT from image")
+}
+
 #[test]
 fn check_mbr_size() {
     check_size!(MasterBootRecord, 512);
@@ -559,6 +591,480 @@ fn cmp_files_recursive<P: AsRef<Path>>(
     Ok(())
 }
 
+#[test]
+fn test_write_overwrite() {
+    let image = image_from_resource!("mock1.fat32.img");
+
+    let mut file = vfat_from_image!(image).open_file("/CS140E").expect("file exists");
+    let size = file.size();
+    file.write_all(b"CS3210").expect("write succeeds");
+    assert_eq!(file.size(), size);
+    file.sync().expect("sync succeeds");
+
+    let mut file = vfat_from_image!(image).open_file("/CS140E").expect("file exists");
+    let mut buf = [0u8; 6];
+    file.read_exact(&mut buf).expect("read succeeds");
+    assert_eq!(&buf, b"CS3210");
+    assert_eq!(file.size(), size);
+}
+
+#[test]
+fn test_write_extend() {
+    let image = image_from_resource!("mock1.fat32.img");
+    let data: Vec<u8> = (0..10000u32).map(|i| (i * 7 + 3) as u8).collect();
+
+    let mut file = vfat_from_image!(image).open_file("/CS140E").expect("file exists");
+    let size = file.size();
+    let mut old = vec![0u8; size as usize];
+    file.read_exact(&mut old).expect("read succeeds");
+    file.write_all(&data).expect("write succeeds");
+    assert_eq!(file.size(), size + data.len() as u64);
+    file.flush().expect("flush succeeds");
+
+    let mut file = vfat_from_image!(image).open_file("/CS140E").expect("file exists");
+    assert_eq!(file.size(), size + data.len() as u64);
+    let mut buf = vec![];
+    file.read_to_end(&mut buf).expect("read succeeds");
+    assert_eq!(&buf[..size as usize], &old[..]);
+    assert_eq!(&buf[size as usize..], &data[..]);
+}
+
+#[test]
+fn test_file_cluster_cache() {
+    use shim::io::SeekFrom;
+
+    let image = image_from_resource!("mock1.fat32.img");
+    let vfat = vfat_from_image!(image);
+    let data: Vec<u8> = (0..40 * 512u32).map(|i| (i * 13 + i / 512) as u8).collect();
+
+    // interleave the writes so the chains of both files are fragmented
+    let mut a = vfat.create_file("/a").expect("create succeeds");
+    let mut b = vfat.create_file("/b").expect("create succeeds");
+    for chunk in data.chunks(512) {
+        a.write_all(chunk).expect("write succeeds");
+        b.write_all(&[0xBB; 512]).expect("write succeeds");
+    }
+    a.flush().expect("flush succeeds");
+
+    let mut file = vfat.open_file("/a").expect("file exists");
+    let mut buf = [0u8; 700];
+    file.seek(SeekFrom::Start(5000)).unwrap();
+    file.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf[..], &data[5000..5700]);
+    // only the clusters up to the last byte read are cached
+    assert_eq!(file.clusters.len(), 5700 / 512 + 1);
+    for &pos in [19000u64, 3, 511, 512, 10240, 0, 20000].iter() {
+        file.seek(SeekFrom::Start(pos)).unwrap();
+        let size = file.read(&mut buf).unwrap();
+        assert_eq!(&buf[..size], &data[pos as usize..(pos as usize + 700).min(data.len())]);
+    }
+    assert_eq!(file.clusters.len(), 40);
+
+    // overwrite across clusters and grow the file through the cache
+    file.seek(SeekFrom::Start(1000)).unwrap();
+    file.write_all(&[0xAA; 600]).unwrap();
+    file.seek(SeekFrom::End(0)).unwrap();
+    file.write_all(&[0xCC; 1000]).unwrap();
+    file.flush().unwrap();
+    let mut buf = vec![];
+    vfat_from_image!(image).open_file("/a").unwrap().read_to_end(&mut buf).unwrap();
+    assert_eq!(&buf[..1000], &data[..1000]);
+    assert_eq!(&buf[1000..1600], &[0xAA; 600][..]);
+    assert_eq!(&buf[1600..data.len()], &data[1600..]);
+    assert_eq!(&buf[data.len()..], &[0xCC; 1000][..]);
+}
+
+/// Runs a sequence of seeks, writes, reads and resizes on `file` and returns
+/// everything observed along the way.
+fn exercise_file<F, L>(file: &mut F, set_len: L) -> Vec<String>
+where
+    F: Read + Write + Seek,
+    L: Fn(&mut F, u64) -> io::Result<()>,
+{
+    use shim::io::SeekFrom;
+
+    let mut log = vec![];
+    let read_all = |file: &mut F, log: &mut Vec<String>| {
+        let mut buf = vec![];
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut buf).unwrap();
+        log.push(format!("{} {:?}", buf.len(), buf.iter().filter(|&&b| b != 0).collect::<Vec<_>>()));
+    };
+
+    file.write_all(&[0xFF; 5000]).unwrap();
+    log.push(format!("{:?}", file.seek(SeekFrom::End(-3))));
+    log.push(format!("{:?}", file.seek(SeekFrom::Current(-5000)).map_err(|e| e.kind())));
+    log.push(format!("{:?}", file.seek(SeekFrom::Current(-4997))));
+    // seeking past the end reads nothing, writing there fills the gap
+    log.push(format!("{:?}", file.seek(SeekFrom::End(2000))));
+    log.push(format!("{:?}", file.read(&mut [0; 10]).unwrap()));
+    file.write_all(b"tail").unwrap();
+    read_all(file, &mut log);
+    set_len(file, 10).unwrap();
+    log.push(format!("{:?}", file.seek(SeekFrom::Current(0))));
+    read_all(file, &mut log);
+    set_len(file, 0).unwrap();
+    set_len(file, 3000).unwrap();
+    read_all(file, &mut log);
+    file.seek(SeekFrom::Start(1000)).unwrap();
+    file.write_all(b"mid").unwrap();
+    set_len(file, 1002).unwrap();
+    read_all(file, &mut log);
+    log
+}
+
+#[test]
+fn test_set_len_and_seek() {
+    let path = std::env::temp_dir().join(format!("fat32-set-len-{}", std::process::id()));
+    let mut host_file = std::fs::OpenOptions::new()
+        .read(true).write(true).create(true).truncate(true)
+        .open(&path).expect("create host file");
+    let expected = exercise_file(&mut host_file, |file, size| file.set_len(size));
+    std::fs::remove_file(&path).unwrap();
+
+    let image = image_from_resource!("mock1.fat32.img");
+    let vfat = vfat_from_image!(image);
+    let free = vfat.lock(|vfat| vfat.free_space().unwrap());
+    let mut file = vfat.create_file("/resized").expect("create succeeds");
+    assert_eq!(exercise_file(&mut file, |file, size| file.set_len(size)), expected);
+    assert_eq!(file.size(), 1002);
+    let e = file.set_len(1 << 32).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    file.sync().unwrap();
+
+    let vfat = vfat_from_image!(image);
+    let mut buf = vec![];
+    vfat.open_file("/resized").unwrap().read_to_end(&mut buf).unwrap();
+    assert_eq!(buf.len(), 1002);
+    assert_eq!(&buf[1000..], b"mi");
+    assert_eq!(vfat.lock(|vfat| vfat.free_space().unwrap()), free - 2 * 512);
+    assert_eq!(vfat.lock(|vfat| vfat.check(false)).expect("check succeeds"), vec![]);
+
+    let mut file = vfat.open_file("/resized").unwrap();
+    file.set_len(0).unwrap();
+    assert_eq!(file.start_cluster.cluster_id(), 0);
+    file.sync().unwrap();
+    assert_eq!(vfat.lock(|vfat| vfat.free_space().unwrap()), free);
+    assert_eq!(vfat.lock(|vfat| vfat.check(false)).expect("check succeeds"), vec![]);
+}
+
+#[test]
+fn test_create_file() {
+    let image = image_from_resource!("mock1.fat32.img");
+
+    let vfat = vfat_from_image!(image);
+    let mut file = vfat.create_file("/NOTES/a rather long file name.txt").expect("create succeeds");
+    file.write_all(b"created by test_create_file").expect("write succeeds");
+    vfat.create_file("/NOTES/SHORT.TXT").expect("create succeeds");
+    file.sync().expect("sync succeeds");
+
+    let vfat = vfat_from_image!(image);
+    let mut file = vfat.open_file("/NOTES/A Rather Long File Name.txt").expect("file exists");
+    let mut buf = String::new();
+    file.read_to_string(&mut buf).expect("read succeeds");
+    assert_eq!(buf, "created by test_create_file");
+
+    let names: Vec<String> = vfat.open_dir("/NOTES").expect("directory exists")
+        .entries().expect("entries interator")
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert!(names.iter().any(|name| name == "a rather long file name.txt"));
+    assert!(names.iter().any(|name| name == "SHORT.TXT"));
+
+    let e = vfat.create_file("/NOTES/short.txt").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+    let e = vfat.create_file("/NOTES/a:b").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_create_dir() {
+    let image = image_from_resource!("mock1.fat32.img");
+
+    let vfat = vfat_from_image!(image);
+    vfat.create_dir("/new directory").expect("create succeeds");
+    let dir = vfat.create_dir("/new directory/nested").expect("create succeeds");
+    // enough entries to span several clusters of the new directory
+    for i in 0..64 {
+        dir.create_file(&format!("file number {}", i)).expect("create succeeds");
+    }
+    let mut file = dir.create_file("last").expect("create succeeds").into_file().unwrap();
+    file.write_all(b"last file").expect("write succeeds");
+    file.sync().expect("sync succeeds");
+
+    let vfat = vfat_from_image!(image);
+    let mut names: Vec<String> = vfat.open_dir("/new directory/nested").expect("directory exists")
+        .entries().expect("entries interator")
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names.len(), 64 + 3);
+    names.sort();
+    assert_eq!(&names[..2], &[".", ".."]);
+
+    let mut buf = String::new();
+    vfat.open_file("/new directory/nested/../nested/last").expect("file exists")
+        .read_to_string(&mut buf).expect("read succeeds");
+    assert_eq!(buf, "last file");
+}
+
+/// Asserts that every copy of the FAT in the first partition of `image` is
+/// identical.
+fn assert_fat_copies_match(image: &SharedImage) {
+    let image = image.0.lock().unwrap();
+    let bytes = image.get_ref();
+    let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]) as usize;
+    let u32_at = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]) as usize;
+
+    let start = u32_at(446 + 8) * 512;
+    let bytes_per_sector = u16_at(start + 11);
+    let fat_start = start + u16_at(start + 14) * bytes_per_sector;
+    let fat_num = bytes[start + 16] as usize;
+    let fat_size = match u16_at(start + 22) {
+        0 => u32_at(start + 36),
+        sectors_per_fat => sectors_per_fat,
+    } * bytes_per_sector;
+    let first = &bytes[fat_start..fat_start + fat_size];
+    for i in 1..fat_num {
+        let copy = &bytes[fat_start + i * fat_size..fat_start + (i + 1) * fat_size];
+        assert!(first == copy, "FAT copy {} differs from the first FAT", i);
+    }
+}
+
+#[test]
+fn test_remove_file() {
+    let image = image_from_resource!("mock1.fat32.img");
+
+    let vfat = vfat_from_image!(image);
+    let file = vfat.open_file("/rpi3-docs/RPi3-Schematics.pdf").expect("file exists");
+    let start_cluster = file.start_cluster;
+    vfat.remove_file("/rpi3-docs/RPi3-Schematics.pdf").expect("remove succeeds");
+    let e = vfat.remove_file("/rpi3-docs/RPi3-Schematics.pdf").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    let e = vfat.remove_file("/NOTES").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::Other);
+
+    // the freed clusters are handed out again
+    let mut file = vfat.create_file("/new file").expect("create succeeds");
+    file.write_all(b"reuses freed clusters").expect("write succeeds");
+    assert_eq!(file.start_cluster, start_cluster);
+    file.sync().expect("sync succeeds");
+    assert_fat_copies_match(&image);
+
+    // neither the regular entry nor its LFN entries are left behind
+    let vfat = vfat_from_image!(image);
+    let names: Vec<String> = vfat.open_dir("/rpi3-docs").expect("directory exists")
+        .entries().expect("entries interator")
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, vec![".", ".."]);
+}
+
+#[test]
+fn test_remove_dir() {
+    let image = image_from_resource!("mock1.fat32.img");
+
+    let vfat = vfat_from_image!(image);
+    let e = vfat.remove_dir("/NOTES").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::Other);
+    let e = vfat.remove_dir("/CS140E").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::Other);
+    let e = vfat.remove_dir("/NOTES/..").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+
+    vfat.remove_file("/NOTES/LEC1/SLIDES.PDF").expect("remove succeeds");
+    vfat.remove_dir("/NOTES/LEC1").expect("remove succeeds");
+    vfat.remove_dir("/solutions").expect("remove succeeds");
+    vfat.lock(|vfat| vfat.flush()).expect("flush succeeds");
+    assert_fat_copies_match(&image);
+
+    let vfat = vfat_from_image!(image);
+    assert!(vfat.open("/solutions").is_err());
+    assert!(vfat.open("/NOTES/LEC1").is_err());
+    vfat.open_dir("/NOTES/LEC2").expect("directory exists");
+}
+
+#[test]
+fn test_rename() {
+    let image = image_from_resource!("mock1.fat32.img");
+
+    let vfat = vfat_from_image!(image);
+    let mut old = vec![];
+    vfat.open_file("/CS140E").expect("file exists")
+        .read_to_end(&mut old).expect("read succeeds");
+    // LFN to short name and back, within a directory and across directories
+    vfat.rename("/rpi3-docs/RPi3-Schematics.pdf", "/rpi3-docs/SCHEMA.PDF").expect("rename succeeds");
+    vfat.rename("/CS140E", "/NOTES/course description").expect("rename succeeds");
+    vfat.rename("/solutions", "/Solutions").expect("rename succeeds");
+    vfat.rename("/NOTES/LEC1", "/Solutions/lecture one").expect("rename succeeds");
+
+    // an existing file is replaced
+    let mut file = vfat.create_file("/paper.tmp").expect("create succeeds");
+    file.write_all(b"new paper").expect("write succeeds");
+    vfat.rename("/paper.tmp", "/NOTES/LEC2/PAPER.PDF").expect("rename succeeds");
+
+    let e = vfat.rename("/NOTES", "/NOTES/LEC2/NOTES").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    let e = vfat.rename("/CS140E", "/CS3210").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    let e = vfat.rename("/NOTES/LEC2", "/NOTES/course description").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::Other);
+    vfat.lock(|vfat| vfat.flush()).expect("flush succeeds");
+    assert_fat_copies_match(&image);
+
+    let vfat = vfat_from_image!(image);
+    let names = |path: &str| -> Vec<String> {
+        let mut names: Vec<String> = vfat.open_dir(path).expect("directory exists")
+            .entries().expect("entries interator")
+            .map(|entry| entry.name().to_string())
+            .collect();
+        names.sort();
+        names
+    };
+    assert_eq!(names("/"), vec!["NOTES", "Solutions", "rpi3-docs"]);
+    assert_eq!(names("/NOTES"), vec![".", "..", "LEC2", "course description"]);
+    assert_eq!(names("/rpi3-docs"), vec![".", "..", "SCHEMA.PDF"]);
+    assert_eq!(names("/Solutions/lecture one/.."), vec![".", "..", "lecture one"]);
+
+    let mut buf = vec![];
+    vfat.open_file("/NOTES/course description").expect("file exists")
+        .read_to_end(&mut buf).expect("read succeeds");
+    assert_eq!(buf, old);
+    let file = vfat.open_file("/Solutions/lecture one/../lecture one/SLIDES.PDF").expect("file exists");
+    assert_eq!(file.size(), 20000);
+    let mut buf = String::new();
+    vfat.open_file("/NOTES/LEC2/PAPER.PDF").expect("file exists")
+        .read_to_string(&mut buf).expect("read succeeds");
+    assert_eq!(buf, "new paper");
+}
+
+#[test]
+fn test_rename_without_room() {
+    // the root directory of this volume holds 16 entries and cannot grow
+    let image = fat16_image(0x01, 4096, 3, 16);
+    let vfat = vfat_from_image!(image);
+    for i in 0..16 {
+        let mut file = vfat.create_file(format!("/FILE{}", i)).expect("create succeeds");
+        file.write_all(format!("file {}", i).as_bytes()).expect("write succeeds");
+    }
+    let read = |path: &str| {
+        let mut buf = String::new();
+        vfat.open_file(path).expect("file exists").read_to_string(&mut buf).expect("read succeeds");
+        buf
+    };
+
+    // a replaced entry is reused, a failed move loses nothing
+    let free = vfat.lock(|vfat| vfat.free_space().unwrap());
+    vfat.rename("/FILE0", "/FILE1").expect("rename succeeds");
+    assert_eq!(read("/FILE1"), "file 0");
+    assert!(vfat.open_file("/FILE0").is_err());
+    assert_eq!(vfat.lock(|vfat| vfat.free_space().unwrap()), free + 2048);
+    vfat.rename("/FILE2", "/a name needing long entries").unwrap_err();
+    assert_eq!(read("/FILE2"), "file 2");
+    vfat.rename("/FILE3", "/file3").unwrap_err();
+    assert_eq!(read("/FILE3"), "file 3");
+
+    // a replaced name that differs in case takes the new case
+    vfat.remove_file("/FILE6").expect("remove succeeds");
+    vfat.remove_file("/FILE7").expect("remove succeeds");
+    vfat.rename("/FILE3", "/file1").expect("rename succeeds");
+    vfat.rename("/file1", "/File1").expect("rename succeeds");
+    assert_eq!(vfat.open_file("/file1").unwrap().name, "File1");
+    vfat.rename("/File1", "/FILE1").expect("rename succeeds");
+    assert_eq!(vfat.open_file("/file1").unwrap().name, "FILE1");
+    assert_eq!(read("/FILE1"), "file 3");
+    assert_eq!(vfat.open_dir("/").unwrap().entries().unwrap().count(), 12);
+    assert_eq!(vfat.lock(|vfat| vfat.check(false)).expect("check succeeds"), vec![]);
+}
+
+#[test]
+fn test_free_space() {
+    let image = image_from_resource!("mock1.fat32.img");
+
+    let vfat = vfat_from_image!(image);
+    let (free, total, cluster_size) = vfat.lock(|vfat| {
+        (vfat.free_space().unwrap(), vfat.total_space(), vfat.bytes_per_cluster())
+    });
+    assert!(free < total);
+    assert_eq!(free % cluster_size, 0);
+
+    let mut file = vfat.create_file("/big").expect("create succeeds");
+    file.write_all(&vec![0xAB; 10000]).expect("write succeeds");
+    file.sync().expect("sync succeeds");
+    let used = (10000 + cluster_size - 1) / cluster_size * cluster_size;
+    assert_eq!(vfat.lock(|vfat| vfat.free_space().unwrap()), free - used);
+
+    // the free cluster count is kept in the FSInfo sector
+    let vfat = vfat_from_image!(image);
+    assert_eq!(vfat.lock(|vfat| vfat.free_space().unwrap()), free - used);
+    vfat.remove_file("/big").expect("remove succeeds");
+    assert_eq!(vfat.lock(|vfat| vfat.free_space().unwrap()), free);
+
+    // without a valid free cluster count the FAT is scanned
+    {
+        let mut image = image.0.lock().unwrap();
+        let bytes = image.get_mut();
+        let start = u32::from_le_bytes([bytes[454], bytes[455], bytes[456], bytes[457]]) as usize * 512;
+        let fsinfo = start + u16::from_le_bytes([bytes[start + 48], bytes[start + 49]]) as usize * 512;
+        bytes[fsinfo + 488..fsinfo + 492].copy_from_slice(&[0xFF; 4]);
+    }
+    let vfat = vfat_from_image!(image);
+    assert_eq!(vfat.lock(|vfat| vfat.free_space().unwrap()), free - used);
+}
+
+#[test]
+fn test_check() {
+    let image = image_from_resource!("mock1.fat32.img");
+
+    let vfat = vfat_from_image!(image);
+    assert_eq!(vfat.lock(|vfat| vfat.check(false)).expect("check succeeds"), vec![]);
+
+    let cs140e = vfat.open_file("/CS140E").expect("file exists");
+    let paper = vfat.open_file("/NOTES/LEC2/PAPER.PDF").expect("file exists");
+    let slides = vfat.open_file("/NOTES/LEC1/SLIDES.PDF").expect("file exists");
+    let schematics = vfat.open_file("/rpi3-docs/RPi3-Schematics.pdf").expect("file exists");
+    let (bad_cluster, last_cluster) = vfat.lock(|vfat| {
+        use crate::vfat::dir::update_regular_entry;
+        update_regular_entry(vfat, &cs140e.location, cs140e.start_cluster, 5000).unwrap();
+        update_regular_entry(vfat, &paper.location, cs140e.start_cluster, 6).unwrap();
+        let bad_cluster = vfat.next_cluster(slides.start_cluster).unwrap().unwrap();
+        vfat.set_fat_status(bad_cluster, Status::Bad).unwrap();
+        let last_cluster = Cluster::from(vfat.clusters_num() + 1);
+        vfat.set_fat_copy_entry(1, last_cluster, 0x0FFFFFFF).unwrap();
+        // corrupt the checksum of the first LFN entry
+        let location = schematics.location;
+        vfat.write_cluster(location.dir_cluster, location.start_offset as usize + 13, &[0]).unwrap();
+        (bad_cluster.cluster_id() as u32, last_cluster.cluster_id() as u32)
+    });
+
+    let problems = vfat.lock(|vfat| vfat.check(false)).expect("check succeeds");
+    let expected = vec![
+        Problem::FatMismatch { copy: 1, cluster: last_cluster },
+        Problem::SizeMismatch { path: "/CS140E".into(), size: 5000, clusters: 1 },
+        Problem::OrphanLfn { path: "/rpi3-docs".into(), offset: schematics.location.start_offset },
+        Problem::CrossLinked { path: "/NOTES/LEC2/PAPER.PDF".into(), cluster: cs140e.start_cluster.cluster_id() as u32 },
+        Problem::BadChain { path: "/NOTES/LEC1/SLIDES.PDF".into(), cluster: bad_cluster },
+        Problem::LostChain { start: paper.start_cluster.cluster_id() as u32, clusters: 1 },
+        Problem::LostChain { start: bad_cluster + 1, clusters: 20000 / 512 - 1 },
+    ];
+    assert_eq!(problems.len(), expected.len());
+    for problem in expected.iter() {
+        assert!(problems.contains(problem), "missing problem: {}", problem);
+    }
+
+    // repairs fix every problem and are written to the disk
+    let free = vfat.lock(|vfat| vfat.free_space().unwrap());
+    assert_eq!(vfat.lock(|vfat| vfat.check(true)).expect("repair succeeds"), problems);
+    let vfat = vfat_from_image!(image);
+    assert_eq!(vfat.lock(|vfat| vfat.check(false)).expect("check succeeds"), vec![]);
+    // the lost chains of PAPER.PDF and SLIDES.PDF are freed
+    assert_eq!(vfat.lock(|vfat| vfat.free_space().unwrap()), free + (1 + 20000 / 512 - 1) * 512);
+    assert_fat_copies_match(&image);
+
+    assert_eq!(vfat.open_file("/CS140E").unwrap().size(), 512);
+    assert_eq!(vfat.open_file("/NOTES/LEC2/PAPER.PDF").unwrap().size(), 0);
+    assert_eq!(vfat.open_file("/NOTES/LEC1/SLIDES.PDF").unwrap().size(), 512);
+    vfat.open_file("/rpi3-docs/RPI3SC~2.PDF").expect("file exists");
+}
 
 // #[test]
 // fn dir_test() {
@@ -580,3 +1086,825 @@ fn cmp_files_recursive<P: AsRef<Path>>(
 //     }
 
 // }
+
+#[test]
+fn test_cache_write_back() {
+    let image = SharedImage(Arc::new(Mutex::new(Cursor::new(vec![0u8; 512 * 512]))));
+    let partition = Partition { start: 64, num_sectors: 128, sector_size: 1024 };
+    let mut cache = CachedPartition::new(image.clone(), partition);
+    // without readahead every miss caches exactly one sector
+    cache.set_readahead(0);
+    let sector_data = |image: &SharedImage, sector: usize| -> Vec<u8> {
+        let offset = (64 + sector * 2) * 512;
+        image.0.lock().unwrap().get_ref()[offset..offset + 1024].to_vec()
+    };
+
+    for sector in 0..100 {
+        cache.get_mut(sector).expect("read succeeds").copy_from_slice(&[sector as u8 + 1; 1024]);
+    }
+    // the sectors evicted so far have been written back, the rest is cached
+    for sector in 0..36 {
+        assert_eq!(sector_data(&image, sector), vec![sector as u8 + 1; 1024]);
+    }
+    for sector in 36..100 {
+        assert_eq!(sector_data(&image, sector), vec![0; 1024]);
+    }
+
+    cache.sync().expect("sync succeeds");
+    for sector in 0..100 {
+        assert_eq!(sector_data(&image, sector), vec![sector as u8 + 1; 1024]);
+    }
+    assert_eq!(&image.0.lock().unwrap().get_ref()[..64 * 512], &[0; 64 * 512][..]);
+}
+
+#[test]
+fn test_cache_readahead() {
+    let data: Vec<u8> = (0..128 * 512u32).map(|i| (i / 512) as u8).collect();
+    let image = SharedImage(Arc::new(Mutex::new(Cursor::new(data))));
+    let (device, trace) = RecordingDevice::new(image);
+    let partition = Partition { start: 0, num_sectors: 100, sector_size: 512 };
+    let mut cache = CachedPartition::new(device, partition);
+    cache.set_readahead(4);
+    let take_reads = || -> Vec<(u64, usize)> {
+        trace.take().into_iter().filter_map(|access| match access {
+            Access::Read { sector, len } => Some((sector, len)),
+            _ => None,
+        }).collect()
+    };
+
+    // a random miss reads one sector, a sequential one reads ahead
+    assert_eq!(cache.get(10).unwrap()[0], 10);
+    assert_eq!(take_reads(), vec![(10, 512)]);
+    assert_eq!(cache.get(11).unwrap()[0], 11);
+    assert_eq!(take_reads(), vec![(11, 5 * 512)]);
+    for sector in 12..16 {
+        assert_eq!(cache.get(sector).unwrap()[0], sector as u8);
+    }
+    assert_eq!(take_reads(), vec![]);
+    assert_eq!(cache.get(16).unwrap()[0], 16);
+    assert_eq!(take_reads(), vec![(16, 5 * 512)]);
+    // readahead stops at cached sectors and at the end of the partition
+    cache.get(30).unwrap();
+    cache.get(28).unwrap();
+    take_reads();
+    cache.get(29).unwrap();
+    assert_eq!(take_reads(), vec![(29, 512)]);
+    cache.get(97).unwrap();
+    cache.get(98).unwrap();
+    assert_eq!(take_reads(), vec![(97, 512), (98, 2 * 512)]);
+
+    // bulk reads bypass the cache but see its dirty sectors
+    cache.get_mut(41).unwrap().copy_from_slice(&[0xAB; 512]);
+    take_reads();
+    let mut buf = vec![0u8; 3 * 512 + 100];
+    assert_eq!(cache.read_sectors(40, &mut buf).unwrap(), buf.len());
+    assert_eq!(take_reads(), vec![(40, buf.len())]);
+    assert_eq!(&buf[..512], &[40; 512][..]);
+    assert_eq!(&buf[512..1024], &[0xAB; 512][..]);
+    assert_eq!(&buf[1024..1536], &[42; 512][..]);
+    assert_eq!(&buf[1536..], &[43; 100][..]);
+    cache.get(40).unwrap();
+    assert_eq!(take_reads(), vec![(40, 512)]);
+    assert!(cache.read_sectors(99, &mut buf).is_err());
+}
+
+#[test]
+fn test_format() {
+    for &(sectors_per_cluster, sectors_num) in [(1u8, 80 * 1024u64), (2, 140 * 1024)].iter() {
+        let image = SharedImage(Arc::new(Mutex::new(Cursor::new(vec![0u8; sectors_num as usize * 512]))));
+        let options = FormatOptions { sectors_per_cluster, ..Default::default() };
+        format(image.clone(), sectors_num, &options).expect("format succeeds");
+        assert_fat_copies_match(&image);
+
+        let vfat = vfat_from_image!(image);
+        let cluster_size = 512 * sectors_per_cluster as u64;
+        let (free, total) = vfat.lock(|vfat| {
+            assert_eq!(vfat.bytes_per_cluster(), cluster_size);
+            (vfat.free_space().unwrap(), vfat.total_space())
+        });
+        assert_eq!(free, total - cluster_size);
+        assert_eq!(vfat.open_dir("/").unwrap().entries().unwrap().count(), 0);
+        assert_eq!(vfat.lock(|vfat| vfat.check(false)).expect("check succeeds"), vec![]);
+
+        let dir = vfat.create_dir("/some directory").expect("create succeeds");
+        let mut file = dir.create_file("file").unwrap().into_file().unwrap();
+        file.write_all(&[0x5A; 3000]).expect("write succeeds");
+        file.sync().expect("sync succeeds");
+
+        let vfat = vfat_from_image!(image);
+        let mut buf = vec![];
+        vfat.open_file("/some directory/file").expect("file exists")
+            .read_to_end(&mut buf).expect("read succeeds");
+        assert_eq!(buf, vec![0x5A; 3000]);
+        assert_eq!(vfat.lock(|vfat| vfat.check(false)).expect("check succeeds"), vec![]);
+    }
+
+    // too few clusters for FAT32
+    let mut data = vec![0u8; 80 * 1024 * 512];
+    let e = format(Cursor::new(&mut data[..]), 80 * 1024, &Default::default()).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    let options = FormatOptions { sectors_per_cluster: 3, ..Default::default() };
+    let e = format(Cursor::new(&mut data[..]), 80 * 1024, &options).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+}
+
+/// Builds an empty FAT12/16 image of `sectors_num` 512-byte sectors holding a
+/// single partition of type `partition_type` at sector 64.
+fn fat16_image(
+    partition_type: u8,
+    sectors_num: u32,
+    sectors_per_fat: u16,
+    max_rootdir_entries: u16,
+) -> SharedImage {
+    let start = 64;
+    let mut data = vec![0u8; sectors_num as usize * 512];
+    let mbr = MasterBootRecord::new(PartitionEntry::new(partition_type, start, sectors_num - start));
+    data[..512].copy_from_slice(&mbr.to_bytes());
+
+    let mut ebpb = BiosParameterBlock::default();
+    ebpb.jump = [0xEB, 0x3C, 0x90];
+    ebpb.bytes_per_sector = 512;
+    ebpb.sectors_per_cluster = 4;
+    ebpb.reserved_sectors_num = 1;
+    ebpb.fat_num = 2;
+    ebpb.max_rootdir_entries = max_rootdir_entries;
+    ebpb.media_descriptor = 0xF8;
+    ebpb.sectors_per_fat_1 = sectors_per_fat;
+    ebpb.set_total_sectors(sectors_num - start);
+    let start = start as usize * 512;
+    data[start..start + 512].copy_from_slice(&ebpb.to_bytes());
+
+    // FAT[0] holds the media descriptor, FAT[1] an end of chain marker
+    let reserved: &[u8] = if partition_type == 0x01 { &[0xF8, 0xFF, 0xFF] } else { &[0xF8, 0xFF, 0xFF, 0xFF] };
+    for i in 0..2 {
+        let fat_start = start + 512 + i * sectors_per_fat as usize * 512;
+        data[fat_start..fat_start + reserved.len()].copy_from_slice(reserved);
+    }
+    SharedImage(Arc::new(Mutex::new(Cursor::new(data))))
+}
+
+#[test]
+fn test_fat12_fat16() {
+    use vfat::FatType;
+
+    for &(partition_type, sectors_num, sectors_per_fat, fat_type) in
+        [(0x01, 4096, 3, FatType::Fat12), (0x06, 32768, 32, FatType::Fat16)].iter()
+    {
+        let image = fat16_image(partition_type, sectors_num, sectors_per_fat, 512);
+        let vfat = vfat_from_image!(image);
+        let free = vfat.lock(|vfat| {
+            assert_eq!(vfat.fat_type(), fat_type);
+            vfat.free_space().unwrap()
+        });
+        assert_eq!(vfat.lock(|vfat| vfat.total_space()), free);
+
+        // files large enough to chain across FAT12 entries spanning sectors
+        let dir = vfat.create_dir("/some directory").expect("create succeeds");
+        let sub_dir = dir.create_dir("nested").unwrap().into_dir().unwrap();
+        for i in 0..200u32 {
+            let mut file = sub_dir.create_file(&format!("file {}", i)).unwrap().into_file().unwrap();
+            file.write_all(&vec![i as u8; 3000]).expect("write succeeds");
+            file.sync().expect("sync succeeds");
+        }
+        let mut file = vfat.create_file("/ROOT.TXT").unwrap();
+        file.write_all(b"in the fixed root region").unwrap();
+        file.sync().unwrap();
+        assert_fat_copies_match(&image);
+
+        let vfat = vfat_from_image!(image);
+        for i in (0..200u32).step_by(17) {
+            let mut buf = vec![];
+            vfat.open_file(format!("/some directory/nested/file {}", i)).expect("file exists")
+                .read_to_end(&mut buf).expect("read succeeds");
+            assert_eq!(buf, vec![i as u8; 3000]);
+        }
+        let mut buf = vec![];
+        vfat.open_file("/ROOT.TXT").unwrap().read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"in the fixed root region");
+        // two directory entries per file plus `.` and `..` fill 7 clusters
+        let used = 1 + 7 + 200 * 2 + 1;
+        assert_eq!(vfat.lock(|vfat| vfat.free_space().unwrap()), free - used * 2048);
+        assert_eq!(vfat.lock(|vfat| vfat.check(false)).expect("check succeeds"), vec![]);
+
+        vfat.remove_file("/some directory/nested/file 3").expect("remove succeeds");
+        assert_eq!(vfat.lock(|vfat| vfat.check(false)).expect("check succeeds"), vec![]);
+    }
+
+    // the root directory of FAT12/16 cannot grow
+    let image = fat16_image(0x01, 4096, 3, 16);
+    let vfat = vfat_from_image!(image);
+    for i in 0..16 {
+        vfat.create_file(format!("/FILE{}", i)).expect("create succeeds");
+    }
+    let e = vfat.create_file("/FILE16").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::Other);
+    assert!(vfat.open_dir("/").unwrap().entries().unwrap().count() == 16);
+}
+
+/// Writes a protective MBR and a primary and backup GPT to `data`. Each of
+/// `partitions` is given as (type GUID, first LBA, last LBA, name).
+fn write_gpt(data: &mut [u8], partitions: &[([u8; 16], u64, u64, &str)]) {
+    use crate::util::crc32;
+
+    let sectors_num = (data.len() / 512) as u64;
+    let mbr = MasterBootRecord::new(PartitionEntry::new(0xEE, 1, (sectors_num - 1) as u32));
+    data[..512].copy_from_slice(&mbr.to_bytes());
+
+    let mut entries = vec![0u8; 128 * 128];
+    for (i, &(type_guid, first_lba, last_lba, name)) in partitions.iter().enumerate() {
+        let entry = &mut entries[i * 128..(i + 1) * 128];
+        entry[..16].copy_from_slice(&type_guid);
+        entry[16] = i as u8 + 1;
+        entry[32..40].copy_from_slice(&first_lba.to_le_bytes());
+        entry[40..48].copy_from_slice(&last_lba.to_le_bytes());
+        for (j, c) in name.encode_utf16().enumerate() {
+            entry[56 + 2 * j..58 + 2 * j].copy_from_slice(&c.to_le_bytes());
+        }
+    }
+
+    let backup_lba = sectors_num - 1;
+    for &(lba, other_lba, entries_lba) in [(1, backup_lba, 2), (backup_lba, 1, backup_lba - 32)].iter() {
+        let mut header = [0u8; 92];
+        header[..8].copy_from_slice(b"EFI PART");
+        header[8..12].copy_from_slice(&0x10000u32.to_le_bytes());
+        header[12..16].copy_from_slice(&92u32.to_le_bytes());
+        header[24..32].copy_from_slice(&lba.to_le_bytes());
+        header[32..40].copy_from_slice(&other_lba.to_le_bytes());
+        header[40..48].copy_from_slice(&34u64.to_le_bytes());
+        header[48..56].copy_from_slice(&(backup_lba - 33).to_le_bytes());
+        header[56..72].copy_from_slice(&[0x42; 16]);
+        header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        header[80..84].copy_from_slice(&128u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        header[88..92].copy_from_slice(&crc32(&entries).to_le_bytes());
+        let header_crc32 = crc32(&header);
+        header[16..20].copy_from_slice(&header_crc32.to_le_bytes());
+
+        let lba = lba as usize * 512;
+        data[lba..lba + 92].copy_from_slice(&header);
+        let entries_lba = entries_lba as usize * 512;
+        data[entries_lba..entries_lba + entries.len()].copy_from_slice(&entries);
+    }
+}
+
+#[test]
+fn test_gpt() {
+    use crate::gpt::{self, GuidPartitionTable};
+    use crate::util::crc32;
+    use crate::{partitions, PartitionKind};
+
+    assert_eq!(crc32(b"123456789"), 0xCBF43926);
+
+    let sectors_num = 80 * 1024;
+    let mut data = vec![0u8; sectors_num as usize * 512];
+    let options = FormatOptions { sectors_per_cluster: 1, ..Default::default() };
+    format(Cursor::new(&mut data[..]), sectors_num - 33, &options).expect("format succeeds");
+    let linux_guid = [0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47, 0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4];
+    write_gpt(&mut data, &[
+        (linux_guid, 34, 2047, "linux"),
+        (gpt::BASIC_DATA_GUID, 2048, sectors_num - 34, "data"),
+    ]);
+
+    let gpt = GuidPartitionTable::from(Cursor::new(&mut data[..])).expect("valid GPT");
+    assert_eq!({ gpt.header.current_lba }, 1);
+    assert_eq!(gpt.entries.len(), 2);
+    assert_eq!(gpt.entries[0].name(), "linux");
+    assert!(gpt.entries[1].is_basic_data());
+    let parts = partitions(Cursor::new(&mut data[..])).expect("partitions");
+    assert_eq!(parts.len(), 2);
+    assert!(!parts[0].is_fat());
+    assert_eq!(parts[1].kind, PartitionKind::Gpt(gpt::BASIC_DATA_GUID));
+    assert_eq!((parts[1].start, parts[1].num_sectors), (2048, sectors_num - 34 - 2048 + 1));
+
+    // the first FAT partition is mounted by default
+    let image = SharedImage(Arc::new(Mutex::new(Cursor::new(data))));
+    let vfat = vfat_from_image!(image);
+    let mut file = vfat.create_file("/on gpt").expect("create succeeds");
+    file.write_all(b"hello gpt").unwrap();
+    file.sync().unwrap();
+    let mut buf = vec![];
+    let vfat = VFat::<StdVFatHandle>::from_partition(image.clone(), &parts[1]).expect("mount succeeds");
+    vfat.open_file("/on gpt").unwrap().read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, b"hello gpt");
+    let e = VFat::<StdVFatHandle>::from_partition(image.clone(), &parts[0]).unwrap_err();
+    expect_variant!(e, vfat::Error::BadSignature);
+
+    // a damaged primary GPT falls back to the backup
+    image.0.lock().unwrap().get_mut()[2 * 512 + 40] ^= 1;
+    let gpt = GuidPartitionTable::from(image.clone()).expect("backup GPT is valid");
+    assert_eq!({ gpt.header.current_lba }, sectors_num - 1);
+    assert_eq!(partitions(image.clone()).expect("partitions"), parts);
+    vfat_from_image!(image);
+
+    image.0.lock().unwrap().get_mut()[(sectors_num as usize - 1) * 512 + 16] ^= 1;
+    let e = GuidPartitionTable::from(image.clone()).unwrap_err();
+    expect_variant!(e, gpt::Error::BadEntriesChecksum);
+    let e = VFat::<StdVFatHandle>::from(image.clone()).unwrap_err();
+    expect_variant!(e, vfat::Error::Gpt(gpt::Error::BadEntriesChecksum));
+
+    // entry sizes and counts outside of the UEFI rules are rejected before
+    // the entries are read, even with a matching header checksum
+    for &(entries_num, entry_size) in [(0xFFFF_FFFFu32, 128u32), (8193, 128), (128, 130)].iter() {
+        let mut header = [0u8; 92];
+        header.copy_from_slice(&image.0.lock().unwrap().get_ref()[512..512 + 92]);
+        header[80..84].copy_from_slice(&entries_num.to_le_bytes());
+        header[84..88].copy_from_slice(&entry_size.to_le_bytes());
+        header[16..20].copy_from_slice(&[0; 4]);
+        let header_crc32 = crc32(&header);
+        header[16..20].copy_from_slice(&header_crc32.to_le_bytes());
+        image.0.lock().unwrap().get_mut()[512..512 + 92].copy_from_slice(&header);
+        let e = GuidPartitionTable::from(image.clone()).unwrap_err();
+        expect_variant!(e, gpt::Error::BadSignature);
+    }
+
+    // entries with a matching checksum but no valid range of sectors are
+    // rejected rather than read as empty or overflowing partitions
+    for &(first_lba, last_lba) in [(2048, 2047), (2048, core::u64::MAX)].iter() {
+        let mut data = vec![0u8; 128 * 512];
+        write_gpt(&mut data, &[(gpt::BASIC_DATA_GUID, first_lba, last_lba, "data")]);
+        let e = GuidPartitionTable::from(Cursor::new(&mut data[..])).unwrap_err();
+        expect_variant!(e, gpt::Error::BadPartitionEntry);
+        let e = partitions(Cursor::new(&mut data[..])).unwrap_err();
+        expect_variant!(e, vfat::Error::Gpt(gpt::Error::BadPartitionEntry));
+    }
+}
+
+/// Returns a FAT32 image of 80Ki sectors formatted with `options`, whose
+/// partition begins at sector 2048.
+fn fat32_image_with(options: &FormatOptions) -> SharedImage {
+    let sectors_num = 80 * 1024u64;
+    let image = SharedImage(Arc::new(Mutex::new(Cursor::new(vec![0u8; sectors_num as usize * 512]))));
+    format(image.clone(), sectors_num, options).expect("format succeeds");
+    image
+}
+
+/// Returns a FAT32 image made by `fat32_image_with()` with one sector per
+/// cluster.
+fn fat32_image() -> SharedImage {
+    fat32_image_with(&FormatOptions { sectors_per_cluster: 1, ..Default::default() })
+}
+
+#[test]
+fn test_volume_info_and_label() {
+    use vfat::{FatType, VolumeInfo};
+
+    let options = FormatOptions { sectors_per_cluster: 1, volume_id: 0x1234ABCD, ..Default::default() };
+    let image = fat32_image_with(&options);
+
+    let vfat = vfat_from_image!(image);
+    let info = vfat.lock(|vfat| vfat.info()).expect("info succeeds");
+    assert_eq!(info, VolumeInfo {
+        label: None,
+        serial: Some(0x1234ABCD),
+        oem_name: "MSWIN4.1".into(),
+        fat_type: FatType::Fat32,
+        bytes_per_cluster: 512,
+        fat_num: 2,
+        total_clusters: info.total_clusters,
+        free_clusters: info.total_clusters - 1,
+    });
+
+    vfat.create_file("/board.cfg").expect("create succeeds");
+    vfat.lock(|vfat| vfat.set_label("board 7")).expect("set label succeeds");
+    vfat.lock(|vfat| vfat.flush()).unwrap();
+
+    // the label is in the EBPB, its backup and the root directory
+    for &sector in [2048, 2048 + 6].iter() {
+        let data = image.0.lock().unwrap().get_ref()[sector * 512..(sector + 1) * 512].to_vec();
+        let ebpb = BiosParameterBlock::from(Cursor::new(data), 0).expect("valid EBPB");
+        assert_eq!(ebpb.volume_label(), Some(*b"BOARD 7    "));
+    }
+    let vfat = vfat_from_image!(image);
+    let info = vfat.lock(|vfat| vfat.info()).unwrap();
+    assert_eq!((info.label.as_ref().map(|s| s.as_str()), info.free_clusters), (Some("BOARD 7"), info.total_clusters - 1));
+    let names: Vec<String> = vfat.open_dir("/").unwrap().entries().unwrap().map(|e| e.name().into()).collect();
+    assert_eq!(names, vec!["board.cfg"]);
+    assert_eq!(vfat.lock(|vfat| vfat.check(false)).expect("check succeeds"), vec![]);
+
+    // the label in the root directory wins over the EBPB
+    vfat.lock(|vfat| vfat.set_label("BOARD 8").and_then(|_| vfat.flush())).unwrap();
+    image.0.lock().unwrap().get_mut()[2048 * 512 + 71..2048 * 512 + 82].copy_from_slice(b"STALE      ");
+    let vfat = vfat_from_image!(image);
+    assert_eq!(vfat.lock(|vfat| vfat.info()).unwrap().label, Some("BOARD 8".into()));
+
+    vfat.lock(|vfat| vfat.set_label("")).expect("removing the label succeeds");
+    assert_eq!(vfat.lock(|vfat| vfat.info()).unwrap().label, None);
+    for &label in ["twelve chars", "a/b", " LEADING"].iter() {
+        let e = vfat.lock(|vfat| vfat.set_label(label)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    // FAT12/16 keep the extended fields at a different offset
+    let image = fat16_image(0x06, 32768, 32, 512);
+    {
+        let mut image = image.0.lock().unwrap();
+        let ebpb = &mut image.get_mut()[64 * 512..65 * 512];
+        ebpb[38] = 0x29;
+        ebpb[39..43].copy_from_slice(&0xCAFEu32.to_le_bytes());
+        ebpb[43..54].copy_from_slice(b"NO NAME    ");
+    }
+    let vfat = vfat_from_image!(image);
+    vfat.lock(|vfat| vfat.set_label("sixteen")).expect("set label succeeds");
+    vfat.lock(|vfat| vfat.flush()).unwrap();
+    assert_eq!(&image.0.lock().unwrap().get_ref()[64 * 512 + 43..64 * 512 + 54], b"SIXTEEN    ");
+    let vfat = vfat_from_image!(image);
+    let info = vfat.lock(|vfat| vfat.info()).unwrap();
+    assert_eq!((info.label, info.serial, info.fat_type), (Some("SIXTEEN".into()), Some(0xCAFE), FatType::Fat16));
+}
+
+#[test]
+fn test_unicode_names() {
+    let image = fat32_image();
+    let vfat = vfat_from_image!(image);
+
+    // the emoji's surrogate pair is split across two LFN entries
+    let names = ["café.txt", "Ωμέγα", "日本語 ファイル.md", "abcdefghijkl😀.txt"];
+    for name in names.iter() {
+        let mut file = vfat.create_file(format!("/{}", name)).expect("create succeeds");
+        file.write_all(name.as_bytes()).unwrap();
+        file.sync().unwrap();
+    }
+    let vfat = vfat_from_image!(image);
+    let listed: Vec<String> = vfat.open_dir("/").unwrap().entries().unwrap().map(|e| e.name().into()).collect();
+    assert_eq!(listed, names);
+    assert_eq!(vfat.lock(|vfat| vfat.check(false)).expect("check succeeds"), vec![]);
+
+    // lookups fold the case of non-ASCII letters
+    for &(lookup, name) in [("/CAFÉ.TXT", "café.txt"), ("/ωμέγα", "Ωμέγα"), ("/ΩΜΈΓΑ", "Ωμέγα")].iter() {
+        let mut buf = String::new();
+        vfat.open_file(lookup).expect("file exists").read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, name);
+    }
+    let e = vfat.create_file("/CAFÉ.txt").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+
+    // short names are stored in code page 437, with 0xE5 escaped as 0x05
+    let entry_offset = |file: &vfat::File<StdVFatHandle>, offset: u64| vfat.lock(|vfat| {
+        (2048 + vfat.cluster_to_sector(file.location.dir_cluster)) as usize * 512 + offset as usize
+    });
+    let mut file = vfat.create_file("/ABC.TXT").unwrap();
+    file.sync().unwrap();
+    let offset = entry_offset(&file, file.location.offset);
+    image.0.lock().unwrap().get_mut()[offset..offset + 3].copy_from_slice(&[0x82, b'T', 0x90]);
+    assert!(vfat_from_image!(image).open_file("/éTÉ.TXT").is_ok());
+    image.0.lock().unwrap().get_mut()[offset] = 0x05;
+    assert!(vfat_from_image!(image).open_file("/σTÉ.TXT").is_ok());
+
+    // an unpaired surrogate is decoded as U+FFFD
+    let file = vfat.open_file("/café.txt").unwrap();
+    let offset = entry_offset(&file, file.location.start_offset);
+    image.0.lock().unwrap().get_mut()[offset + 1..offset + 3].copy_from_slice(&0xD800u16.to_le_bytes());
+    let vfat = vfat_from_image!(image);
+    assert!(vfat.open_file("/\u{FFFD}afé.txt").is_ok());
+}
+
+#[derive(Debug, Clone)]
+struct TestClock(Arc<Mutex<std::time::Duration>>);
+
+impl Clock for TestClock {
+    fn now(&self) -> std::time::Duration {
+        *self.0.lock().unwrap()
+    }
+}
+
+#[test]
+fn test_timestamps() {
+    use std::time::Duration;
+    use vfat::Timestamp as VFatTimestamp;
+
+    fn ymdhms<T: Timestamp>(t: T) -> (usize, u8, u8, u8, u8, u8) {
+        (t.year(), t.month(), t.day(), t.hour(), t.minute(), t.second())
+    }
+
+    let t = VFatTimestamp::from_unix(Duration::from_secs(1519604720));
+    assert_eq!(ymdhms(t), (2018, 2, 26, 0, 25, 20));
+    assert_eq!(t.to_unix(), Duration::from_secs(1519604720));
+    let (t, hundredths) = VFatTimestamp::from_unix_precise(Duration::new(1582979697, 250_000_000));
+    assert_eq!((ymdhms(t), hundredths), ((2020, 2, 29, 12, 34, 56), 125));
+    assert_eq!(t.to_unix(), Duration::from_secs(1582979696));
+    // times outside of 1980 to 2107 are clamped
+    let t = VFatTimestamp::from_unix(Duration::from_secs(0));
+    assert_eq!((ymdhms(t), t.to_unix()), ((1980, 1, 1, 0, 0, 0), Duration::from_secs(315532800)));
+    let t = VFatTimestamp::from_unix(Duration::from_secs(1 << 40));
+    assert_eq!(ymdhms(t), (2107, 12, 31, 23, 59, 58));
+    assert_eq!(VFatTimestamp::default().to_unix(), Duration::from_secs(315532800));
+
+    let image = fat32_image();
+    let clock = TestClock(Arc::new(Mutex::new(Duration::new(1519604721, 500_000_000))));
+    let set_time = |secs: u64| *clock.0.lock().unwrap() = Duration::from_secs(secs);
+    let vfat = VFat::<StdVFatHandle>::from_with_clock(image.clone(), clock.clone()).expect("mount succeeds");
+
+    let dir = vfat.create_dir("/dir").expect("create succeeds");
+    let dot = dir.entries().unwrap().next().expect("`.` exists");
+    assert_eq!(ymdhms(dot.metadata().created()), (2018, 2, 26, 0, 25, 20));
+    assert_eq!(dot.metadata().created_hundredths, 150);
+    let mut file = dir.create_file("file").unwrap().into_file().unwrap();
+    set_time(1519700000);
+    file.write_all(b"stamped").unwrap();
+    assert_eq!(ymdhms(file.metadata.modified()), (2018, 2, 27, 2, 53, 20));
+    file.sync().unwrap();
+
+    // reads only record the access date
+    set_time(1519800000);
+    let mut file = vfat.open_file("/dir/file").unwrap();
+    file.read_to_end(&mut vec![]).unwrap();
+    file.sync().unwrap();
+    let metadata = vfat_from_image!(image).open_file("/dir/file").unwrap().metadata;
+    assert_eq!(ymdhms(metadata.created()), (2018, 2, 26, 0, 25, 20));
+    assert_eq!(metadata.created_hundredths, 150);
+    assert_eq!(ymdhms(metadata.modified()), (2018, 2, 27, 2, 53, 20));
+    assert_eq!(ymdhms(metadata.accessed()), (2018, 2, 28, 0, 0, 0));
+
+    // without a clock, timestamps are left alone
+    let mut file = vfat_from_image!(image).open_file("/dir/file").unwrap();
+    file.write_all(b"unstamped").unwrap();
+    assert_eq!(ymdhms(file.metadata.modified()), (2018, 2, 27, 2, 53, 20));
+}
+
+#[test]
+fn test_active_fat_and_mirroring() {
+    use vfat::MountOptions;
+
+    let image = fat32_image();
+    let data: Vec<u8> = (0..3000u32).map(|i| i as u8).collect();
+    let vfat = vfat_from_image!(image);
+    let mut file = vfat.create_file("/file").unwrap();
+    file.write_all(&data).unwrap();
+    file.sync().unwrap();
+    let start = file.start_cluster.cluster_id() as usize;
+
+    // break the chain in the first FAT and make the second one active
+    let ebpb_start = 2048 * 512;
+    let fat_start = (2048 + 32) * 512;
+    {
+        let mut image = image.0.lock().unwrap();
+        let bytes = image.get_mut();
+        bytes[fat_start + start * 4..fat_start + start * 4 + 4].copy_from_slice(&0x0FFFFFFFu32.to_le_bytes());
+        bytes[ebpb_start + 40..ebpb_start + 42].copy_from_slice(&0x81u16.to_le_bytes());
+    }
+    let vfat = vfat_from_image!(image);
+    assert_eq!(vfat.lock(|vfat| vfat.active_fat()), 1);
+    let mut buf = vec![];
+    vfat.open_file("/file").unwrap().read_to_end(&mut buf).expect("read succeeds");
+    assert_eq!(buf, data);
+
+    let verify = || MountOptions { verify_fat_copies: true, ..Default::default() };
+    let e = VFat::<StdVFatHandle>::from_with_options(image.clone(), verify()).unwrap_err();
+    expect_variant!(e, vfat::Error::FatMismatch { copy: 0, cluster } if cluster as usize == start);
+    let problems = vfat.lock(|vfat| vfat.check(true)).expect("check succeeds");
+    assert_eq!(problems, vec![Problem::FatMismatch { copy: 0, cluster: start as u32 }]);
+    let vfat = VFat::<StdVFatHandle>::from_with_options(image.clone(), verify()).expect("copies match");
+
+    // updates are written to every copy
+    let mut file = vfat.create_file("/other").unwrap();
+    file.write_all(&data).unwrap();
+    file.sync().unwrap();
+    vfat.remove_file("/file").unwrap();
+    vfat.lock(|vfat| vfat.flush()).unwrap();
+    assert_fat_copies_match(&image);
+
+    image.0.lock().unwrap().get_mut()[ebpb_start + 40] = 0x82;
+    let e = VFat::<StdVFatHandle>::from(image.clone()).unwrap_err();
+    expect_variant!(e, vfat::Error::Io(ref e) if e.kind() == io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_volume_dirty_bit() {
+    let image = fat32_image();
+    let fat_state = |image: &SharedImage, copy: usize| {
+        let bytes = image.0.lock().unwrap();
+        let bytes = bytes.get_ref();
+        let ebpb_start = 2048 * 512;
+        let sectors_per_fat = u32::from_le_bytes([
+            bytes[ebpb_start + 36], bytes[ebpb_start + 37], bytes[ebpb_start + 38], bytes[ebpb_start + 39],
+        ]);
+        let fat_start = (2048 + 32 + copy * sectors_per_fat as usize) * 512;
+        u32::from_le_bytes([bytes[fat_start + 4], bytes[fat_start + 5], bytes[fat_start + 6], bytes[fat_start + 7]])
+    };
+
+    let vfat = vfat_from_image!(image);
+    assert!(vfat.lock(|vfat| vfat.was_clean()));
+    assert!(!vfat.lock(|vfat| vfat.had_hard_error()));
+
+    // the first modification marks every FAT copy dirty on the disk at once
+    vfat.open_dir("/").unwrap();
+    assert_eq!(fat_state(&image, 0) & 0x0800_0000, 0x0800_0000);
+    vfat.create_dir("/dir").unwrap();
+    for copy in 0..2 {
+        assert_eq!(fat_state(&image, copy), 0x07FF_FFFF);
+    }
+    let remounted = vfat_from_image!(image);
+    assert!(!remounted.lock(|vfat| vfat.was_clean()));
+
+    // a flush marks it clean again
+    vfat.lock(|vfat| vfat.flush()).unwrap();
+    assert_eq!(fat_state(&image, 0), 0x0FFF_FFFF);
+    let remounted = vfat_from_image!(image);
+    assert!(remounted.lock(|vfat| vfat.was_clean()));
+    remounted.open_dir("/dir").expect("directory was written");
+
+    // the hard error bit is reported and left alone
+    image.0.lock().unwrap().get_mut()[(2048 + 32) * 512 + 7] = 0x0B;
+    let vfat = vfat_from_image!(image);
+    assert!(vfat.lock(|vfat| vfat.was_clean()));
+    assert!(vfat.lock(|vfat| vfat.had_hard_error()));
+    vfat.create_file("/file").unwrap();
+    vfat.lock(|vfat| vfat.flush()).unwrap();
+    assert_eq!(fat_state(&image, 0), 0x0BFF_FFFF);
+
+    // FAT16 keeps the bits in the high bits of its 16-bit FAT[1]
+    let image = fat16_image(0x06, 32768, 32, 512);
+    let vfat = vfat_from_image!(image);
+    assert!(vfat.lock(|vfat| vfat.was_clean()));
+    vfat.create_file("/file").unwrap();
+    let fat_start = (64 + 1) * 512;
+    assert_eq!(image.0.lock().unwrap().get_ref()[fat_start + 3], 0x7F);
+    assert!(!vfat_from_image!(image).lock(|vfat| vfat.was_clean()));
+    vfat.lock(|vfat| vfat.flush()).unwrap();
+    assert!(vfat_from_image!(image).lock(|vfat| vfat.was_clean()));
+}
+
+/// Returns the device sectors of the first sector of each FAT copy of a
+/// FAT32 image made by `fat32_image()`.
+fn fat32_fat_sectors(image: &SharedImage) -> [u64; 2] {
+    let mut ebpb = [0u8; 512];
+    image.clone().read_sector(2048, &mut ebpb).unwrap();
+    let sectors_per_fat = u32::from_le_bytes([ebpb[36], ebpb[37], ebpb[38], ebpb[39]]) as u64;
+    [2048 + 32, 2048 + 32 + sectors_per_fat]
+}
+
+#[test]
+fn test_fault_injection() {
+    let image = fat32_image();
+    let data: Vec<u8> = (0..3000u32).map(|i| (i * 7) as u8).collect();
+    let vfat = vfat_from_image!(image);
+    let mut file = vfat.create_file("/file").unwrap();
+    file.write_all(&data).unwrap();
+    file.sync().unwrap();
+    let data_sector = 2048 + vfat.lock(|vfat| vfat.cluster_to_sector(file.start_cluster));
+
+    // errors keep their kind on the way up, at mount and on reads
+    let (device, faults) = FaultyDevice::new(image.clone());
+    faults.fail_reads(2048, io::ErrorKind::TimedOut);
+    let e = VFat::<StdVFatHandle>::from(device).unwrap_err();
+    expect_variant!(e, vfat::Error::Io(ref e) if e.kind() == io::ErrorKind::TimedOut);
+
+    let (device, faults) = FaultyDevice::new(image.clone());
+    let vfat = VFat::<StdVFatHandle>::from(device).expect("mount succeeds");
+    faults.fail_reads(data_sector + 2, io::ErrorKind::TimedOut);
+    let mut buf = vec![];
+    let e = vfat.open_file("/file").unwrap().read_to_end(&mut buf).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+
+    // a flipped bit reaches the reader unnoticed
+    faults.clear();
+    faults.flip_bit(data_sector + 1, 10, 3);
+    let mut buf = vec![];
+    vfat.open_file("/file").unwrap().read_to_end(&mut buf).unwrap();
+    let mut expected = data.clone();
+    expected[512 + 10] ^= 1 << 3;
+    assert_eq!(buf, expected);
+
+    // a failed write back leaves the sector dirty for the next flush
+    faults.clear();
+    faults.fail_writes(data_sector, io::ErrorKind::PermissionDenied);
+    let mut file = vfat.open_file("/file").unwrap();
+    file.write_all(&[0xAA; 16]).unwrap();
+    let e = vfat.lock(|vfat| vfat.flush()).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+    assert!(!vfat_from_image!(image).lock(|vfat| vfat.was_clean()));
+    faults.clear();
+    vfat.lock(|vfat| vfat.flush()).expect("flush succeeds");
+    let remounted = vfat_from_image!(image);
+    assert!(remounted.lock(|vfat| vfat.was_clean()));
+    let mut buf = vec![];
+    remounted.open_file("/file").unwrap().read_to_end(&mut buf).unwrap();
+    assert_eq!(&buf[..16], &[0xAA; 16]);
+    assert_eq!(&buf[16..], &data[16..]);
+
+    // a chain leading to a bad cluster is reported as invalid data
+    let start = file.start_cluster;
+    vfat.lock(|vfat| vfat.set_fat_status(start, Status::Bad)).unwrap();
+    let mut buf = vec![];
+    let e = vfat.open_file("/file").unwrap().read_to_end(&mut buf).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_fat_write_failure() {
+    use std::time::Duration;
+
+    let image = fat32_image();
+    let data: Vec<u8> = (0..3000u32).map(|i| (i * 7) as u8).collect();
+    let vfat = vfat_from_image!(image);
+    vfat.create_file("/file").unwrap().write_all(&data).unwrap();
+    vfat.lock(|vfat| vfat.flush()).unwrap();
+
+    // the access date cannot be recorded when the dirty mark cannot be
+    // written, but reading still works, as does the next flush
+    let [fat, backup_fat] = fat32_fat_sectors(&image);
+    let (device, faults) = FaultyDevice::new(image.clone());
+    faults.fail_writes(fat, io::ErrorKind::PermissionDenied);
+    faults.fail_writes(backup_fat, io::ErrorKind::PermissionDenied);
+    let clock = TestClock(Arc::new(Mutex::new(Duration::from_secs(1519604721))));
+    let vfat = VFat::<StdVFatHandle>::from_with_clock(device, clock).expect("mount succeeds");
+    for _ in 0..2 {
+        let mut buf = vec![];
+        vfat.open_file("/file").unwrap().read_to_end(&mut buf).expect("read succeeds");
+        assert_eq!(buf, data);
+    }
+    vfat.lock(|vfat| vfat.flush()).expect("flush succeeds");
+    let e = vfat.create_file("/other").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+    vfat.lock(|vfat| vfat.flush()).expect("flush succeeds");
+    assert!(vfat_from_image!(image).lock(|vfat| vfat.was_clean()));
+
+    // once writes succeed again, the volume is marked dirty as usual
+    faults.clear();
+    vfat.create_file("/other").expect("create succeeds");
+    assert!(!vfat_from_image!(image).lock(|vfat| vfat.was_clean()));
+    vfat.lock(|vfat| vfat.flush()).unwrap();
+    assert!(vfat_from_image!(image).lock(|vfat| vfat.was_clean()));
+}
+
+#[test]
+fn test_access_trace() {
+    let image = fat32_image();
+    let [fat, backup_fat] = fat32_fat_sectors(&image);
+    let (device, trace) = RecordingDevice::new(image.clone());
+    let vfat = VFat::<StdVFatHandle>::from(device).expect("mount succeeds");
+    vfat.open_dir("/").unwrap().entries().unwrap().count();
+    assert_eq!(trace.count(Access::is_write), 0);
+
+    // the first modification writes the dirty mark of both FATs through
+    trace.take();
+    let mut file = vfat.create_file("/file").unwrap();
+    file.write_all(&[1; 3000]).unwrap();
+    let mut writes = trace.take().into_iter()
+        .filter(|access| !access.is_read())
+        .collect::<Vec<_>>();
+    assert_eq!(writes.pop(), Some(Access::Sync));
+    writes.sort_by_key(|access| match *access {
+        Access::Write { sector, .. } => sector,
+        _ => 0,
+    });
+    assert_eq!(writes, vec![
+        Access::Write { sector: fat, len: 512 },
+        Access::Write { sector: backup_fat, len: 512 },
+    ]);
+
+    // a flush writes every dirty sector once and the clean mark last
+    vfat.lock(|vfat| vfat.flush()).unwrap();
+    let data_sector = 2048 + vfat.lock(|vfat| vfat.cluster_to_sector(file.start_cluster));
+    assert_eq!(trace.writes_of(data_sector), 1);
+    assert_eq!(trace.writes_of(fat), 2);
+    let accesses = trace.take();
+    let end = &accesses[accesses.len() - 3..];
+    assert!(end.contains(&Access::Write { sector: fat, len: 512 }));
+    assert!(end.contains(&Access::Write { sector: backup_fat, len: 512 }));
+    assert_eq!(end[2], Access::Sync);
+
+    // nothing is written without a modification
+    vfat.lock(|vfat| vfat.flush()).unwrap();
+    assert_eq!(trace.count(Access::is_write), 0);
+}
+
+#[test]
+fn test_power_loss() {
+    let formatted = fat32_image().0.lock().unwrap().get_ref().clone();
+    let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+    let run = |cut: Option<u64>| -> (SharedImage, u64, u64) {
+        let image = SharedImage(Arc::new(Mutex::new(Cursor::new(formatted.clone()))));
+        let (device, power) = PowerLossDevice::new(image.clone());
+        if let Some(cut) = cut {
+            power.cut_after(cut);
+        }
+        let vfat = VFat::<StdVFatHandle>::from(device).expect("mount succeeds");
+        vfat.create_dir("/dir").unwrap();
+        vfat.create_file("/dir/file").unwrap().write_all(&data).unwrap();
+        vfat.lock(|vfat| vfat.flush()).unwrap();
+        (image, power.written(), power.dropped())
+    };
+    let (_, total, _) = run(None);
+
+    for cut in 0..=total {
+        let (image, written, dropped) = run(Some(cut));
+        assert_eq!((written, dropped), (cut, total - cut));
+        let vfat = vfat_from_image!(image);
+        let clean = vfat.lock(|vfat| vfat.was_clean());
+        // both FATs are marked dirty before anything else is written, and
+        // clean after everything else
+        if cut == 0 || cut == total {
+            assert!(clean, "cut after {} of {} writes", cut, total);
+        } else if cut >= 2 && cut < total - 1 {
+            assert!(!clean, "cut after {} of {} writes", cut, total);
+        }
+        let problems = vfat.lock(|vfat| vfat.check(false)).expect("check succeeds");
+        if cut == total {
+            assert_eq!(problems, vec![]);
+            let mut buf = vec![];
+            vfat.open_file("/dir/file").unwrap().read_to_end(&mut buf).unwrap();
+            assert_eq!(buf, data);
+        }
+    }
+}
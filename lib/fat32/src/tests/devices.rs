@@ -0,0 +1,287 @@
+//! `BlockDevice` adapters that wrap another device to inject faults, record
+//! the accesses made to it or simulate a power loss. Each adapter is created
+//! along with a handle that stays with the test once the adapter is moved
+//! into a `CachedPartition` or a `VFat`.
+
+use std::collections::HashMap;
+use std::io;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use crate::traits::BlockDevice;
+
+/// Returns the sectors of a request of `len` bytes beginning at sector `n`.
+fn sectors(n: u64, len: usize, sector_size: u64) -> Range<u64> {
+    n..n + (len as u64 + sector_size - 1) / sector_size
+}
+
+#[derive(Default)]
+struct FaultPlan {
+    read_errors: HashMap<u64, io::ErrorKind>,
+    write_errors: HashMap<u64, io::ErrorKind>,
+    bit_flips: Vec<(u64, usize, u8)>, // sector, byte and bit flipped on reads
+}
+
+/// Returns the error of the first sector of `sectors` that has one in
+/// `errors`.
+fn injected_error(errors: &HashMap<u64, io::ErrorKind>, mut sectors: Range<u64>) -> io::Result<()> {
+    match sectors.find_map(|sector| errors.get(&sector)) {
+        Some(&kind) => Err(io::Error::new(kind, "injected fault")),
+        None => Ok(()),
+    }
+}
+
+/// The faults injected by a `FaultyDevice`. Sectors are sectors of the
+/// wrapped device, not of the partition.
+#[derive(Clone, Default)]
+pub struct Faults(Arc<Mutex<FaultPlan>>);
+
+impl Faults {
+    /// Makes every read request that covers `sector` fail with an error of
+    /// kind `kind`.
+    pub fn fail_reads(&self, sector: u64, kind: io::ErrorKind) {
+        self.0.lock().unwrap().read_errors.insert(sector, kind);
+    }
+
+    /// Makes every write of `sector` fail with an error of kind `kind`. The
+    /// sector is left unchanged.
+    pub fn fail_writes(&self, sector: u64, kind: io::ErrorKind) {
+        self.0.lock().unwrap().write_errors.insert(sector, kind);
+    }
+
+    /// Flips bit `bit` of byte `byte` of `sector` in the data returned by
+    /// every read of it. The stored sector is left unchanged.
+    pub fn flip_bit(&self, sector: u64, byte: usize, bit: u8) {
+        self.0.lock().unwrap().bit_flips.push((sector, byte, bit));
+    }
+
+    /// Removes every fault.
+    pub fn clear(&self) {
+        *self.0.lock().unwrap() = FaultPlan::default();
+    }
+}
+
+/// A device that fails or corrupts the accesses of chosen sectors.
+pub struct FaultyDevice<D> {
+    device: D,
+    faults: Faults,
+}
+
+impl<D: BlockDevice> FaultyDevice<D> {
+    /// Wraps `device`, which behaves normally until faults are added through
+    /// the returned `Faults`.
+    pub fn new(device: D) -> (FaultyDevice<D>, Faults) {
+        let faults = Faults::default();
+        (FaultyDevice { device, faults: faults.clone() }, faults)
+    }
+
+    fn read(&mut self, n: u64, buf: &mut [u8], bulk: bool) -> io::Result<usize> {
+        let sector_size = self.sector_size();
+        let plan = self.faults.0.lock().unwrap();
+        let len = if bulk { buf.len() } else { buf.len().min(sector_size as usize) };
+        injected_error(&plan.read_errors, sectors(n, len, sector_size))?;
+        let read = if bulk { self.device.read_sectors(n, buf)? } else { self.device.read_sector(n, buf)? };
+        for &(sector, byte, bit) in plan.bit_flips.iter().filter(|&&(sector, _, _)| sector >= n) {
+            let index = ((sector - n) * sector_size) as usize + byte;
+            if index < read {
+                buf[index] ^= 1 << bit;
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for FaultyDevice<D> {
+    fn sector_size(&self) -> u64 {
+        self.device.sector_size()
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.read(n, buf, false)
+    }
+
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.read(n, buf, true)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        injected_error(&self.faults.0.lock().unwrap().write_errors, n..n + 1)?;
+        self.device.write_sector(n, buf)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.device.sync()
+    }
+}
+
+/// A request made to a `RecordingDevice`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// A read of `len` bytes beginning at sector `sector`.
+    Read { sector: u64, len: usize },
+    /// A write of `len` bytes to sector `sector`.
+    Write { sector: u64, len: usize },
+    Sync,
+}
+
+impl Access {
+    pub fn is_read(&self) -> bool {
+        match self {
+            Access::Read { .. } => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_write(&self) -> bool {
+        match self {
+            Access::Write { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+/// The accesses recorded by a `RecordingDevice`, oldest first.
+#[derive(Clone, Default)]
+pub struct Trace(Arc<Mutex<Vec<Access>>>);
+
+impl Trace {
+    /// Returns the accesses recorded since the last call and forgets them.
+    pub fn take(&self) -> Vec<Access> {
+        self.0.lock().unwrap().drain(..).collect()
+    }
+
+    /// Returns the number of recorded accesses matching `predicate`.
+    pub fn count(&self, predicate: impl Fn(&Access) -> bool) -> usize {
+        self.0.lock().unwrap().iter().filter(|access| predicate(access)).count()
+    }
+
+    /// Returns the number of recorded writes of `sector`.
+    pub fn writes_of(&self, sector: u64) -> usize {
+        self.count(|access| match *access {
+            Access::Write { sector: written, .. } => written == sector,
+            _ => false,
+        })
+    }
+}
+
+/// A device that records every request made to it.
+pub struct RecordingDevice<D> {
+    device: D,
+    trace: Trace,
+}
+
+impl<D: BlockDevice> RecordingDevice<D> {
+    /// Wraps `device`, recording its accesses in the returned `Trace`.
+    pub fn new(device: D) -> (RecordingDevice<D>, Trace) {
+        let trace = Trace::default();
+        (RecordingDevice { device, trace: trace.clone() }, trace)
+    }
+
+    fn record(&self, access: Access) {
+        self.trace.0.lock().unwrap().push(access);
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for RecordingDevice<D> {
+    fn sector_size(&self) -> u64 {
+        self.device.sector_size()
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.record(Access::Read { sector: n, len: buf.len() });
+        self.device.read_sector(n, buf)
+    }
+
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.record(Access::Read { sector: n, len: buf.len() });
+        self.device.read_sectors(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.record(Access::Write { sector: n, len: buf.len() });
+        self.device.write_sector(n, buf)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.record(Access::Sync);
+        self.device.sync()
+    }
+}
+
+#[derive(Default)]
+struct PowerState {
+    writes_left: Option<u64>, // None while the power stays on
+    written: u64,
+    dropped: u64,
+}
+
+/// Controls the power of a `PowerLossDevice`.
+#[derive(Clone, Default)]
+pub struct PowerSwitch(Arc<Mutex<PowerState>>);
+
+impl PowerSwitch {
+    /// Cuts the power once `writes` more sectors have been written. Writes
+    /// after that are dropped.
+    pub fn cut_after(&self, writes: u64) {
+        self.0.lock().unwrap().writes_left = Some(writes);
+    }
+
+    /// Returns the number of sectors written to the wrapped device.
+    pub fn written(&self) -> u64 {
+        self.0.lock().unwrap().written
+    }
+
+    /// Returns the number of sector writes dropped since the power was cut.
+    pub fn dropped(&self) -> u64 {
+        self.0.lock().unwrap().dropped
+    }
+}
+
+/// A device that silently drops every write once its power is cut, the way
+/// a card loses the writes still in flight when the board loses power.
+/// Reads keep working.
+pub struct PowerLossDevice<D> {
+    device: D,
+    power: PowerSwitch,
+}
+
+impl<D: BlockDevice> PowerLossDevice<D> {
+    /// Wraps `device`, whose power stays on until cut through the returned
+    /// `PowerSwitch`.
+    pub fn new(device: D) -> (PowerLossDevice<D>, PowerSwitch) {
+        let power = PowerSwitch::default();
+        (PowerLossDevice { device, power: power.clone() }, power)
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for PowerLossDevice<D> {
+    fn sector_size(&self) -> u64 {
+        self.device.sector_size()
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.device.read_sector(n, buf)
+    }
+
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.device.read_sectors(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.power.0.lock().unwrap();
+        match state.writes_left {
+            Some(0) => {
+                state.dropped += 1;
+                return Ok(buf.len().min(self.device.sector_size() as usize));
+            },
+            Some(ref mut left) => *left -= 1,
+            None => {},
+        }
+        state.written += 1;
+        self.device.write_sector(n, buf)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.device.sync()
+    }
+}
@@ -20,6 +20,25 @@ pub trait BlockDevice: Send {
     /// Returns an error if seeking or reading from `self` fails.
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize>;
 
+    /// Read consecutive sectors beginning at sector number `n` into `buf`.
+    ///
+    /// `buf.len()` bytes are read; the last sector may be read partially. The
+    /// number of bytes read is returned. The default implementation calls
+    /// `read_sector` once per sector; devices that can transfer several
+    /// sectors in one request should override it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if seeking or reading from `self` fails.
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector_size = self.sector_size() as usize;
+        let mut read = 0;
+        for (i, chunk) in buf.chunks_mut(sector_size).enumerate() {
+            read += self.read_sector(n + i as u64, chunk)?;
+        }
+        Ok(read)
+    }
+
     /// Append sector number `n` into `vec`.
     ///
     /// `self.sector_size()` bytes are appended to `vec`. The number of bytes
@@ -56,16 +75,38 @@ pub trait BlockDevice: Send {
     /// error of `UnexpectedEof` if the length of `buf` is less than
     /// `self.sector_size()`.
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize>;
+
+    /// Ensures that all sectors written to `self` have reached the storage.
+    /// Devices that do not buffer writes need not override this.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if flushing buffered data fails.
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 impl<'a, T: BlockDevice> BlockDevice for &'a mut T {
+    fn sector_size(&self) -> u64 {
+        (**self).sector_size()
+    }
+
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
         (*self).read_sector(n, buf)
     }
 
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        (*self).read_sectors(n, buf)
+    }
+
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
         (*self).write_sector(n, buf)
     }
+
+    fn sync(&mut self) -> io::Result<()> {
+        (*self).sync()
+    }
 }
 
 macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
@@ -80,6 +121,12 @@ macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
             Ok(to_read)
         }
 
+        fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+            self.seek(io::SeekFrom::Start(n * self.sector_size()))?;
+            self.read_exact(buf)?;
+            Ok(buf.len())
+        }
+
         fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
             let sector_size = self.sector_size();
             let to_write = ::core::cmp::min(sector_size as usize, buf.len());
@@ -87,11 +134,15 @@ macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
             self.write_all(&buf[..to_write])?;
             Ok(to_write)
         }
+
+        fn sync(&mut self) -> io::Result<()> {
+            self.flush()
+        }
     }
 }
 
 impl_for_read_write_seek!(<'a> shim::io::Cursor<&'a mut [u8]>);
 impl_for_read_write_seek!(shim::io::Cursor<Vec<u8>>);
 impl_for_read_write_seek!(shim::io::Cursor<Box<[u8]>>);
-#[cfg(test)]
+#[cfg(not(feature = "no_std"))]
 impl_for_read_write_seek!(::std::fs::File);
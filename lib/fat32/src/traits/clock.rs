@@ -0,0 +1,12 @@
+use core::fmt::Debug;
+use core::time::Duration;
+
+/// Trait implemented by sources of the current time, used to stamp the
+/// timestamps of directory entries.
+pub trait Clock: Debug + Send + Sync {
+    /// Returns the current time as the time since the Unix epoch.
+    ///
+    /// FAT timestamps record local time, so the returned time should be
+    /// offset by the local time zone.
+    fn now(&self) -> Duration;
+}
@@ -37,6 +37,9 @@ impl File for Dummy {
     fn is_end(&self) -> bool {
         panic!("Dummy")
     }
+    fn set_len(&mut self, _size: u64) -> io::Result<()> {
+        panic!("Dummy")
+    }
 }
 
 /// Trait implemented by directories in a file system.
@@ -49,6 +52,26 @@ impl Dir for Dummy {
     fn entries(&self) -> io::Result<Self::Iter> {
         panic!("Dummy")
     }
+
+    fn create_file(&self, _name: &str) -> io::Result<Self::Entry> {
+        panic!("Dummy")
+    }
+
+    fn create_dir(&self, _name: &str) -> io::Result<Self::Entry> {
+        panic!("Dummy")
+    }
+
+    fn remove_file(&self, _name: &str) -> io::Result<()> {
+        panic!("Dummy")
+    }
+
+    fn remove_dir(&self, _name: &str) -> io::Result<()> {
+        panic!("Dummy")
+    }
+
+    fn rename(&self, _name: &str, _new_dir: &Self, _new_name: &str) -> io::Result<()> {
+        panic!("Dummy")
+    }
 }
 
 impl Iterator for Dummy {
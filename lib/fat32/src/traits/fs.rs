@@ -12,6 +12,16 @@ pub trait File: io::Read + io::Write + io::Seek + Sized {
 
     /// Returns whether the file pos reach till the end of the file
     fn is_end(&self) -> bool;
+
+    /// Truncates or extends the file to `size` bytes. Bytes added by an
+    /// extension read as zeros. The file position is left unchanged.
+    ///
+    /// # Errors
+    ///
+    /// If `size` is larger than the file system supports, an error kind of
+    /// `InvalidInput` is returned. All other error values are implementation
+    /// defined.
+    fn set_len(&mut self, size: u64) -> io::Result<()>;
 }
 
 /// Trait implemented by directories in a file system.
@@ -24,6 +34,77 @@ pub trait Dir: Sized {
 
     /// Returns an interator over the entries in this directory.
     fn entries(&self) -> io::Result<Self::Iter>;
+
+    /// Creates an empty regular file named `name` in this directory and
+    /// returns its entry.
+    ///
+    /// # Errors
+    ///
+    /// If `name` is not a valid file name, an error kind of `InvalidInput` is
+    /// returned.
+    ///
+    /// If an entry named `name` already exists, an error kind of
+    /// `AlreadyExists` is returned.
+    ///
+    /// All other error values are implementation defined.
+    fn create_file(&self, name: &str) -> io::Result<Self::Entry>;
+
+    /// Creates an empty directory named `name` in this directory and returns
+    /// its entry.
+    ///
+    /// # Errors
+    ///
+    /// The error conditions are the same as for `create_file()`.
+    fn create_dir(&self, name: &str) -> io::Result<Self::Entry>;
+
+    /// Removes the regular file named `name` from this directory and frees
+    /// its data.
+    ///
+    /// # Errors
+    ///
+    /// If there is no entry named `name`, an error kind of `NotFound` is
+    /// returned.
+    ///
+    /// If the entry named `name` is not a regular file, an error kind of
+    /// `Other` is returned.
+    ///
+    /// All other error values are implementation defined.
+    fn remove_file(&self, name: &str) -> io::Result<()>;
+
+    /// Removes the empty directory named `name` from this directory.
+    ///
+    /// # Errors
+    ///
+    /// If there is no entry named `name`, an error kind of `NotFound` is
+    /// returned.
+    ///
+    /// If `name` is `.` or `..`, an error kind of `InvalidInput` is returned.
+    ///
+    /// If the entry named `name` is not a directory or the directory is not
+    /// empty, an error kind of `Other` is returned.
+    ///
+    /// All other error values are implementation defined.
+    fn remove_dir(&self, name: &str) -> io::Result<()>;
+
+    /// Moves the entry named `name` in this directory into `new_dir` under
+    /// the name `new_name`. `new_dir` may be this directory. An existing
+    /// entry named `new_name` in `new_dir` is replaced if it is of the same
+    /// kind and, if it is a directory, empty.
+    ///
+    /// # Errors
+    ///
+    /// If there is no entry named `name`, an error kind of `NotFound` is
+    /// returned.
+    ///
+    /// If `name` is `.` or `..`, `new_name` is not a valid file name or a
+    /// directory would be moved into itself, an error kind of `InvalidInput`
+    /// is returned.
+    ///
+    /// If an existing entry named `new_name` cannot be replaced, the errors
+    /// of `remove_file()` or `remove_dir()` are returned.
+    ///
+    /// All other error values are implementation defined.
+    fn rename(&self, name: &str, new_dir: &Self, new_name: &str) -> io::Result<()>;
 }
 
 /// Trait implemented by directory entries in a file system.
@@ -116,4 +197,85 @@ pub trait FileSystem: Sized {
             .into_dir()
             .ok_or(io::Error::new(io::ErrorKind::Other, "not a directory"))
     }
+
+    /// Creates an empty regular file at `path`. `path` must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the error conditions for `open_dir()` on the parent of
+    /// `path`, this method returns the errors of `Dir::create_file()`. If
+    /// `path` has no file name, an error kind of `InvalidInput` is returned.
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
+        let (parent, name) = split_path(path.as_ref())?;
+        self.open_dir(parent)?
+            .create_file(name)?
+            .into_file()
+            .ok_or(io::Error::new(io::ErrorKind::Other, "not a regular file"))
+    }
+
+    /// Creates an empty directory at `path`. `path` must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// The error conditions are the same as for `create_file()`, except that
+    /// `Dir::create_dir()` is used to create the directory.
+    fn create_dir<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Dir> {
+        let (parent, name) = split_path(path.as_ref())?;
+        self.open_dir(parent)?
+            .create_dir(name)?
+            .into_dir()
+            .ok_or(io::Error::new(io::ErrorKind::Other, "not a directory"))
+    }
+
+    /// Removes the regular file at `path`. `path` must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the error conditions for `open_dir()` on the parent of
+    /// `path`, this method returns the errors of `Dir::remove_file()`. If
+    /// `path` has no file name, an error kind of `InvalidInput` is returned.
+    fn remove_file<P: AsRef<Path>>(self, path: P) -> io::Result<()> {
+        let (parent, name) = split_path(path.as_ref())?;
+        self.open_dir(parent)?.remove_file(name)
+    }
+
+    /// Removes the empty directory at `path`. `path` must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// The error conditions are the same as for `remove_file()`, except that
+    /// `Dir::remove_dir()` is used to remove the directory.
+    fn remove_dir<P: AsRef<Path>>(self, path: P) -> io::Result<()> {
+        let (parent, name) = split_path(path.as_ref())?;
+        self.open_dir(parent)?.remove_dir(name)
+    }
+
+    /// Moves the entry at `from` to `to`. Both paths must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the error conditions for `open_dir()` on the parents of
+    /// `from` and `to`, this method returns the errors of `Dir::rename()`. If
+    /// either path has no file name, an error kind of `InvalidInput` is
+    /// returned.
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<()>
+    where
+        Self: Copy,
+    {
+        let (from_parent, from_name) = split_path(from.as_ref())?;
+        let (to_parent, to_name) = split_path(to.as_ref())?;
+        let from_dir = self.open_dir(from_parent)?;
+        let to_dir = self.open_dir(to_parent)?;
+        from_dir.rename(from_name, &to_dir, to_name)
+    }
+}
+
+/// Splits `path` into its parent directory and its final component.
+fn split_path(path: &Path) -> io::Result<(&Path, &str)> {
+    let parent = path.parent();
+    let name = path.file_name().and_then(|name| name.to_str());
+    match (parent, name) {
+        (Some(parent), Some(name)) => Ok((parent, name)),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "path has no valid file name")),
+    }
 }
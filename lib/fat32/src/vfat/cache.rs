@@ -27,8 +27,16 @@ pub struct CachedPartition {
     // cache: HashMap<u64, CacheEntry>,
     cache: LRUCache<CacheEntry>,
     partition: Partition,
+    readahead: u64, // sectors read along with a sequential miss
+    next_miss: u64, // the sector following the last one read on a miss
 }
 
+/// Sectors read ahead by default on a sequential miss.
+const DEFAULT_READAHEAD: u64 = 8;
+/// Readahead is capped well below the cache capacity so that a miss never
+/// evicts the sectors it just read.
+const MAX_READAHEAD: u64 = 32;
+
 impl CachedPartition {
     /// Creates a new `CachedPartition` that transparently caches sectors from
     /// `device` and maps physical sectors to logical sectors inside of
@@ -56,9 +64,18 @@ impl CachedPartition {
             device: Box::new(device),
             cache: LRUCache::new(),
             partition: partition,
+            readahead: DEFAULT_READAHEAD,
+            next_miss: 0,
         }
     }
 
+    /// Sets the number of sectors read ahead into the cache when a sector is
+    /// missed right after the previously missed run, i.e. during sequential
+    /// access. `0` disables readahead. Values above 32 are capped.
+    pub fn set_readahead(&mut self, sectors: u64) {
+        self.readahead = sectors.min(MAX_READAHEAD);
+    }
+
     /// Returns the number of physical sectors that corresponds to
     /// one logical sector.
     fn factor(&self) -> u64 {
@@ -88,19 +105,13 @@ impl CachedPartition {
     /// # Errors
     ///
     /// Returns an error if there is an error reading the sector from the disk.
-    #[allow(dead_code)]
     pub fn get_mut(&mut self, sector: u64) -> io::Result<&mut [u8]> {
         if self.cache.contains_key(&sector) {
             let cache_entry = self.cache.get_mut(sector).unwrap();
             cache_entry.dirty = true;
             Ok(cache_entry.data.as_mut_slice())
         } else {
-            let mut buf = vec![0; self.sector_size() as usize];
-            self.read_sector(sector, &mut buf[..])?;
-            self.cache.put(sector, CacheEntry {
-                data: buf,
-                dirty: true,
-            });
+            self.fill(sector, true)?;
             let cache_entry = self.cache.get_mut(sector).unwrap();
             Ok(cache_entry.data.as_mut_slice())
         }
@@ -117,16 +128,139 @@ impl CachedPartition {
             let cache_entry = self.cache.get(sector).unwrap();
             Ok(cache_entry.data.as_slice())
         } else {
-            let mut buf = vec![0; self.partition.sector_size as usize];
-            self.read_sector(sector, &mut buf[..])?;
-            self.cache.put(sector, CacheEntry {
-                data: buf,
-                dirty: false,
-            });
+            self.fill(sector, false)?;
             let cache_entry = self.cache.get(sector).unwrap();
             Ok(cache_entry.data.as_slice())
         }
     }
+
+    /// Discards the changes made to the cached sector `sector` by reading it
+    /// back from the disk and marking it clean. Does nothing if the sector is
+    /// not cached.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is an error reading the sector from the disk.
+    /// The cached sector is left unchanged in that case.
+    pub fn revert(&mut self, sector: u64) -> io::Result<()> {
+        if !self.cache.contains_key(&sector) {
+            return Ok(());
+        }
+        let mut data = vec![0; self.sector_size() as usize];
+        let start_physical_sec = match self.virtual_to_physical(sector) {
+            Some(start_physical_sec) => start_physical_sec,
+            None => return ioerr!(UnexpectedEof, "sector number is out of range"),
+        };
+        self.device.read_sectors(start_physical_sec, &mut data)?;
+        let cache_entry = self.cache.get_mut(sector).unwrap();
+        cache_entry.data = data;
+        cache_entry.dirty = false;
+        Ok(())
+    }
+
+    /// Writes every dirty cached sector back to the disk and marks it clean.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is an error writing a sector to the disk.
+    /// Sectors that were not yet written back stay dirty.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let (start, factor) = (self.partition.start, self.factor());
+        let device = &mut *self.device;
+        for (sector, cache_entry) in self.cache.iter_mut() {
+            write_back(device, start, factor, sector, cache_entry)?;
+        }
+        Ok(())
+    }
+
+    /// Writes every dirty cached sector back to the disk, then asks the
+    /// underlying device to persist any data it buffers.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `flush()` or syncing the device fails.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.device.sync()
+    }
+
+    /// Reads the uncached sector `sector` from the disk into the cache and
+    /// marks it dirty if `dirty` is set. If the miss continues the previous
+    /// one, up to `readahead` following uncached sectors are read with it in
+    /// a single device request.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the sectors or caching them fails.
+    fn fill(&mut self, sector: u64, dirty: bool) -> io::Result<()> {
+        let mut count = 1;
+        if sector == self.next_miss {
+            while count <= self.readahead
+                && sector + count < self.partition.num_sectors
+                && !self.cache.contains_key(&(sector + count))
+            {
+                count += 1;
+            }
+        }
+        let sector_size = self.sector_size() as usize;
+        let mut buf = vec![0; count as usize * sector_size];
+        let start_physical_sec = match self.virtual_to_physical(sector) {
+            Some(start_physical_sec) => start_physical_sec,
+            None => return ioerr!(UnexpectedEof, "sector number is out of range"),
+        };
+        self.device.read_sectors(start_physical_sec, &mut buf)?;
+        self.next_miss = sector + count;
+
+        // cache `sector` last so it is the most recently used one
+        for (i, data) in buf.chunks(sector_size).enumerate().rev() {
+            self.insert(sector + i as u64, CacheEntry {
+                data: data.to_vec(),
+                dirty: dirty && i == 0,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Caches `cache_entry` as the sector `sector`. If the cache is full, the
+    /// least recently used sector is written back first if it is dirty, and
+    /// then evicted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing back the evicted sector fails. Nothing is
+    /// evicted or cached in that case.
+    fn insert(&mut self, sector: u64, cache_entry: CacheEntry) -> io::Result<()> {
+        if self.cache.is_full() {
+            let (start, factor) = (self.partition.start, self.factor());
+            if let Some((victim, victim_entry)) = self.cache.lru_mut() {
+                write_back(&mut *self.device, start, factor, victim, victim_entry)?;
+            }
+        }
+        self.cache.put(sector, cache_entry);
+        Ok(())
+    }
+}
+
+/// Writes the cached sector `sector` of the partition beginning at physical
+/// sector `start`, with `factor` physical sectors per logical sector, back to
+/// `device` if it is dirty, and marks it clean.
+fn write_back(
+    device: &mut dyn BlockDevice,
+    start: u64,
+    factor: u64,
+    sector: u64,
+    cache_entry: &mut CacheEntry,
+) -> io::Result<()> {
+    if !cache_entry.dirty {
+        return Ok(());
+    }
+    let physical_sec_size = device.sector_size() as usize;
+    let start_physical_sec = start + sector * factor;
+    for (i, chunk) in cache_entry.data.chunks(physical_sec_size).enumerate() {
+        device.write_sector(start_physical_sec + i as u64, chunk)?;
+    }
+    cache_entry.dirty = false;
+    Ok(())
 }
 
 // FIXME: Implement `BlockDevice` for `CacheDevice`. The `read_sector` and
@@ -151,6 +285,28 @@ impl BlockDevice for CachedPartition {
         }
     }
 
+    /// Reads the run of sectors beginning at `sector` in a single device
+    /// request, bypassing the cache so bulk reads do not evict the sectors
+    /// cached for metadata. Dirty cached sectors take precedence over the
+    /// disk contents.
+    fn read_sectors(&mut self, sector: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector_size = self.sector_size() as usize;
+        let count = ((buf.len() + sector_size - 1) / sector_size) as u64;
+        let start_physical_sec = match self.virtual_to_physical(sector) {
+            Some(start_physical_sec) if sector + count <= self.partition.num_sectors => start_physical_sec,
+            _ => return ioerr!(UnexpectedEof, "sector number is out of range"),
+        };
+        self.device.read_sectors(start_physical_sec, buf)?;
+        for (i, chunk) in buf.chunks_mut(sector_size).enumerate() {
+            if let Some(cache_entry) = self.cache.peek(sector + i as u64) {
+                if cache_entry.dirty {
+                    chunk.copy_from_slice(&cache_entry.data[..chunk.len()]);
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
     fn write_sector(&mut self, sector: u64, buf: &[u8]) -> io::Result<usize> {
         let physical_sec_size = self.device.sector_size();
         let write_size = buf.len().min(self.sector_size() as usize);
@@ -165,6 +321,10 @@ impl BlockDevice for CachedPartition {
             ioerr!(UnexpectedEof, "sector number is out of range")
         }
     }
+
+    fn sync(&mut self) -> io::Result<()> {
+        CachedPartition::sync(self)
+    }
 }
 
 impl fmt::Debug for CachedPartition {
@@ -1,4 +1,4 @@
-#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Hash)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Default, Copy, Clone, Hash)]
 pub struct Cluster(u32);
 
 impl From<u32> for Cluster {
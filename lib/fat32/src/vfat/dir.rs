@@ -8,10 +8,13 @@ use shim::{io, ioerr};
 use crate::traits;
 use crate::util::VecExt;
 use crate::vfat::{Date, Metadata, Time, Timestamp};
-use crate::vfat::{Cluster, Entry, File, VFatHandle};
+use crate::vfat::{Cluster, Entry, File, VFat, VFatHandle};
+use crate::vfat::name;
 
+use core::fmt;
 use core::str;
 use core::char;
+use core::mem::{self, size_of};
 
 #[derive(Debug)]
 pub struct Dir<HANDLE: VFatHandle> {
@@ -20,6 +23,20 @@ pub struct Dir<HANDLE: VFatHandle> {
     pub start_cluster: Cluster,
     pub metadata: Metadata,
     pub name: String,
+    pub location: EntryLocation,
+}
+
+/// Location of an entry's regular directory entry inside its parent
+/// directory.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct EntryLocation {
+    /// The start cluster of the parent directory.
+    pub dir_cluster: Cluster,
+    /// Byte offset of the first entry belonging to the entry, i.e. its
+    /// first LFN entry or the regular entry if it has no long file name.
+    pub start_offset: u64,
+    /// Byte offset of the regular entry in the parent directory's chain.
+    pub offset: u64,
 }
 
 #[repr(C, packed)]
@@ -29,7 +46,8 @@ pub struct VFatRegularDirEntry {
     pub name: [u8; 8],
     pub extension: [u8; 3],
     pub attributes: u8,
-    _1: [u8; 2],
+    _1: u8,
+    pub created_hundredths: u8, // 10ms units past `created_time`, 0 to 199
     pub created_time: Time,
     pub created_date: Date,
     pub accessed_date: Date,
@@ -49,7 +67,8 @@ pub struct VFatLfnDirEntry {
     pub sequence_num: u8,
     pub name_1: [u16; 5],
     pub attributes: u8, // use to determine if it is LFN
-    _1: [u8; 2],
+    _1: u8,
+    pub checksum: u8, // checksum of the corresponding short file name
     pub name_2: [u16; 6],
     _2: [u8; 2],
     pub name_3: [u16; 2],
@@ -85,8 +104,8 @@ use crate::traits::Dir as DirTrait;
 use crate::traits::Entry as EntryTrait;
 
 impl<HANDLE: VFatHandle> Dir<HANDLE> {
-    /// Finds the entry named `name` in `self` and returns it. Comparison is
-    /// case-insensitive.
+    /// Finds the entry named `name` in `self` and returns it. Comparison
+    /// ignores the case of characters in the BMP, as on Windows.
     ///
     /// # Errors
     ///
@@ -100,7 +119,7 @@ impl<HANDLE: VFatHandle> Dir<HANDLE> {
         if let Some(name) = name.to_str() {
             let entries = self.entries()?;
             for entry in entries {
-                if name.eq_ignore_ascii_case(entry.name()) {
+                if name::eq_ignore_case(name, entry.name()) {
                     return Ok(entry);
                 }
             }
@@ -110,6 +129,299 @@ impl<HANDLE: VFatHandle> Dir<HANDLE> {
         }
     }
 
+    /// Creates a new entry named `name` with the attributes `attributes` in
+    /// `self`. If the new entry is a directory, its first cluster is
+    /// allocated and filled with the `.` and `..` entries.
+    fn create_entry(&self, name: &str, attributes: u8) -> io::Result<Entry<HANDLE>> {
+        check_name(name)?;
+        if self.find(name).is_ok() {
+            return ioerr!(AlreadyExists, "Dir::create_entry: entry already exists");
+        }
+
+        self.vfat.lock(|vfat| {
+            let now = vfat.now();
+            let new_entry = |short_name: [u8; 11], cluster: Cluster| {
+                let mut regular_entry = VFatRegularDirEntry::new(short_name, attributes, cluster);
+                if let Some((now, hundredths)) = now {
+                    regular_entry.set_created(now, hundredths);
+                }
+                regular_entry
+            };
+            // a new directory owns a cluster holding its `.` and `..` entries
+            let start_cluster = if attributes & VFatRegularDirEntry::ATTR_DIRECTORY_FLAG != 0 {
+                let cluster = vfat.alloc_cluster(None)?;
+                let mut buf = vec![0u8; vfat.bytes_per_cluster() as usize];
+                let dot_entries = [
+                    new_entry(*b".          ", cluster),
+                    new_entry(*b"..         ", self.parent_cluster_id(vfat)),
+                ];
+                for (i, dot_entry) in dot_entries.iter().enumerate() {
+                    buf[i * 32..(i + 1) * 32].copy_from_slice(&dot_entry.to_bytes());
+                }
+                vfat.write_cluster(cluster, 0, &buf)?;
+                cluster
+            } else {
+                Cluster::from(0)
+            };
+            let regular_entry = new_entry(*b"           ", start_cluster);
+            let location = self.insert_entry(vfat, name, regular_entry)?;
+            let metadata = regular_entry.metadata();
+            if attributes & VFatRegularDirEntry::ATTR_DIRECTORY_FLAG != 0 {
+                Ok(Entry::Dir(Dir {
+                    vfat: self.vfat.clone(),
+                    start_cluster,
+                    metadata,
+                    name: name.into(),
+                    location,
+                }))
+            } else {
+                Ok(Entry::File(File {
+                    vfat: self.vfat.clone(),
+                    start_cluster,
+                    metadata,
+                    name: name.into(),
+                    pos: 0,
+                    size: 0,
+                    location,
+                    clusters: Vec::new(),
+                }))
+            }
+        })
+    }
+
+    /// Stores `regular_entry` in `self` under the name `name` and returns its
+    /// location. A long file name entry sequence is written in front of the
+    /// regular entry unless `name` is a valid 8.3 short name by itself. The
+    /// short name of `regular_entry` is overwritten.
+    fn insert_entry(
+        &self,
+        vfat: &mut VFat<HANDLE>,
+        name: &str,
+        mut regular_entry: VFatRegularDirEntry,
+    ) -> io::Result<EntryLocation> {
+        let dir_entries = self.read_dir_entries(vfat)?;
+        let short_names: Vec<[u8; 11]> = dir_entries.iter()
+            .take_while(|dir_entry| !dir_entry.is_last_entry())
+            .filter(|dir_entry| !dir_entry.is_unused_entry())
+            .filter_map(|dir_entry| match dir_entry.to_wrap_entry() {
+                VFatWrapEntry::Reguler(regular_entry) => Some(regular_entry.short_name()),
+                VFatWrapEntry::LongFilename(_) => None,
+            })
+            .collect();
+
+        // a name that is a valid 8.3 name is stored without LFN entries
+        let (short_name, mut new_entries) = match exact_short_name(name) {
+            Some(short_name) => {
+                if short_names.contains(&short_name) {
+                    return ioerr!(AlreadyExists, "Dir::insert_entry: entry already exists");
+                }
+                (short_name, vec![])
+            },
+            None => {
+                let short_name = generate_short_name(name, &short_names)?;
+                (short_name, VFatLfnDirEntry::sequence(name, lfn_checksum(&short_name)))
+            },
+        };
+        regular_entry.set_short_name(short_name);
+        new_entries.push(VFatDirEntry { regular: regular_entry });
+
+        let index = place_entries(vfat, self.start_cluster, &dir_entries, &new_entries)?;
+        let end = (index + new_entries.len()) * size_of::<VFatDirEntry>();
+        Ok(EntryLocation {
+            dir_cluster: self.start_cluster,
+            start_offset: (index * size_of::<VFatDirEntry>()) as u64,
+            offset: (end - size_of::<VFatDirEntry>()) as u64,
+        })
+    }
+
+    /// Returns the cluster recorded in the `..` entry of a child of `self`,
+    /// which is 0 if `self` is the root directory.
+    fn parent_cluster_id(&self, vfat: &VFat<HANDLE>) -> Cluster {
+        if self.start_cluster == vfat.rootdir_cluster() {
+            Cluster::from(0)
+        } else {
+            self.start_cluster
+        }
+    }
+
+    /// Removes the entry named `name` from `self`. Its regular entry and all
+    /// of its LFN entries are marked as deleted and its cluster chain is
+    /// freed. A directory is only removed if it is empty.
+    fn remove_entry(&self, name: &str, is_dir: bool) -> io::Result<()> {
+        if name == "." || name == ".." {
+            return ioerr!(InvalidInput, "Dir::remove_entry: cannot remove `.` or `..`");
+        }
+        let (start_cluster, location) = match self.find(name)? {
+            Entry::File(file) => {
+                if is_dir {
+                    return ioerr!(Other, "not a directory");
+                }
+                (file.start_cluster, file.location)
+            },
+            Entry::Dir(dir) => {
+                if !is_dir {
+                    return ioerr!(Other, "not a regular file");
+                }
+                if dir.entries()?.any(|entry| entry.name() != "." && entry.name() != "..") {
+                    return ioerr!(Other, "directory not empty");
+                }
+                (dir.start_cluster, dir.location)
+            },
+        };
+
+        self.vfat.lock(|vfat| {
+            delete_entry(vfat, &location)?;
+            if start_cluster.cluster_id() != 0 {
+                vfat.free_chain(start_cluster)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Moves the entry named `name` in `self` into `new_dir` under the name
+    /// `new_name`. An existing entry named `new_name` in `new_dir` is
+    /// replaced if it is of the same kind, and, if it is a directory, empty.
+    /// The `..` entry of a directory moved to another parent is updated.
+    ///
+    /// The entry is written at its new place before it is removed from its
+    /// old one, and a replaced entry is only freed once nothing is lost if
+    /// the move fails.
+    fn rename_entry(&self, name: &str, new_dir: &Dir<HANDLE>, new_name: &str) -> io::Result<()> {
+        if name == "." || name == ".." {
+            return ioerr!(InvalidInput, "Dir::rename_entry: cannot rename `.` or `..`");
+        }
+        check_name(new_name)?;
+        let (start_cluster, location, is_dir) = match self.find(name)? {
+            Entry::File(file) => (file.start_cluster, file.location, false),
+            Entry::Dir(dir) => (dir.start_cluster, dir.location, true),
+        };
+        if is_dir {
+            new_dir.check_not_within(start_cluster)?;
+        }
+
+        // the entry found under `new_name`, which is the moved entry itself
+        // when only the case of its name changes
+        let replaced = match new_dir.find(new_name) {
+            Ok(Entry::File(file)) => {
+                if is_dir {
+                    return ioerr!(Other, "not a directory");
+                }
+                Some((file.start_cluster, file.location, file.name))
+            },
+            Ok(Entry::Dir(dir)) => {
+                if !is_dir {
+                    return ioerr!(Other, "not a regular file");
+                }
+                if dir.location != location
+                    && dir.entries()?.any(|entry| entry.name() != "." && entry.name() != "..")
+                {
+                    return ioerr!(Other, "directory not empty");
+                }
+                Some((dir.start_cluster, dir.location, dir.name))
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+
+        self.vfat.lock(|vfat| {
+            let regular_entry = read_regular_entry(vfat, &location)?;
+            let (new_location, stored_name) = match replaced {
+                Some((_, replaced_location, ref stored_name)) if replaced_location == location => {
+                    (location, stored_name.as_str())
+                },
+                Some((replaced_cluster, replaced_location, ref stored_name)) => {
+                    // take over the regular entry of the replaced one, which
+                    // keeps its name and LFN entries
+                    let replaced_entry = read_regular_entry(vfat, &replaced_location)?;
+                    let mut new_entry = regular_entry;
+                    new_entry.name = replaced_entry.name;
+                    new_entry.extension = replaced_entry.extension;
+                    new_entry._1 = replaced_entry._1;
+                    write_regular_entry(vfat, &replaced_location, &new_entry)?;
+                    delete_entry(vfat, &location)?;
+                    if replaced_cluster.cluster_id() != 0 {
+                        vfat.free_chain(replaced_cluster)?;
+                    }
+                    (replaced_location, stored_name.as_str())
+                },
+                None => {
+                    let new_location = new_dir.insert_entry(vfat, new_name, regular_entry)?;
+                    delete_entry(vfat, &location)?;
+                    (new_location, new_name)
+                },
+            };
+            // the name found may differ from `new_name` in case
+            if stored_name != new_name {
+                new_dir.rename_in_place(vfat, &new_location, new_name)?;
+            }
+
+            if is_dir && new_dir.start_cluster != self.start_cluster {
+                let dot_dot = EntryLocation {
+                    dir_cluster: start_cluster,
+                    start_offset: size_of::<VFatDirEntry>() as u64,
+                    offset: size_of::<VFatDirEntry>() as u64,
+                };
+                update_regular_entry(vfat, &dot_dot, new_dir.parent_cluster_id(vfat), 0)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Stores the entry at `location` in `self` under `new_name` instead of
+    /// its current name, which matches `new_name` but for its case.
+    fn rename_in_place(&self, vfat: &mut VFat<HANDLE>, location: &EntryLocation, new_name: &str) -> io::Result<()> {
+        let mut regular_entry = read_regular_entry(vfat, location)?;
+        if exact_short_name(new_name) == Some(regular_entry.short_name()) {
+            // the short name alone holds `new_name`: drop the LFN entries
+            regular_entry.set_short_name(regular_entry.short_name());
+            write_regular_entry(vfat, location, &regular_entry)?;
+            if location.start_offset < location.offset {
+                let lfn_location = EntryLocation {
+                    offset: location.offset - size_of::<VFatDirEntry>() as u64,
+                    ..*location
+                };
+                delete_entry(vfat, &lfn_location)?;
+            }
+            Ok(())
+        } else {
+            self.insert_entry(vfat, new_name, regular_entry)?;
+            delete_entry(vfat, location)
+        }
+    }
+
+    /// Returns an error of `InvalidInput` if `self` is the directory starting
+    /// at `cluster` or lies below it.
+    fn check_not_within(&self, cluster: Cluster) -> io::Result<()> {
+        self.vfat.lock(|vfat| {
+            let mut cur = self.start_cluster;
+            loop {
+                if cur == cluster {
+                    return ioerr!(InvalidInput, "cannot move a directory into itself");
+                }
+                if cur == vfat.rootdir_cluster() {
+                    return Ok(());
+                }
+                // follow the `..` entry, the second entry of every directory
+                let dot_dot = EntryLocation {
+                    dir_cluster: cur,
+                    start_offset: size_of::<VFatDirEntry>() as u64,
+                    offset: size_of::<VFatDirEntry>() as u64,
+                };
+                cur = match read_regular_entry(vfat, &dot_dot)?.cluster() {
+                    parent if parent.cluster_id() == 0 => vfat.rootdir_cluster(),
+                    parent => parent,
+                };
+            }
+        })
+    }
+
+    /// Reads all of the raw directory entries of `self`.
+    fn read_dir_entries(&self, vfat: &mut VFat<HANDLE>) -> io::Result<Vec<VFatDirEntry>> {
+        let mut buf: Vec<u8> = vec![];
+        vfat.read_chain(self.start_cluster, &mut buf)?;
+        Ok(unsafe { VecExt::cast(buf) })
+    }
+
     pub fn root_dir(vfat: HANDLE) -> Self {
         let root_dir_cluster = vfat.lock(|vfat| vfat.rootdir_cluster());
         Dir {
@@ -117,6 +429,7 @@ impl<HANDLE: VFatHandle> Dir<HANDLE> {
             start_cluster: root_dir_cluster,
             metadata: Default::default(),
             name: "/".into(),
+            location: Default::default(),
         }
     }
 
@@ -128,51 +441,149 @@ impl<HANDLE: VFatHandle> traits::Dir for Dir<HANDLE> {
     type Iter = DirIter<HANDLE>;
 
     fn entries(&self) -> io::Result<DirIter<HANDLE>> {
-        let mut buf: Vec<u8> = vec![];
         self.vfat.lock(|vfat| {
-            vfat.read_chain(self.start_cluster, &mut buf)?;
-            let buf: Vec<VFatDirEntry> = unsafe { VecExt::cast(buf) };
+            let buf = self.read_dir_entries(vfat)?;
             Ok(DirIter {
                 vfat: self.vfat.clone(),
+                dir_cluster: self.start_cluster,
                 dir_entry_buf: buf,
                 expect_index: 0,
             })
         })
     }
+
+    fn create_file(&self, name: &str) -> io::Result<Entry<HANDLE>> {
+        self.create_entry(name, VFatRegularDirEntry::ATTR_ARCHIVE_FLAG)
+    }
+
+    fn create_dir(&self, name: &str) -> io::Result<Entry<HANDLE>> {
+        self.create_entry(name, VFatRegularDirEntry::ATTR_DIRECTORY_FLAG)
+    }
+
+    fn remove_file(&self, name: &str) -> io::Result<()> {
+        self.remove_entry(name, false)
+    }
+
+    fn remove_dir(&self, name: &str) -> io::Result<()> {
+        self.remove_entry(name, true)
+    }
+
+    fn rename(&self, name: &str, new_dir: &Self, new_name: &str) -> io::Result<()> {
+        self.rename_entry(name, new_dir, new_name)
+    }
 }
 
 impl VFatDirEntry {
     const ATTR_LFN_FLAG: u8 = 0x0F;
-    const ID_UNUSED_ENTRY: u8 = 0xE5;
+    pub(crate) const ID_UNUSED_ENTRY: u8 = 0xE5;
     const ID_LAST_ENTRY: u8 = 0;
 
     fn to_unknown(&self) -> VFatUnknownDirEntry {
         unsafe { self.unknown }
     }
 
-    fn to_wrap_entry(&self) -> VFatWrapEntry {
+    pub(crate) fn to_wrap_entry(&self) -> VFatWrapEntry {
         match self.to_unknown().attributes {
             Self::ATTR_LFN_FLAG => VFatWrapEntry::LongFilename(unsafe { self.long_filename }),
             _ => VFatWrapEntry::Reguler(unsafe { self.regular }),
         }
     }
 
-    fn is_last_entry(&self) -> bool {
+    pub(crate) fn is_last_entry(&self) -> bool {
         self.to_unknown().id == Self::ID_LAST_ENTRY
     }
 
-    fn is_unused_entry(&self) -> bool {
+    pub(crate) fn is_unused_entry(&self) -> bool {
         self.to_unknown().id == Self::ID_UNUSED_ENTRY
     }
+
+    fn to_bytes(&self) -> [u8; 32] {
+        unsafe { mem::transmute(self.unknown) }
+    }
 }
 
 impl VFatRegularDirEntry {
+    pub(crate) const ATTR_VOLUME_ID_FLAG: u8 = 0x08;
     const ATTR_DIRECTORY_FLAG: u8 = 0x10;
+    const ATTR_ARCHIVE_FLAG: u8 = 0x20;
+    const ID_E5_ESCAPE: u8 = 0x05;
+
+    fn new(short_name: [u8; 11], attributes: u8, cluster: Cluster) -> Self {
+        let mut regular_entry: VFatRegularDirEntry = unsafe { mem::zeroed() };
+        regular_entry.set_short_name(short_name);
+        regular_entry.attributes = attributes;
+        regular_entry.set_cluster(cluster);
+        regular_entry
+    }
+
+    pub(crate) fn short_name(&self) -> [u8; 11] {
+        let mut short_name = [0u8; 11];
+        short_name[..8].copy_from_slice(&self.name);
+        short_name[8..].copy_from_slice(&self.extension);
+        short_name
+    }
+
+    fn set_short_name(&mut self, short_name: [u8; 11]) {
+        self.name.copy_from_slice(&short_name[..8]);
+        self.extension.copy_from_slice(&short_name[8..]);
+        // the case flags describe the previous short name
+        self._1 = 0;
+    }
 
-    fn is_directory(&self) -> bool {
+    fn to_bytes(&self) -> [u8; 32] {
+        unsafe { mem::transmute(*self) }
+    }
+
+    /// Records `now` as the creation, modification and access time.
+    fn set_created(&mut self, now: Timestamp, hundredths: u8) {
+        self.created_date = now.date;
+        self.created_time = now.time;
+        self.created_hundredths = hundredths;
+        self.set_modified(now);
+    }
+
+    /// Records `now` as the modification time and the access date.
+    fn set_modified(&mut self, now: Timestamp) {
+        self.modified_date = now.date;
+        self.modified_time = now.time;
+        self.accessed_date = now.date;
+    }
+
+    /// Returns the name stored in the short name, decoded from code page
+    /// 437.
+    pub(crate) fn short_name_string(&self) -> String {
+        let mut base = self.name;
+        // a name starting with 0xE5 stores 0x05 instead
+        if base[0] == Self::ID_E5_ESCAPE {
+            base[0] = VFatDirEntry::ID_UNUSED_ENTRY;
+        }
+        let base = parse_str_from_byte(&base);
+        let extension = parse_str_from_byte(&{ self.extension });
+        if extension.is_empty() {
+            base
+        } else {
+            format!("{}.{}", base, extension)
+        }
+    }
+
+    pub(crate) fn is_volume_id(&self) -> bool {
+        (self.attributes & Self::ATTR_VOLUME_ID_FLAG) != 0
+    }
+
+    pub(crate) fn is_directory(&self) -> bool {
         (self.attributes & Self::ATTR_DIRECTORY_FLAG) != 0
     }
 
+    pub(crate) fn cluster(&self) -> Cluster {
+        get_u32_from_u16(self.cluster_id_hi, self.cluster_id_lo).into()
+    }
+
+    fn set_cluster(&mut self, cluster: Cluster) {
+        let cluster_id = cluster.cluster_id() as u32;
+        self.cluster_id_hi = (cluster_id >> 16) as u16;
+        self.cluster_id_lo = cluster_id as u16;
+    }
+
     fn metadata(&self) -> Metadata {
         Metadata {
           attributes: self.attributes.into(),
@@ -180,6 +591,7 @@ impl VFatRegularDirEntry {
               date: self.created_date.into(),
               time: self.created_time.into(),
           },
+          created_hundredths: self.created_hundredths,
           accessed_timestamp: Timestamp {
               date: self.accessed_date.into(),
               ..Default::default()
@@ -195,35 +607,75 @@ impl VFatRegularDirEntry {
 impl VFatLfnDirEntry {
     const NAME_END_FLAG1: u16 = 0;
     const NAME_END_FLAG2: u16 = 0xFFFF;
-    fn extract_name(&self) -> String {
-        let mut u16_vec: Vec<u16> = vec![];
-        unsafe {
-            u16_vec.extend_from_slice(&self.name_1);
-            u16_vec.extend_from_slice(&self.name_2);
-            u16_vec.extend_from_slice(&self.name_3);
+    const NAME_LEN: usize = 13;
+    pub(crate) const LAST_SEQUENCE_FLAG: u8 = 0x40;
+
+    /// Returns the LFN entries storing `name` in on-disk order, i.e. the
+    /// entry with the last part of the name comes first.
+    fn sequence(name: &str, checksum: u8) -> Vec<VFatDirEntry> {
+        let mut name: Vec<u16> = name.encode_utf16().collect();
+        // the name is terminated by 0 and padded with 0xFFFF
+        if name.len() % Self::NAME_LEN != 0 {
+            name.push(Self::NAME_END_FLAG1);
         }
-        for (i, ch) in u16_vec.as_slice().iter().enumerate() {
-            if *ch == Self::NAME_END_FLAG1
-                || *ch == Self::NAME_END_FLAG2 {
-                    return Self::decode_u16(&u16_vec[..i]);
-            }
+        while name.len() % Self::NAME_LEN != 0 {
+            name.push(Self::NAME_END_FLAG2);
         }
-        return Self::decode_u16(&u16_vec);
+
+        let count = name.len() / Self::NAME_LEN;
+        name.chunks(Self::NAME_LEN).enumerate().rev().map(|(i, part)| {
+            let mut lfn_entry: VFatLfnDirEntry = unsafe { mem::zeroed() };
+            lfn_entry.sequence_num = (i + 1) as u8;
+            if i + 1 == count {
+                lfn_entry.sequence_num |= Self::LAST_SEQUENCE_FLAG;
+            }
+            lfn_entry.attributes = VFatDirEntry::ATTR_LFN_FLAG;
+            lfn_entry.checksum = checksum;
+            let (mut name_1, mut name_2, mut name_3) = ([0u16; 5], [0u16; 6], [0u16; 2]);
+            name_1.copy_from_slice(&part[..5]);
+            name_2.copy_from_slice(&part[5..11]);
+            name_3.copy_from_slice(&part[11..]);
+            lfn_entry.name_1 = name_1;
+            lfn_entry.name_2 = name_2;
+            lfn_entry.name_3 = name_3;
+            VFatDirEntry { long_filename: lfn_entry }
+        }).collect()
     }
 
-    fn decode_u16(buf: &[u16]) -> String {
-        char::decode_utf16(buf.iter().cloned())
-            .map(|r| r.expect("VFatLfnDirEntry::extract_name: failed to decode u16"))
-            .collect::<String>()
+    /// Returns the UTF-16 code units of the part of the name stored in the
+    /// entry. A surrogate pair may be split across two entries, so the parts
+    /// are only decoded once joined.
+    pub(crate) fn name_units(&self) -> Vec<u16> {
+        let mut units: Vec<u16> = vec![];
+        units.extend_from_slice(&{ self.name_1 });
+        units.extend_from_slice(&{ self.name_2 });
+        units.extend_from_slice(&{ self.name_3 });
+        if let Some(end) = units.iter().position(|&unit| unit == Self::NAME_END_FLAG1 || unit == Self::NAME_END_FLAG2) {
+            units.truncate(end);
+        }
+        units
     }
 }
 
+/// An iterator over the entries of a directory as they were when it was
+/// created by `Dir::entries()`.
 pub struct DirIter<HANDLE: VFatHandle> {
     vfat: HANDLE,
+    dir_cluster: Cluster,
     dir_entry_buf: Vec<VFatDirEntry>,
     expect_index: usize,
 }
 
+impl<HANDLE: VFatHandle> fmt::Debug for DirIter<HANDLE> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DirIter")
+            .field("dir_cluster", &self.dir_cluster)
+            .field("entries_num", &self.dir_entry_buf.len())
+            .field("expect_index", &self.expect_index)
+            .finish()
+    }
+}
+
 impl<HANDLE: VFatHandle> Iterator for DirIter<HANDLE> {
     type Item = Entry<HANDLE>;
 
@@ -232,47 +684,56 @@ impl<HANDLE: VFatHandle> Iterator for DirIter<HANDLE> {
             return None;
         }
 
-        let mut name: Vec<String> = vec![];
+        let mut name = String::new();
+        let mut lfn_parts: Vec<Vec<u16>> = vec![];
         let mut metadata: Metadata = Default::default();
         let mut start_cluster: Cluster = 0.into();
         let mut is_directory = false;
         let mut is_lfn = false;
         let mut size = 0;
         let mut n = 0;
+        let mut first_index = None;
+        let mut regular_index = 0;
 
         for dir_entry in self.dir_entry_buf[self.expect_index..].iter() {
             if dir_entry.is_last_entry() { break; }
             n += 1;
             if dir_entry.is_unused_entry() { continue; }
+            if first_index.is_none() {
+                first_index = Some(self.expect_index + n - 1);
+            }
 
             match dir_entry.to_wrap_entry() {
+                VFatWrapEntry::Reguler(regular_entry) if regular_entry.is_volume_id() => {
+                    // the volume label is not an entry of the directory
+                    lfn_parts.clear();
+                    is_lfn = false;
+                    first_index = None;
+                },
                 VFatWrapEntry::Reguler(regular_entry) => {
-                    if !is_lfn {
-                        let extension = parse_str_from_byte(&regular_entry.extension);
-                        name.push(parse_str_from_byte(&regular_entry.name));
-                        if extension.len() > 0 {
-                            name.push(".".into());
-                            name.push(extension);
-                        }
-                    }
+                    name = if is_lfn {
+                        name::decode_utf16_lossy(&lfn_parts.concat())
+                    } else {
+                        regular_entry.short_name_string()
+                    };
                     // entry corresponding start cluster
-                    start_cluster = get_u32_from_u16(regular_entry.cluster_id_hi, regular_entry.cluster_id_lo).into();
+                    start_cluster = regular_entry.cluster();
                     // entry type is directory or file
                     is_directory = regular_entry.is_directory();
                     // entry metadata
                     metadata = regular_entry.metadata();
                     // entry size
                     size = regular_entry.file_size;
+                    regular_index = self.expect_index + n - 1;
                     break;
                 },
                 VFatWrapEntry::LongFilename(lfn_entry) => {
                     is_lfn = true; // indicate lfn
-                    let sequence_num = lfn_entry.sequence_num as usize;
-                    let name_segment = lfn_entry.extract_name();
-                    if name.len() <= sequence_num {
-                        name.resize_with(sequence_num + 1, || "".into());
+                    let sequence_num = (lfn_entry.sequence_num & !VFatLfnDirEntry::LAST_SEQUENCE_FLAG) as usize;
+                    if lfn_parts.len() <= sequence_num {
+                        lfn_parts.resize_with(sequence_num + 1, Vec::new);
                     }
-                    name[sequence_num] = name_segment;
+                    lfn_parts[sequence_num] = lfn_entry.name_units();
                 },
             }
         }
@@ -283,15 +744,22 @@ impl<HANDLE: VFatHandle> Iterator for DirIter<HANDLE> {
             return None;
         }
 
-        // construct final name
-        let name = name.into_iter()
-                              .fold(String::new(), |res, cur| res + &cur);
-    
+        let location = EntryLocation {
+            dir_cluster: self.dir_cluster,
+            start_offset: (first_index.unwrap_or(regular_index) * size_of::<VFatDirEntry>()) as u64,
+            offset: (regular_index * size_of::<VFatDirEntry>()) as u64,
+        };
+
         if is_directory {
+            // a `..` entry pointing to the root directory records cluster 0
+            if start_cluster.cluster_id() == 0 {
+                start_cluster = self.vfat.lock(|vfat| vfat.rootdir_cluster());
+            }
             Some(Entry::Dir(Dir {
                 name,
                 metadata,
                 start_cluster,
+                location,
                 vfat: self.vfat.clone(),
             }))
         } else {
@@ -301,23 +769,282 @@ impl<HANDLE: VFatHandle> Iterator for DirIter<HANDLE> {
                 metadata,
                 start_cluster,
                 size: size as u64,
+                location,
+                clusters: Vec::new(),
                 vfat: self.vfat.clone(),
             }))
         }
     }
 }
 
-/// Return string from the first 8 bytes of the entry.
-/// A file name may be terminated early using 0x00 or 0x20 characters.
-fn parse_str_from_byte(buf: &[u8]) -> String {
-    let mut end = 0;
-    for byte in buf.iter() {
-        if *byte == 0x0 || *byte == 0x20 {
-            break;
+/// Reads the regular directory entry at `location`.
+fn read_regular_entry<HANDLE: VFatHandle>(
+    vfat: &mut VFat<HANDLE>,
+    location: &EntryLocation,
+) -> io::Result<VFatRegularDirEntry> {
+    let mut buf = [0u8; size_of::<VFatRegularDirEntry>()];
+    vfat.read_cluster(location.dir_cluster, location.offset as usize, &mut buf)?;
+    Ok(unsafe { mem::transmute(buf) })
+}
+
+/// Marks the regular directory entry at `location` and all of its LFN entries
+/// as deleted.
+pub(crate) fn delete_entry<HANDLE: VFatHandle>(vfat: &mut VFat<HANDLE>, location: &EntryLocation) -> io::Result<()> {
+    let entry_size = size_of::<VFatDirEntry>();
+    for offset in (location.start_offset..=location.offset).step_by(entry_size) {
+        vfat.write_cluster(location.dir_cluster, offset as usize, &[VFatDirEntry::ID_UNUSED_ENTRY])?;
+    }
+    Ok(())
+}
+
+/// Rewrites the start cluster and the size recorded in the regular directory
+/// entry at `location`.
+pub(crate) fn update_regular_entry<HANDLE: VFatHandle>(
+    vfat: &mut VFat<HANDLE>,
+    location: &EntryLocation,
+    start_cluster: Cluster,
+    size: u32,
+) -> io::Result<()> {
+    let mut regular_entry = read_regular_entry(vfat, location)?;
+    regular_entry.set_cluster(start_cluster);
+    regular_entry.file_size = size;
+    write_regular_entry(vfat, location, &regular_entry)
+}
+
+/// Writes `regular_entry` as the regular directory entry at `location`.
+fn write_regular_entry<HANDLE: VFatHandle>(
+    vfat: &mut VFat<HANDLE>,
+    location: &EntryLocation,
+    regular_entry: &VFatRegularDirEntry,
+) -> io::Result<()> {
+    vfat.write_cluster(location.dir_cluster, location.offset as usize, &regular_entry.to_bytes())?;
+    Ok(())
+}
+
+/// Writes `new_entries` to the first run of free slots in `dir_entries`
+/// that fits them, the raw entries of the directory starting at
+/// `dir_cluster`, and returns the index of the first one. The directory is
+/// grown by zeroed clusters if no such run exists.
+fn place_entries<HANDLE: VFatHandle>(
+    vfat: &mut VFat<HANDLE>,
+    dir_cluster: Cluster,
+    dir_entries: &[VFatDirEntry],
+    new_entries: &[VFatDirEntry],
+) -> io::Result<usize> {
+    let index = find_free_slots(dir_entries, new_entries.len());
+    let end = (index + new_entries.len()) * size_of::<VFatDirEntry>();
+    let chain_size = dir_entries.len() * size_of::<VFatDirEntry>();
+    if end > chain_size {
+        let cluster_size = vfat.bytes_per_cluster() as usize;
+        let grow_size = (end - chain_size + cluster_size - 1) / cluster_size * cluster_size;
+        vfat.write_cluster(dir_cluster, chain_size, &vec![0u8; grow_size])?;
+    }
+    let mut buf = Vec::with_capacity(new_entries.len() * size_of::<VFatDirEntry>());
+    for new_entry in new_entries.iter() {
+        buf.extend_from_slice(&new_entry.to_bytes());
+    }
+    vfat.write_cluster(dir_cluster, index * size_of::<VFatDirEntry>(), &buf)?;
+    Ok(index)
+}
+
+/// Returns the index of the volume-ID entry of the root directory and the
+/// raw entries of the root directory.
+fn find_volume_id_entry<HANDLE: VFatHandle>(vfat: &mut VFat<HANDLE>) -> io::Result<(Option<usize>, Vec<VFatDirEntry>)> {
+    let mut buf: Vec<u8> = vec![];
+    vfat.read_chain(vfat.rootdir_cluster(), &mut buf)?;
+    let dir_entries: Vec<VFatDirEntry> = unsafe { VecExt::cast(buf) };
+    let index = dir_entries.iter()
+        .take_while(|dir_entry| !dir_entry.is_last_entry())
+        .position(|dir_entry| !dir_entry.is_unused_entry() && match dir_entry.to_wrap_entry() {
+            VFatWrapEntry::Reguler(regular_entry) => regular_entry.is_volume_id(),
+            VFatWrapEntry::LongFilename(_) => false,
+        });
+    Ok((index, dir_entries))
+}
+
+/// Returns the padded volume label recorded in the root directory, if any.
+pub(crate) fn read_volume_label<HANDLE: VFatHandle>(vfat: &mut VFat<HANDLE>) -> io::Result<Option<[u8; 11]>> {
+    let (index, dir_entries) = find_volume_id_entry(vfat)?;
+    Ok(index.map(|index| unsafe { dir_entries[index].regular }.short_name()))
+}
+
+/// Records the padded volume label `label` in the volume-ID entry of the
+/// root directory, creating the entry if there is none. If `label` is
+/// `None`, the entry is deleted.
+pub(crate) fn write_volume_label<HANDLE: VFatHandle>(
+    vfat: &mut VFat<HANDLE>,
+    label: Option<[u8; 11]>,
+) -> io::Result<()> {
+    let rootdir_cluster = vfat.rootdir_cluster();
+    let (index, dir_entries) = find_volume_id_entry(vfat)?;
+    let entry_size = size_of::<VFatDirEntry>();
+    match (index, label) {
+        (Some(index), Some(label)) => {
+            vfat.write_cluster(rootdir_cluster, index * entry_size, &label)?;
+        },
+        (Some(index), None) => {
+            vfat.write_cluster(rootdir_cluster, index * entry_size, &[VFatDirEntry::ID_UNUSED_ENTRY])?;
+        },
+        (None, Some(label)) => {
+            let regular_entry = VFatRegularDirEntry::new(label, VFatRegularDirEntry::ATTR_VOLUME_ID_FLAG, Cluster::from(0));
+            place_entries(vfat, rootdir_cluster, &dir_entries, &[VFatDirEntry { regular: regular_entry }])?;
+        },
+        (None, None) => {},
+    }
+    Ok(())
+}
+
+/// Records `now` as the access date of the regular directory entry at
+/// `location`, and as its modification time too if `modified` is set.
+/// Returns the updated metadata of the entry.
+pub(crate) fn stamp_regular_entry<HANDLE: VFatHandle>(
+    vfat: &mut VFat<HANDLE>,
+    location: &EntryLocation,
+    now: Timestamp,
+    modified: bool,
+) -> io::Result<Metadata> {
+    let mut regular_entry = read_regular_entry(vfat, location)?;
+    if modified {
+        regular_entry.set_modified(now);
+    } else {
+        regular_entry.accessed_date = now.date;
+    }
+    vfat.write_cluster(location.dir_cluster, location.offset as usize, &regular_entry.to_bytes())?;
+    Ok(regular_entry.metadata())
+}
+
+/// Characters that are not allowed anywhere in a long file name.
+const INVALID_NAME_CHARS: &str = "\"*/:<>?\\|";
+
+/// Special characters that are allowed in a short (8.3) file name.
+const SHORT_NAME_SPECIAL_CHARS: &[u8] = b"!#$%&'()-@^_`{}~";
+
+/// Checks that `name` can be stored as the name of a directory entry.
+fn check_name(name: &str) -> io::Result<()> {
+    if name.is_empty() || name == "." || name == ".." {
+        ioerr!(InvalidInput, "invalid file name")
+    } else if name.encode_utf16().count() > 255 {
+        ioerr!(InvalidInput, "file name is too long")
+    } else if name.chars().any(|c| c < ' ' || INVALID_NAME_CHARS.contains(c)) {
+        ioerr!(InvalidInput, "file name contains invalid characters")
+    } else {
+        Ok(())
+    }
+}
+
+fn is_short_name_char(c: char) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit()
+        || (c.is_ascii() && SHORT_NAME_SPECIAL_CHARS.contains(&(c as u8)))
+}
+
+/// Returns the padded volume label for `label`, or `None` if `label` is
+/// empty. Lowercase letters are converted to uppercase.
+pub(crate) fn volume_label_from_str(label: &str) -> io::Result<Option<[u8; 11]>> {
+    let label = label.trim_end_matches(' ').to_ascii_uppercase();
+    if label.is_empty() {
+        return Ok(None);
+    } else if label.len() > 11 {
+        return ioerr!(InvalidInput, "volume label is too long");
+    } else if label.starts_with(' ') || !label.chars().all(|c| c == ' ' || is_short_name_char(c)) {
+        return ioerr!(InvalidInput, "volume label contains invalid characters");
+    }
+    let mut padded = [b' '; 11];
+    padded[..label.len()].copy_from_slice(label.as_bytes());
+    Ok(Some(padded))
+}
+
+/// Splits a name into its base name and its extension.
+fn split_extension(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(i) if i > 0 => (&name[..i], &name[i + 1..]),
+        _ => (name, ""),
+    }
+}
+
+/// Returns the padded 8.3 short name of `name` if `name` is a valid short
+/// name on its own.
+fn exact_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, extension) = split_extension(name);
+    if base.is_empty() || base.len() > 8 || extension.len() > 3
+        || !base.chars().chain(extension.chars()).all(is_short_name_char) {
+        return None;
+    }
+    let mut short_name = [b' '; 11];
+    short_name[..base.len()].copy_from_slice(base.as_bytes());
+    short_name[8..8 + extension.len()].copy_from_slice(extension.as_bytes());
+    Some(short_name)
+}
+
+/// Generates a short name for `name` with a `~N` numeric tail that collides
+/// with none of `short_names`.
+fn generate_short_name(name: &str, short_names: &[[u8; 11]]) -> io::Result<[u8; 11]> {
+    fn to_short_chars(s: &str) -> Vec<u8> {
+        s.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(name::to_uppercase)
+            .map(|c| match name::encode_cp437(c) {
+                Some(byte) if byte >= 0x80 || is_short_name_char(c) => byte,
+                _ => b'_',
+            })
+            .collect()
+    }
+
+    let (base, extension) = split_extension(name);
+    let base = to_short_chars(base);
+    let extension = to_short_chars(extension);
+
+    let mut short_name = [b' '; 11];
+    let extension_len = extension.len().min(3);
+    short_name[8..8 + extension_len].copy_from_slice(&extension[..extension_len]);
+    for n in 1..1_000_000 {
+        let tail = format!("~{}", n);
+        let base_len = base.len().min(8 - tail.len());
+        short_name[..8].copy_from_slice(b"        ");
+        short_name[..base_len].copy_from_slice(&base[..base_len]);
+        short_name[base_len..base_len + tail.len()].copy_from_slice(tail.as_bytes());
+        if short_name[0] == VFatDirEntry::ID_UNUSED_ENTRY {
+            short_name[0] = VFatRegularDirEntry::ID_E5_ESCAPE;
+        }
+        if !short_names.contains(&short_name) {
+            return Ok(short_name);
+        }
+    }
+    ioerr!(AlreadyExists, "generate_short_name: no unique short name left")
+}
+
+/// Returns the checksum of a short name stored in each of its LFN entries.
+pub(crate) fn lfn_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name.iter().fold(0u8, |sum, &byte| {
+        ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(byte)
+    })
+}
+
+/// Returns the index of the first run of `count` free slots in `dir_entries`.
+/// The returned run may extend past the end of `dir_entries`.
+fn find_free_slots(dir_entries: &[VFatDirEntry], count: usize) -> usize {
+    let mut run = 0;
+    for (i, dir_entry) in dir_entries.iter().enumerate() {
+        if dir_entry.is_last_entry() {
+            // every slot after the last entry is free
+            return i - run;
+        } else if dir_entry.is_unused_entry() {
+            run += 1;
+            if run == count {
+                return i + 1 - count;
+            }
+        } else {
+            run = 0;
         }
-        end += 1;
     }
-    str::from_utf8(&buf[..end]).expect("parse_str_from_byte: failed to parse utf8").into()
+    dir_entries.len() - run
+}
+
+/// Return string from the name or the extension of a short name, decoded
+/// from code page 437. A part may be terminated early using 0x00 or 0x20
+/// characters.
+pub(crate) fn parse_str_from_byte(buf: &[u8]) -> String {
+    let end = buf.iter().position(|&byte| byte == 0x0 || byte == 0x20).unwrap_or(buf.len());
+    name::decode_cp437(&buf[..end])
 }
 
 #[inline(always)]
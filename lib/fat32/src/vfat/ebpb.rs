@@ -7,21 +7,34 @@ use crate::vfat::Error;
 #[repr(C, packed)]
 pub struct BiosParameterBlock {
     // FIXME: Fill me in.
-    _1: [u8; 11],
+    pub(crate) jump: [u8; 3],
+    pub(crate) oem_name: [u8; 8],
     pub bytes_per_sector: u16, // bytes per logical sector
     pub sectors_per_cluster: u8,
     pub reserved_sectors_num: u16, // offset from partition start to FAT
     pub fat_num: u8,
-    _2: [u8; 2],
+    pub(crate) max_rootdir_entries: u16, // 0 for FAT32
     sectors_num_1: u16,
-    _3: u8,
+    pub(crate) media_descriptor: u8,
     pub sectors_per_fat_1: u16,
-    _4: [u8; 8],
+    pub(crate) sectors_per_track: u16,
+    pub(crate) heads_num: u16,
+    pub(crate) hidden_sectors: u32, // sectors preceding the partition
     sectors_num_2: u32,
     pub sectors_per_fat_2: u32,
-    _5: [u8; 4],
+    pub(crate) ext_flags: u16,
+    pub(crate) version: u16,
     pub rootdir_cluster: u32,
-    _6: [u8; 462],
+    pub fsinfo_sector: u16, // logical sector of the FSInfo structure
+    pub(crate) backup_boot_sector: u16,
+    _1: [u8; 12],
+    pub(crate) drive_num: u8,
+    _2: u8,
+    pub(crate) ext_signature: u8, // 0x29 if the three fields below are valid
+    pub(crate) volume_id: u32,
+    pub(crate) volume_label: [u8; 11],
+    pub(crate) fs_type: [u8; 8],
+    _3: [u8; 420],
     magic: [u8; 2],
 }
 
@@ -35,6 +48,15 @@ impl Default for BiosParameterBlock {
 }
 
 const MAGIC: [u8; 2] = [0x55, 0xAA];
+/// Value of `ext_signature` if the serial number, label and file system type
+/// fields are valid.
+const EXT_SIGNATURE: u8 = 0x29;
+/// Offset of the drive number field in the EBPB of FAT12/16 and FAT32
+/// volumes. The fields up to the file system type follow it in both.
+const FAT16_EXT_OFFSET: usize = 36;
+const FAT32_EXT_OFFSET: usize = 64;
+/// The label of a volume without one.
+pub(crate) const NO_LABEL: [u8; 11] = *b"NO NAME    ";
 
 impl BiosParameterBlock {
     /// Reads the FAT32 extended BIOS parameter block from sector `sector` of
@@ -46,13 +68,106 @@ impl BiosParameterBlock {
     pub fn from<T: BlockDevice>(mut device: T, sector: u64) -> Result<BiosParameterBlock, Error> {
         let mut buf = [0u8; 512];
         device.read_sector(sector, &mut buf)?;
-        let ebpb = unsafe { mem::transmute::<[u8; 512], BiosParameterBlock>(buf) };
+        let ebpb = BiosParameterBlock::from_bytes(&buf);
         if MAGIC != ebpb.magic {
             Err(Error::BadSignature)
         } else {
             Ok(ebpb)
         }
     }
+
+    /// Parses the EBPB at the start of the sector `sector` without checking
+    /// its signature.
+    pub(crate) fn from_bytes(sector: &[u8]) -> BiosParameterBlock {
+        let mut buf = [0u8; 512];
+        buf.copy_from_slice(&sector[..512]);
+        unsafe { mem::transmute::<[u8; 512], BiosParameterBlock>(buf) }
+    }
+
+    /// Returns the total number of logical sectors in the volume.
+    pub fn total_sectors(&self) -> u32 {
+        match self.sectors_num_1 {
+            0 => self.sectors_num_2,
+            num => num as u32,
+        }
+    }
+
+    /// Returns the number of sectors per FAT. FAT12/16 volumes store it in
+    /// the BPB, FAT32 volumes in the extended BPB.
+    pub fn sectors_per_fat(&self) -> u32 {
+        match self.sectors_per_fat_1 {
+            0 => self.sectors_per_fat_2,
+            num => num as u32,
+        }
+    }
+
+    /// Sets the total number of logical sectors in the volume.
+    pub(crate) fn set_total_sectors(&mut self, sectors_num: u32) {
+        self.sectors_num_1 = 0;
+        self.sectors_num_2 = sectors_num;
+    }
+
+    /// Returns the OEM name of the formatting tool.
+    pub fn oem_name(&self) -> [u8; 8] {
+        self.oem_name
+    }
+
+    /// Returns the offset of the extended fields, which FAT12/16 volumes
+    /// keep where FAT32 volumes keep their sectors per FAT. A FAT32 volume
+    /// has no 16-bit sectors per FAT.
+    fn ext_offset(&self) -> usize {
+        match self.sectors_per_fat_1 {
+            0 => FAT32_EXT_OFFSET,
+            _ => FAT16_EXT_OFFSET,
+        }
+    }
+
+    /// Returns the volume serial number, or `None` if the EBPB does not
+    /// record one.
+    pub fn volume_id(&self) -> Option<u32> {
+        let bytes = self.to_bytes();
+        let offset = self.ext_offset();
+        if bytes[offset + 2] != EXT_SIGNATURE {
+            return None;
+        }
+        let mut volume_id = [0u8; 4];
+        volume_id.copy_from_slice(&bytes[offset + 3..offset + 7]);
+        Some(u32::from_le_bytes(volume_id))
+    }
+
+    /// Returns the padded volume label, or `None` if the EBPB does not record
+    /// one. A volume without a label records `NO NAME`.
+    pub fn volume_label(&self) -> Option<[u8; 11]> {
+        let bytes = self.to_bytes();
+        let offset = self.ext_offset();
+        if bytes[offset + 2] != EXT_SIGNATURE {
+            return None;
+        }
+        let mut label = [0u8; 11];
+        label.copy_from_slice(&bytes[offset + 7..offset + 18]);
+        Some(label)
+    }
+
+    /// Sets the padded volume label. The label is only written if the EBPB
+    /// has the extended fields, and `false` is returned otherwise.
+    pub(crate) fn set_volume_label(&mut self, label: [u8; 11]) -> bool {
+        let mut bytes = self.to_bytes();
+        let offset = self.ext_offset();
+        if bytes[offset + 2] != EXT_SIGNATURE {
+            return false;
+        }
+        bytes[offset + 7..offset + 18].copy_from_slice(&label);
+        *self = unsafe { mem::transmute::<[u8; 512], BiosParameterBlock>(bytes) };
+        true
+    }
+
+    /// Returns the on-disk representation of the EBPB, including its
+    /// signature.
+    pub fn to_bytes(&self) -> [u8; 512] {
+        let mut buf: [u8; 512] = unsafe { mem::transmute_copy(self) };
+        buf[510..].copy_from_slice(&MAGIC);
+        buf
+    }
 }
 
 impl fmt::Debug for BiosParameterBlock {
@@ -65,6 +180,7 @@ impl fmt::Debug for BiosParameterBlock {
          .field("sectors num", &{ self.sectors_num_2 })
          .field("sectors per fat", &{ self.sectors_per_fat_2 })
          .field("cluster num of root dir", &{ self.rootdir_cluster })
+         .field("fsinfo sector", &{ self.fsinfo_sector })
          .finish()
     }
 }
@@ -1,13 +1,18 @@
 use shim::io;
 
+use crate::gpt;
 use crate::mbr;
 
 #[derive(Debug)]
 pub enum Error {
     Mbr(mbr::Error),
+    Gpt(gpt::Error),
     Io(io::Error),
     BadSignature,
     NotFound,
+    /// The entry of `cluster` in the FAT copy `copy` differs from the one in
+    /// the active FAT.
+    FatMismatch { copy: u8, cluster: u32 },
 }
 
 impl From<mbr::Error> for Error {
@@ -16,6 +21,12 @@ impl From<mbr::Error> for Error {
     }
 }
 
+impl From<gpt::Error> for Error {
+    fn from(error: gpt::Error) -> Error {
+        Error::Gpt(error)
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Error {
         Error::Io(error)
@@ -1,7 +1,7 @@
 use crate::vfat::*;
 use core::fmt;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Status {
     /// The FAT entry corresponds to an unused (free) cluster.
     Free,
@@ -17,26 +17,97 @@ pub enum Status {
     Eoc(u32),
 }
 
-#[repr(C, packed)]
-pub struct FatEntry(pub u32);
+/// The width of the entries of a FAT.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    /// Returns the type of a volume with `clusters_num` data clusters. The
+    /// cluster count alone determines the type of a FAT volume.
+    pub fn from_clusters_num(clusters_num: u32) -> FatType {
+        if clusters_num < 4085 {
+            FatType::Fat12
+        } else if clusters_num < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        }
+    }
+
+    /// Returns the width of an entry in bits.
+    pub fn bits(&self) -> u64 {
+        match self {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        }
+    }
+
+    /// Returns the bits of an entry that hold its value. The high 4 bits of a
+    /// FAT32 entry are reserved.
+    pub fn mask(&self) -> u32 {
+        match self {
+            FatType::Fat12 => 0xFFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFFFFFF,
+        }
+    }
+
+    /// Returns the bits of FAT[1] that are set while the volume is cleanly
+    /// unmounted and while no disk error has been seen, in that order. FAT12
+    /// has neither.
+    pub(crate) fn volume_state_bits(&self) -> Option<(u32, u32)> {
+        match self {
+            FatType::Fat12 => None,
+            FatType::Fat16 => Some((0x8000, 0x4000)),
+            FatType::Fat32 => Some((0x0800_0000, 0x0400_0000)),
+        }
+    }
+}
+
+/// An entry of a FAT of type `fat_type`. `0` holds the raw value of the
+/// entry, including the reserved high bits of a FAT32 entry.
+#[derive(Copy, Clone)]
+pub struct FatEntry(pub u32, pub FatType);
 
 impl FatEntry {
     /// Returns the `Status` of the FAT entry `self`.
     pub fn status(&self) -> Status {
-        match self.0 & (!(0xF << 28)) {
+        let mask = self.1.mask();
+        match self.0 & mask {
             0 => Status::Free,
-            2..=0x0FFFFFEF => Status::Data(Cluster::from(self.0)),
-            0x0FFFFFF7 => Status::Bad,
-            0x0FFFFFF8..=0x0FFFFFFF => Status::Eoc(self.0),
+            1 => Status::Reserved,
+            value if value < (0x0FFFFFF0 & mask) => Status::Data(Cluster::from(value)),
+            value if value == (0x0FFFFFF7 & mask) => Status::Bad,
+            value if value >= (0x0FFFFFF8 & mask) => Status::Eoc(self.0),
             _ => Status::Reserved,
         }
     }
+
+    /// Overwrites the entry with `status`. The high 4 reserved bits of a
+    /// FAT32 entry are kept.
+    pub fn set_status(&mut self, status: Status) {
+        let value = match status {
+            Status::Free => 0,
+            Status::Reserved => 1,
+            Status::Data(cluster) => cluster.cluster_id() as u32,
+            Status::Bad => 0x0FFFFFF7,
+            Status::Eoc(value) => value,
+        };
+        let mask = self.1.mask();
+        self.0 = (self.0 & !mask) | (value & mask);
+    }
 }
 
 impl fmt::Debug for FatEntry {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("FatEntry")
             .field("value", &{ self.0 })
+            .field("type", &self.1)
             .field("status", &self.status())
             .finish()
     }
@@ -1,10 +1,12 @@
 use alloc::string::String;
+use alloc::vec::Vec;
 
 use shim::io::{self, SeekFrom};
 use shim::ioerr;
 
 use crate::traits;
-use crate::vfat::{Cluster, Metadata, VFatHandle};
+use crate::vfat::dir::{stamp_regular_entry, update_regular_entry, EntryLocation};
+use crate::vfat::{Cluster, Metadata, VFat, VFatHandle};
 
 #[derive(Debug)]
 pub struct File<HANDLE: VFatHandle> {
@@ -15,26 +17,131 @@ pub struct File<HANDLE: VFatHandle> {
     pub name: String,
     pub pos: u64,
     pub size: u64,
+    pub location: EntryLocation,
+    /// The clusters of the file read from its chain so far, in order.
+    pub(crate) clusters: Vec<Cluster>,
+}
+
+impl<HANDLE: VFatHandle> File<HANDLE> {
+    /// Returns the cluster holding byte `offset` of the file, following the
+    /// chain from the last cached cluster only as far as needed. Returns
+    /// `None` if the chain ends before `offset`.
+    fn cluster_at(&mut self, vfat: &mut VFat<HANDLE>, offset: u64) -> io::Result<Option<Cluster>> {
+        let index = (offset / vfat.bytes_per_cluster()) as usize;
+        if self.clusters.is_empty() {
+            if self.start_cluster.cluster_id() == 0 {
+                return Ok(None);
+            }
+            self.clusters.push(self.start_cluster);
+        }
+        while self.clusters.len() <= index {
+            match vfat.next_cluster(self.clusters[self.clusters.len() - 1])? {
+                Some(cluster) => self.clusters.push(cluster),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(self.clusters[index]))
+    }
+
+    /// Writes `buf` at byte `offset` of the file, which must not lie past
+    /// its end, allocating clusters as needed. The size of the file is left
+    /// to the caller to update.
+    fn write_at(&mut self, vfat: &mut VFat<HANDLE>, offset: u64, buf: &[u8]) -> io::Result<usize> {
+        // an empty file owns no cluster yet
+        if self.start_cluster.cluster_id() == 0 {
+            self.start_cluster = vfat.alloc_cluster(None)?;
+            self.clusters.clear();
+        }
+        // start from the cluster at `offset`, or from the last one if the
+        // write grows the chain
+        let cluster_size = vfat.bytes_per_cluster();
+        let (cluster, offset) = match self.cluster_at(vfat, offset)? {
+            Some(cluster) => (cluster, offset % cluster_size),
+            None => {
+                let index = self.clusters.len() - 1;
+                (self.clusters[index], offset - index as u64 * cluster_size)
+            }
+        };
+        vfat.write_cluster(cluster, offset as usize, buf)
+    }
+
+    /// Grows the file to `size` bytes, filling the new bytes with zeros.
+    fn zero_extend(&mut self, vfat: &mut VFat<HANDLE>, size: u64) -> io::Result<()> {
+        let zeros = vec![0u8; vfat.bytes_per_cluster() as usize];
+        while self.size < size {
+            let len = (size - self.size).min(zeros.len() as u64) as usize;
+            let offset = self.size;
+            self.size += self.write_at(vfat, offset, &zeros[..len])? as u64;
+        }
+        Ok(())
+    }
+
+    /// Records the current time of the clock of `vfat`, if it has one, as the
+    /// access date of the file, and as its modification time if `modified`
+    /// is set. The directory entry is only rewritten if it changes.
+    fn stamp(&mut self, vfat: &mut VFat<HANDLE>, modified: bool) -> io::Result<()> {
+        if let Some((now, _)) = vfat.now() {
+            if modified || self.metadata.accessed_timestamp.date != now.date {
+                self.metadata = stamp_regular_entry(vfat, &self.location, now, modified)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Shrinks the file to `size` bytes and frees the clusters past its new
+    /// end.
+    fn truncate(&mut self, vfat: &mut VFat<HANDLE>, size: u64) -> io::Result<()> {
+        let cluster_size = vfat.bytes_per_cluster();
+        let clusters_num = (size + cluster_size - 1) / cluster_size;
+        if clusters_num == 0 && self.start_cluster.cluster_id() != 0 {
+            vfat.free_chain(self.start_cluster)?;
+            self.start_cluster = Cluster::from(0);
+        } else if let Some(last) = self.cluster_at(vfat, (clusters_num.max(1) - 1) * cluster_size)? {
+            vfat.truncate_chain(last)?;
+        }
+        self.clusters.truncate(clusters_num as usize);
+        self.size = size;
+        Ok(())
+    }
 }
 
 // FIXME: Implement `traits::File` (and its supertraits) for `File`.
 impl<HANDLE: VFatHandle> traits::File for File<HANDLE> {
     fn sync(&mut self) -> io::Result<()> {
-        panic!("dummy")
+        self.vfat.lock(|vfat| vfat.flush())
     }
     fn size(&self) -> u64 {
         self.size
     }
     fn is_end(&self) -> bool {
-        self.size == self.pos
+        self.pos >= self.size
+    }
+
+    fn set_len(&mut self, size: u64) -> io::Result<()> {
+        // the size of a FAT32 file is limited to 4GiB - 1
+        if size > core::u32::MAX as u64 {
+            return ioerr!(InvalidInput, "set_len: size exceeds the file size limit");
+        }
+        let vfat = self.vfat.clone();
+        vfat.lock(|vfat| {
+            let result = if size > self.size {
+                self.zero_extend(vfat, size)
+            } else {
+                self.truncate(vfat, size)
+            };
+            update_regular_entry(vfat, &self.location, self.start_cluster, self.size as u32)?;
+            self.stamp(vfat, true)?;
+            result
+        })
     }
 }
 
 impl<HANDLE: VFatHandle> io::Seek for File<HANDLE> {
     /// Seek to offset `pos` in the file.
     ///
-    /// A seek to the end of the file is allowed. A seek _beyond_ the end of the
-    /// file returns an `InvalidInput` error.
+    /// As with `std::fs::File`, a seek beyond the end of the file is allowed.
+    /// Reads there return no data, and a write there first fills the gap
+    /// with zeros.
     ///
     /// If the seek operation completes successfully, this method returns the
     /// new position from the start of the stream. That position can be used
@@ -42,60 +149,109 @@ impl<HANDLE: VFatHandle> io::Seek for File<HANDLE> {
     ///
     /// # Errors
     ///
-    /// Seeking before the start of a file or beyond the end of the file results
-    /// in an `InvalidInput` error.
-    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
-        match _pos {
-            SeekFrom::Start(off) => {
-                if off > self.size {
-                    return ioerr!(InvalidInput, "seek: seeking before the start of the file");
-                }
-                self.pos = off;
-            },
-            SeekFrom::End(off) => {
-                if off > 0 {
-                    return ioerr!(InvalidInput, "seek: seeking after the end of the file");
-                }
-                self.pos = self.size;
+    /// Seeking to a negative or overflowing position results in an
+    /// `InvalidInput` error.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.pos = offset;
+                return Ok(offset);
             },
-            SeekFrom::Current(off) => {
-                if off < 0 && (self.pos as i64) + off < 0 {
-                    return ioerr!(InvalidInput, "seek: seeking before the start of the file");
-                } else if off > 0 && self.pos + (off as u64) > self.size {
-                    return ioerr!(InvalidInput, "seek: seeking after the end of the file");
-                } 
-                self.pos = (self.pos as i64 + off) as u64;
-            }
+            SeekFrom::End(offset) => (self.size, offset),
+            SeekFrom::Current(offset) => (self.pos, offset),
+        };
+        let pos = if offset >= 0 {
+            base.checked_add(offset as u64)
+        } else {
+            base.checked_sub(offset.wrapping_neg() as u64)
         };
-        Ok(self.pos as u64)
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            },
+            None => ioerr!(InvalidInput, "seek: invalid seek to a negative or overflowing position"),
+        }
     }
 }
 
 impl<HANDLE: VFatHandle> io::Read for File<HANDLE> {
-    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
-        // if self.size == 0 || self.pos >= self.size {
-        //     return Ok(0);
-        // }
-        if self.size == 0 { return Ok(0); }
-        // read from current pos of the file
-        let read_result = self.vfat.lock(|vfat| {
-            let max_read_size = ((self.size - self.pos).min(_buf.len() as u64)) as usize;
-            vfat.read_cluster(self.start_cluster, self.pos as usize, &mut _buf[..max_read_size])
-        });
-        if let Ok(read_size) = read_result {
-            self.pos += read_size as u64;
-            Ok(read_size)
-        } else {
-            read_result
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.size {
+            return Ok(0);
         }
+        let max_read_size = ((self.size - self.pos).min(buf.len() as u64)) as usize;
+        let vfat = self.vfat.clone();
+        vfat.lock(|vfat| {
+            let cluster_size = vfat.bytes_per_cluster();
+            let mut read_size = 0;
+            // read run by run of contiguous clusters, resolved through the
+            // cached chain
+            while read_size < max_read_size {
+                let cluster = match self.cluster_at(vfat, self.pos)? {
+                    Some(cluster) => cluster,
+                    None => return ioerr!(InvalidData, "read: cluster chain is shorter than the file"),
+                };
+                let offset = (self.pos % cluster_size) as usize;
+                let left = max_read_size - read_size;
+                let mut size = cluster_size as usize - offset;
+                while size < left {
+                    let next_id = cluster.cluster_id() + ((offset + size) as u64 / cluster_size);
+                    match self.cluster_at(vfat, self.pos + size as u64)? {
+                        Some(next) if next.cluster_id() == next_id => size += cluster_size as usize,
+                        _ => break,
+                    }
+                }
+                let size = size.min(left);
+                let size = vfat.read_contiguous(cluster, offset, &mut buf[read_size..read_size + size])?;
+                read_size += size;
+                self.pos += size as u64;
+            }
+            // the bytes are read and the position has moved: failing to
+            // record the access date must not turn this into an error
+            let _ = self.stamp(vfat, false);
+            Ok(read_size)
+        })
     }
 }
 
 impl<HANDLE: VFatHandle> io::Write for File<HANDLE> {
-    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
-        panic!("dummy")
+    /// Writes `buf` at the current position of the file, overwriting the
+    /// existing bytes and extending the file if the write goes past its end.
+    /// A gap between the end of the file and the position is zero-filled.
+    ///
+    /// The written data stays in the sector cache until `flush()` or
+    /// `sync()` is called.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // the size of a FAT32 file is limited to 4GiB - 1
+        let max_write_size = (core::u32::MAX as u64).saturating_sub(self.pos);
+        if buf.is_empty() {
+            return Ok(0);
+        } else if max_write_size == 0 {
+            return ioerr!(Other, "write: file size limit reached");
+        }
+        let buf = &buf[..(max_write_size.min(buf.len() as u64) as usize)];
+
+        let vfat = self.vfat.clone();
+        vfat.lock(|vfat| {
+            let pos = self.pos;
+            let result = if pos > self.size {
+                self.zero_extend(vfat, pos)
+            } else {
+                Ok(())
+            }.and_then(|_| self.write_at(vfat, pos, buf));
+            if let Ok(write_size) = result {
+                self.pos += write_size as u64;
+                self.size = self.size.max(self.pos);
+            }
+            // record the clusters allocated even if the write failed
+            update_regular_entry(vfat, &self.location, self.start_cluster, self.size as u32)?;
+            self.stamp(vfat, true)?;
+            result
+        })
     }
+
     fn flush(&mut self) -> io::Result<()> {
-        panic!("dummy")
+        self.vfat.lock(|vfat| vfat.flush())
     }
 }
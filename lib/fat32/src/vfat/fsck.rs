@@ -0,0 +1,383 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem::size_of;
+
+use shim::io;
+
+use crate::util::VecExt;
+use crate::vfat::dir::{self, EntryLocation, VFatDirEntry, VFatLfnDirEntry, VFatRegularDirEntry, VFatWrapEntry};
+use crate::vfat::{name, Cluster, Status, VFat, VFatHandle};
+
+/// An inconsistency found by `VFat::check()`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// The FAT entry of `cluster` in the FAT copy `copy` differs from the
+    /// one in the active FAT.
+    FatMismatch { copy: u8, cluster: u32 },
+    /// The chain of the entry at `path` runs into `cluster`, which already
+    /// belongs to another chain or to an earlier part of the same chain.
+    CrossLinked { path: String, cluster: u32 },
+    /// The chain of the entry at `path` runs into `cluster`, which is out of
+    /// range or whose FAT entry is bad, reserved or free.
+    BadChain { path: String, cluster: u32 },
+    /// The file at `path` is `size` bytes long but its chain holds
+    /// `clusters` clusters.
+    SizeMismatch { path: String, size: u32, clusters: u32 },
+    /// The LFN entries starting at byte `offset` of the directory at `path`
+    /// do not belong to the regular entry following them.
+    OrphanLfn { path: String, offset: u64 },
+    /// A chain of `clusters` allocated clusters beginning at `start` is not
+    /// referenced by any directory entry.
+    LostChain { start: u32, clusters: u32 },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::FatMismatch { copy, cluster } => {
+                write!(f, "FAT copy {} differs from the active FAT at cluster {}", copy, cluster)
+            },
+            Problem::CrossLinked { path, cluster } => {
+                write!(f, "{}: chain is cross-linked at cluster {}", path, cluster)
+            },
+            Problem::BadChain { path, cluster } => {
+                write!(f, "{}: chain runs into unusable cluster {}", path, cluster)
+            },
+            Problem::SizeMismatch { path, size, clusters } => {
+                write!(f, "{}: size is {} bytes but chain has {} clusters", path, size, clusters)
+            },
+            Problem::OrphanLfn { path, offset } => {
+                write!(f, "{}: orphaned long file name entries at offset {}", path, offset)
+            },
+            Problem::LostChain { start, clusters } => {
+                write!(f, "lost chain of {} clusters at cluster {}", clusters, start)
+            },
+        }
+    }
+}
+
+/// How a chain walked by `Checker::walk_chain()` ends.
+enum ChainEnd {
+    Eoc,
+    CrossLinked(Cluster),
+    Bad(Cluster),
+}
+
+struct Checker<'a, HANDLE: VFatHandle> {
+    vfat: &'a mut VFat<HANDLE>,
+    repair: bool,
+    used: Vec<bool>, // whether a cluster belongs to a chain seen so far
+    problems: Vec<Problem>,
+}
+
+impl<HANDLE: VFatHandle> VFat<HANDLE> {
+    /// Checks the consistency of the file system and returns every problem
+    /// found. Both the FAT copies and the directory tree are checked.
+    ///
+    /// If `repair` is `true`, the problems are also fixed: FAT copies are
+    /// overwritten with the active FAT, cross-linked and broken chains are cut
+    /// short, file sizes are fitted to their chains, orphaned LFN entries are
+    /// deleted and lost chains are freed. Repairs are flushed to the disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading or writing the disk fails.
+    pub fn check(&mut self, repair: bool) -> io::Result<Vec<Problem>> {
+        let clusters_end = self.clusters_num() as usize + 2;
+        let mut checker = Checker {
+            vfat: self,
+            repair,
+            used: vec![false; clusters_end],
+            problems: vec![],
+        };
+        checker.check_fats()?;
+        checker.check_tree()?;
+        checker.check_lost_chains()?;
+        let problems = checker.problems;
+        if repair && !problems.is_empty() {
+            self.recount_free_space()?;
+            self.flush()?;
+        }
+        Ok(problems)
+    }
+}
+
+impl<'a, HANDLE: VFatHandle> Checker<'a, HANDLE> {
+    fn in_range(&self, cluster: Cluster) -> bool {
+        cluster.cluster_id() >= 2 && (cluster.cluster_id() as usize) < self.used.len()
+    }
+
+    /// Compares every FAT copy with the active FAT.
+    fn check_fats(&mut self) -> io::Result<()> {
+        let active_fat = self.vfat.active_fat();
+        for copy in (0..self.vfat.fat_num()).filter(|&copy| copy != active_fat) {
+            for id in 0..self.used.len() as u32 {
+                let cluster = Cluster::from(id);
+                let active = self.vfat.fat_copy_entry(active_fat, cluster)?;
+                // the high 4 bits of an entry are reserved
+                if (active ^ self.vfat.fat_copy_entry(copy, cluster)?) & 0x0FFFFFFF == 0 {
+                    continue;
+                }
+                self.problems.push(Problem::FatMismatch { copy, cluster: id });
+                if self.repair {
+                    self.vfat.set_fat_copy_entry(copy, cluster, active)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Walks the directory tree beginning at the root directory.
+    fn check_tree(&mut self) -> io::Result<()> {
+        let root = self.vfat.rootdir_cluster();
+        // the root directory of FAT12/16 is a fixed region, not a chain
+        let clusters = if self.vfat.is_rootdir_region(root) {
+            vec![root]
+        } else {
+            self.check_chain("/", root, None, None)?
+        };
+        let mut dirs = vec![(String::from("/"), clusters)];
+        while let Some((path, clusters)) = dirs.pop() {
+            if !clusters.is_empty() {
+                let subdirs = self.check_dir(&path, &clusters)?;
+                dirs.extend(subdirs);
+            }
+        }
+        Ok(())
+    }
+
+    /// Follows the chain beginning at `start` and claims its clusters. The
+    /// walk stops at the first cluster that cannot be part of the chain.
+    fn walk_chain(&mut self, start: Cluster) -> io::Result<(Vec<Cluster>, ChainEnd)> {
+        let mut clusters = vec![];
+        let mut cluster = start;
+        loop {
+            if !self.in_range(cluster) {
+                return Ok((clusters, ChainEnd::Bad(cluster)));
+            }
+            if self.used[cluster.cluster_id() as usize] {
+                return Ok((clusters, ChainEnd::CrossLinked(cluster)));
+            }
+            let status = self.vfat.fat_entry(cluster)?.status();
+            match status {
+                Status::Data(_) | Status::Eoc(_) => {
+                    self.used[cluster.cluster_id() as usize] = true;
+                    clusters.push(cluster);
+                },
+                _ => return Ok((clusters, ChainEnd::Bad(cluster))),
+            }
+            match status {
+                Status::Data(next_cluster) => cluster = next_cluster,
+                _ => return Ok((clusters, ChainEnd::Eoc)),
+            }
+        }
+    }
+
+    /// Checks the chain beginning at `start` of the entry at `path`. `size`
+    /// is the size of a regular file, or `None` for a directory. `location`
+    /// is the location of the entry, which is `None` for the root directory.
+    /// Returns the clusters of the chain that are left after repairing it.
+    fn check_chain(
+        &mut self,
+        path: &str,
+        start: Cluster,
+        size: Option<u32>,
+        location: Option<EntryLocation>,
+    ) -> io::Result<Vec<Cluster>> {
+        let (mut clusters, end) = if start.cluster_id() == 0 && size.is_some() {
+            // an empty file owns no cluster
+            (vec![], ChainEnd::Eoc)
+        } else {
+            self.walk_chain(start)?
+        };
+        let mut changed = true;
+        match end {
+            ChainEnd::Eoc => changed = false,
+            ChainEnd::CrossLinked(cluster) => self.problems.push(Problem::CrossLinked {
+                path: path.into(),
+                cluster: cluster.cluster_id() as u32,
+            }),
+            ChainEnd::Bad(cluster) => self.problems.push(Problem::BadChain {
+                path: path.into(),
+                cluster: cluster.cluster_id() as u32,
+            }),
+        }
+
+        let cluster_size = self.vfat.bytes_per_cluster();
+        let mut new_size = size.unwrap_or(0);
+        if let Some(size) = size {
+            let needed = ((size as u64 + cluster_size - 1) / cluster_size) as usize;
+            if !changed && needed != clusters.len() {
+                self.problems.push(Problem::SizeMismatch {
+                    path: path.into(),
+                    size,
+                    clusters: clusters.len() as u32,
+                });
+                changed = true;
+            }
+            if self.repair && clusters.len() > needed {
+                for &cluster in clusters[needed..].iter() {
+                    self.vfat.set_fat_status(cluster, Status::Free)?;
+                    self.used[cluster.cluster_id() as usize] = false;
+                }
+                clusters.truncate(needed);
+            }
+            new_size = (size as u64).min(clusters.len() as u64 * cluster_size) as u32;
+        }
+
+        if !self.repair || !changed {
+            return Ok(clusters);
+        }
+        if let Some(&last) = clusters.last() {
+            self.vfat.set_fat_status(last, Status::Eoc(0x0FFFFFFF))?;
+        }
+        if let Some(location) = location {
+            if size.is_none() && clusters.is_empty() {
+                // a directory without any cluster cannot be kept
+                dir::delete_entry(self.vfat, &location)?;
+            } else {
+                let start = clusters.first().cloned().unwrap_or_default();
+                dir::update_regular_entry(self.vfat, &location, start, new_size)?;
+            }
+        }
+        Ok(clusters)
+    }
+
+    /// Checks the entries of the directory at `path` stored in `clusters`.
+    /// Returns the path and the clusters of each of its subdirectories.
+    fn check_dir(&mut self, path: &str, clusters: &[Cluster]) -> io::Result<Vec<(String, Vec<Cluster>)>> {
+        let dir_start = clusters[0];
+        let cluster_size = if self.vfat.is_rootdir_region(dir_start) {
+            self.vfat.rootdir_region_size() as usize
+        } else {
+            self.vfat.bytes_per_cluster() as usize
+        };
+        let entry_size = size_of::<VFatDirEntry>();
+        let mut lfns: Vec<(u64, VFatLfnDirEntry)> = vec![];
+        let mut subdirs = vec![];
+
+        'clusters: for (i, &cluster) in clusters.iter().enumerate() {
+            let mut buf = vec![0u8; cluster_size];
+            self.vfat.read_cluster(cluster, 0, &mut buf)?;
+            let dir_entries: Vec<VFatDirEntry> = unsafe { VecExt::cast(buf) };
+            for (j, dir_entry) in dir_entries.iter().enumerate() {
+                let offset = (i * cluster_size + j * entry_size) as u64;
+                if dir_entry.is_last_entry() {
+                    break 'clusters;
+                }
+                if dir_entry.is_unused_entry() {
+                    self.check_orphan_lfns(path, dir_start, &mut lfns)?;
+                    continue;
+                }
+
+                let regular_entry = match dir_entry.to_wrap_entry() {
+                    VFatWrapEntry::LongFilename(lfn_entry) => {
+                        // a new name begins with its last LFN entry
+                        if lfn_entry.sequence_num & VFatLfnDirEntry::LAST_SEQUENCE_FLAG != 0 {
+                            self.check_orphan_lfns(path, dir_start, &mut lfns)?;
+                        }
+                        lfns.push((offset, lfn_entry));
+                        continue;
+                    },
+                    VFatWrapEntry::Reguler(regular_entry) => regular_entry,
+                };
+
+                let checksum = dir::lfn_checksum(&regular_entry.short_name());
+                if lfns.iter().any(|(_, lfn_entry)| lfn_entry.checksum != checksum) {
+                    self.check_orphan_lfns(path, dir_start, &mut lfns)?;
+                }
+                let name = if lfns.is_empty() {
+                    regular_entry.short_name_string()
+                } else {
+                    let units: Vec<u16> = lfns.iter().rev().flat_map(|(_, lfn_entry)| lfn_entry.name_units()).collect();
+                    name::decode_utf16_lossy(&units)
+                };
+                let start_offset = lfns.first().map(|(offset, _)| *offset).unwrap_or(offset);
+                lfns.clear();
+                if name == "." || name == ".."
+                    || regular_entry.attributes & VFatRegularDirEntry::ATTR_VOLUME_ID_FLAG != 0 {
+                    continue;
+                }
+
+                let entry_path = if path == "/" {
+                    format!("/{}", name)
+                } else {
+                    format!("{}/{}", path, name)
+                };
+                let location = EntryLocation { dir_cluster: dir_start, start_offset, offset };
+                if regular_entry.is_directory() {
+                    let clusters = self.check_chain(&entry_path, regular_entry.cluster(), None, Some(location))?;
+                    if !clusters.is_empty() {
+                        subdirs.push((entry_path, clusters));
+                    }
+                } else {
+                    let size = regular_entry.file_size;
+                    self.check_chain(&entry_path, regular_entry.cluster(), Some(size), Some(location))?;
+                }
+            }
+        }
+        self.check_orphan_lfns(path, dir_start, &mut lfns)?;
+        Ok(subdirs)
+    }
+
+    /// Reports the pending LFN entries `lfns` of the directory at `path`
+    /// beginning at `dir_start` as orphaned, if there are any.
+    fn check_orphan_lfns(
+        &mut self,
+        path: &str,
+        dir_start: Cluster,
+        lfns: &mut Vec<(u64, VFatLfnDirEntry)>,
+    ) -> io::Result<()> {
+        if let Some(&(offset, _)) = lfns.first() {
+            self.problems.push(Problem::OrphanLfn { path: path.into(), offset });
+            if self.repair {
+                for &(offset, _) in lfns.iter() {
+                    self.vfat.write_cluster(dir_start, offset as usize, &[VFatDirEntry::ID_UNUSED_ENTRY])?;
+                }
+            }
+        }
+        lfns.clear();
+        Ok(())
+    }
+
+    /// Reports every allocated cluster that is not part of a chain seen so
+    /// far. Chains are reported from their heads where possible; clusters
+    /// left after that belong to cycles.
+    fn check_lost_chains(&mut self) -> io::Result<()> {
+        let end = self.used.len();
+        let mut referenced = vec![false; end];
+        for id in 2..end {
+            if self.used[id] {
+                continue;
+            }
+            if let Status::Data(next_cluster) = self.vfat.fat_entry(Cluster::from(id as u32))?.status() {
+                if self.in_range(next_cluster) {
+                    referenced[next_cluster.cluster_id() as usize] = true;
+                }
+            }
+        }
+
+        for heads_only in [true, false].iter() {
+            for id in 2..end {
+                if self.used[id] || (*heads_only && referenced[id]) {
+                    continue;
+                }
+                let cluster = Cluster::from(id as u32);
+                match self.vfat.fat_entry(cluster)?.status() {
+                    Status::Data(_) | Status::Eoc(_) => (),
+                    _ => continue,
+                }
+                let (clusters, _) = self.walk_chain(cluster)?;
+                self.problems.push(Problem::LostChain { start: id as u32, clusters: clusters.len() as u32 });
+                if self.repair {
+                    for &cluster in clusters.iter() {
+                        self.vfat.set_fat_status(cluster, Status::Free)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
@@ -0,0 +1,72 @@
+use core::{fmt, mem};
+use shim::const_assert_size;
+
+use crate::vfat::Error;
+
+#[repr(C, packed)]
+pub struct FsInfo {
+    lead_signature: [u8; 4],
+    _1: [u8; 480],
+    struct_signature: [u8; 4],
+    pub free_count: u32, // last known free cluster count, 0xFFFFFFFF if unknown
+    pub next_free: u32,  // cluster to start looking for free clusters at
+    _2: [u8; 12],
+    trail_signature: [u8; 4],
+}
+
+const_assert_size!(FsInfo, 512);
+
+const LEAD_SIGNATURE: [u8; 4] = *b"RRaA";
+const STRUCT_SIGNATURE: [u8; 4] = *b"rrAa";
+const TRAIL_SIGNATURE: [u8; 4] = [0x00, 0x00, 0x55, 0xAA];
+
+impl FsInfo {
+    /// Value of `free_count` and `next_free` when the field is not known.
+    pub const UNKNOWN: u32 = 0xFFFFFFFF;
+
+    /// Returns an FSInfo structure with the free cluster count `free_count`
+    /// and the next free cluster hint `next_free`.
+    pub fn new(free_count: u32, next_free: u32) -> FsInfo {
+        let mut fsinfo: FsInfo = unsafe { mem::zeroed() };
+        fsinfo.lead_signature = LEAD_SIGNATURE;
+        fsinfo.struct_signature = STRUCT_SIGNATURE;
+        fsinfo.trail_signature = TRAIL_SIGNATURE;
+        fsinfo.free_count = free_count;
+        fsinfo.next_free = next_free;
+        fsinfo
+    }
+
+    /// Parses the FSInfo structure at the start of the sector `sector`.
+    ///
+    /// # Errors
+    ///
+    /// If any of the three FSInfo signatures is invalid, returns an error of
+    /// `BadSignature`.
+    pub fn from_bytes(sector: &[u8]) -> Result<FsInfo, Error> {
+        let mut buf = [0u8; 512];
+        buf.copy_from_slice(&sector[..512]);
+        let fsinfo = unsafe { mem::transmute::<[u8; 512], FsInfo>(buf) };
+        if fsinfo.lead_signature != LEAD_SIGNATURE
+            || fsinfo.struct_signature != STRUCT_SIGNATURE
+            || fsinfo.trail_signature != TRAIL_SIGNATURE {
+            Err(Error::BadSignature)
+        } else {
+            Ok(fsinfo)
+        }
+    }
+
+    /// Writes the FSInfo structure to the start of the sector `sector`.
+    pub fn write_to(&self, sector: &mut [u8]) {
+        let buf: &[u8; 512] = unsafe { mem::transmute(self) };
+        sector[..512].copy_from_slice(buf);
+    }
+}
+
+impl fmt::Debug for FsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FsInfo")
+         .field("free count", &{ self.free_count })
+         .field("next free", &{ self.next_free })
+         .finish()
+    }
+}
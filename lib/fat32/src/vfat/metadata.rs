@@ -1,4 +1,5 @@
 use core::fmt;
+use core::time::Duration;
 
 use crate::traits;
 
@@ -30,6 +31,8 @@ pub struct Metadata {
     // FIXME: Fill me in.
     pub attributes: Attributes,
     pub created_timestamp: Timestamp,
+    /// Hundredths of a second to add to `created_timestamp`, from 0 to 199.
+    pub created_hundredths: u8,
     pub accessed_timestamp: Timestamp,
     pub modified_timestamp: Timestamp,
 }
@@ -47,6 +50,75 @@ const MINUTES_OFF: u16 = 5;
 const HOURS_MASK: u16 = 0xF800;
 const HOURS_OFF: u16 = 11;
 
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Returns the number of days from 1970-01-01 to the date `year`-`month`-`day`
+/// of the proleptic Gregorian calendar. `year` must be at least 1970.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Returns the date (year, month, day) `days` days after 1970-01-01. The
+/// inverse of `days_from_civil()`.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = (shifted_month + 2) % 12 + 1;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+impl Timestamp {
+    /// Returns the timestamp of `time`, a time since the Unix epoch, and the
+    /// hundredths of a second (0 to 199) that it lies past the timestamp.
+    /// Times outside of the range of FAT timestamps, 1980 to 2107, are
+    /// clamped to it.
+    pub fn from_unix_precise(time: Duration) -> (Timestamp, u8) {
+        let min = days_from_civil(1980, 1, 1) * SECONDS_PER_DAY;
+        let max = days_from_civil(2108, 1, 1) * SECONDS_PER_DAY - 2;
+        let (secs, hundredths) = if time.as_secs() < min {
+            (min, 0)
+        } else if time.as_secs() > max {
+            (max, 0)
+        } else {
+            let secs = time.as_secs();
+            (secs, ((secs % 2) * 100) as u8 + (time.subsec_nanos() / 10_000_000) as u8)
+        };
+
+        let (year, month, day) = civil_from_days(secs / SECONDS_PER_DAY);
+        let secs_of_day = secs % SECONDS_PER_DAY;
+        let (hour, minute, second) = (secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60);
+        let date = (((year - 1980) as u16) << YEAR_OFF) | ((month as u16) << MONTH_OFF) | ((day as u16) << DAY_OFF);
+        let time = ((hour as u16) << HOURS_OFF) | ((minute as u16) << MINUTES_OFF) | ((second / 2) as u16) << SECONDS_OFF;
+        (Timestamp { date: Date(date), time: Time(time) }, hundredths)
+    }
+
+    /// Returns the timestamp of `time`, a time since the Unix epoch, rounded
+    /// down to an even second. Times outside of the range of FAT timestamps,
+    /// 1980 to 2107, are clamped to it.
+    pub fn from_unix(time: Duration) -> Timestamp {
+        Timestamp::from_unix_precise(time).0
+    }
+
+    /// Returns the time since the Unix epoch of the timestamp. A zero month
+    /// or day, as left by tools that do not record a timestamp, is read as 1.
+    pub fn to_unix(&self) -> Duration {
+        let days = days_from_civil(self.year() as u64, self.month().max(1) as u64, self.day().max(1) as u64);
+        let secs = self.hour() as u64 * 3600 + self.minute() as u64 * 60 + self.second() as u64;
+        Duration::from_secs(days * SECONDS_PER_DAY + secs)
+    }
+}
+
 // FIXME: Implement `traits::Timestamp` for `Timestamp`.
 impl traits::Timestamp for Timestamp {
     fn year(&self) -> usize {
@@ -0,0 +1,157 @@
+use shim::io;
+use shim::ioerr;
+
+use crate::mbr::{MasterBootRecord, PartitionEntry};
+use crate::traits::BlockDevice;
+use crate::vfat::ebpb::NO_LABEL;
+use crate::vfat::{BiosParameterBlock, FsInfo};
+
+/// Options of `format()`.
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    /// Sectors per cluster. Must be a power of 2 no greater than 128.
+    pub sectors_per_cluster: u8,
+    /// Number of copies of the FAT.
+    pub fat_num: u8,
+    /// Number of reserved sectors in front of the first FAT.
+    pub reserved_sectors_num: u16,
+    /// The sector the partition begins at.
+    pub partition_start: u32,
+    /// The volume serial number.
+    pub volume_id: u32,
+}
+
+impl Default for FormatOptions {
+    fn default() -> FormatOptions {
+        FormatOptions {
+            sectors_per_cluster: 8,
+            fat_num: 2,
+            reserved_sectors_num: 32,
+            partition_start: 2048,
+            volume_id: 0,
+        }
+    }
+}
+
+const SECTOR_SIZE: u64 = 512;
+const FAT32_LBA_PARTITION_TYPE: u8 = 0xC;
+const FSINFO_SECTOR: u16 = 1;
+const BACKUP_BOOT_SECTOR: u16 = 6;
+const ROOTDIR_CLUSTER: u32 = 2;
+/// A FAT32 volume has at least this many clusters; fewer make it FAT16.
+const MIN_CLUSTERS_NUM: u64 = 65525;
+const MAX_CLUSTERS_NUM: u64 = 0x0FFFFFF5;
+
+/// Formats the first `sectors_num` sectors of `device` as a disk holding a
+/// single FAT32 partition. An MBR, the boot sector and its backup, the FSInfo
+/// sector, the FATs and an empty root directory are written.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if the sector size of `device` is not
+/// 512 bytes, if `options` is invalid or if the partition is too small or
+/// too large to hold a FAT32 volume with the requested cluster size.
+///
+/// Returns an error if writing to `device` fails.
+pub fn format<T: BlockDevice>(mut device: T, sectors_num: u64, options: &FormatOptions) -> io::Result<()> {
+    let spc = options.sectors_per_cluster as u64;
+    let fat_num = options.fat_num as u64;
+    let reserved = options.reserved_sectors_num as u64;
+    let start = options.partition_start as u64;
+    if device.sector_size() != SECTOR_SIZE {
+        return ioerr!(InvalidInput, "format: sector size must be 512 bytes");
+    }
+    if spc == 0 || spc > 128 || !spc.is_power_of_two() {
+        return ioerr!(InvalidInput, "format: invalid number of sectors per cluster");
+    }
+    if fat_num == 0 || reserved <= BACKUP_BOOT_SECTOR as u64 + 1 || start == 0 {
+        return ioerr!(InvalidInput, "format: invalid volume layout");
+    }
+    if sectors_num <= start || sectors_num - start > core::u32::MAX as u64 {
+        return ioerr!(InvalidInput, "format: invalid number of sectors");
+    }
+    let partition_sectors = sectors_num - start;
+
+    // grow the FAT until it can describe every cluster left beside it
+    let mut sectors_per_fat = 1;
+    let clusters_num = loop {
+        let meta_sectors = reserved + fat_num * sectors_per_fat;
+        if meta_sectors + spc > partition_sectors {
+            return ioerr!(InvalidInput, "format: too few sectors for FAT32");
+        }
+        let clusters_num = (partition_sectors - meta_sectors) / spc;
+        let needed = ((clusters_num + 2) * 4 + SECTOR_SIZE - 1) / SECTOR_SIZE;
+        if needed <= sectors_per_fat {
+            break clusters_num;
+        }
+        sectors_per_fat = needed;
+    };
+    if clusters_num < MIN_CLUSTERS_NUM {
+        return ioerr!(InvalidInput, "format: too few clusters for FAT32");
+    }
+    if clusters_num > MAX_CLUSTERS_NUM {
+        return ioerr!(InvalidInput, "format: too many clusters for FAT32");
+    }
+
+    let mbr = MasterBootRecord::new(PartitionEntry::new(
+        FAT32_LBA_PARTITION_TYPE,
+        start as u32,
+        partition_sectors as u32,
+    ));
+    device.write_sector(0, &mbr.to_bytes())?;
+
+    let mut ebpb = BiosParameterBlock::default();
+    ebpb.jump = [0xEB, 0x58, 0x90];
+    ebpb.oem_name = *b"MSWIN4.1";
+    ebpb.bytes_per_sector = SECTOR_SIZE as u16;
+    ebpb.sectors_per_cluster = options.sectors_per_cluster;
+    ebpb.reserved_sectors_num = options.reserved_sectors_num;
+    ebpb.fat_num = options.fat_num;
+    ebpb.media_descriptor = 0xF8;
+    ebpb.sectors_per_track = 63;
+    ebpb.heads_num = 255;
+    ebpb.hidden_sectors = start as u32;
+    ebpb.set_total_sectors(partition_sectors as u32);
+    ebpb.sectors_per_fat_2 = sectors_per_fat as u32;
+    ebpb.rootdir_cluster = ROOTDIR_CLUSTER;
+    ebpb.fsinfo_sector = FSINFO_SECTOR;
+    ebpb.backup_boot_sector = BACKUP_BOOT_SECTOR;
+    ebpb.drive_num = 0x80;
+    ebpb.ext_signature = 0x29;
+    ebpb.volume_id = options.volume_id;
+    ebpb.volume_label = NO_LABEL;
+    ebpb.fs_type = *b"FAT32   ";
+    let fsinfo = FsInfo::new(clusters_num as u32 - 1, ROOTDIR_CLUSTER + 1);
+    let mut fsinfo_buf = [0u8; SECTOR_SIZE as usize];
+    fsinfo.write_to(&mut fsinfo_buf);
+
+    // reserved region, with the boot and FSInfo sectors and their backups
+    let zeros = [0u8; SECTOR_SIZE as usize];
+    for sector in 0..reserved {
+        device.write_sector(start + sector, &zeros)?;
+    }
+    for &boot_sector in [0, BACKUP_BOOT_SECTOR as u64].iter() {
+        device.write_sector(start + boot_sector, &ebpb.to_bytes())?;
+        device.write_sector(start + boot_sector + FSINFO_SECTOR as u64, &fsinfo_buf)?;
+    }
+
+    // FATs with the two reserved entries and the root directory's cluster
+    let mut first_fat_sector = [0u8; SECTOR_SIZE as usize];
+    for (i, entry) in [0x0FFFFFF8u32, 0x0FFFFFFF, 0x0FFFFFFF].iter().enumerate() {
+        first_fat_sector[i * 4..(i + 1) * 4].copy_from_slice(&entry.to_le_bytes());
+    }
+    for copy in 0..fat_num {
+        let fat_start = start + reserved + copy * sectors_per_fat;
+        device.write_sector(fat_start, &first_fat_sector)?;
+        for sector in 1..sectors_per_fat {
+            device.write_sector(fat_start + sector, &zeros)?;
+        }
+    }
+
+    // empty root directory
+    let data_start = start + reserved + fat_num * sectors_per_fat;
+    for sector in 0..spc {
+        device.write_sector(data_start + sector, &zeros)?;
+    }
+    device.sync()
+}
@@ -6,16 +6,24 @@ pub(crate) mod entry;
 pub(crate) mod error;
 pub(crate) mod fat;
 pub(crate) mod file;
+pub(crate) mod fsck;
+pub(crate) mod fsinfo;
 pub(crate) mod metadata;
+pub(crate) mod name;
+pub(crate) mod mkfs;
 pub(crate) mod vfat;
 
-pub use self::dir::Dir;
+pub use self::dir::{Dir, DirIter};
 pub use self::ebpb::BiosParameterBlock;
+pub use self::fat::FatType;
 pub use self::entry::Entry;
 pub use self::error::Error;
 pub use self::file::File;
+pub use self::fsck::Problem;
+pub use self::fsinfo::FsInfo;
 pub use self::metadata::{Attributes, Date, Metadata, Time, Timestamp};
-pub use self::vfat::{VFat, VFatHandle};
+pub use self::mkfs::{format, FormatOptions};
+pub use self::vfat::{MountOptions, VFat, VFatHandle, VolumeInfo};
 
 pub(crate) use self::cache::{CachedPartition, Partition};
 pub(crate) use self::cluster::Cluster;
@@ -0,0 +1,80 @@
+use alloc::string::String;
+use core::char;
+
+/// Characters of code page 437 bytes `0x80` to `0xFF`, the OEM code page
+/// short names are stored in. Bytes below `0x80` are ASCII.
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç',
+    'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù',
+    'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º',
+    '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖',
+    '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟',
+    '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫',
+    '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ',
+    'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈',
+    '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{00A0}',
+];
+
+/// Decodes `bytes` from code page 437.
+pub(crate) fn decode_cp437(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|&byte| match byte {
+            0..=0x7F => byte as char,
+            _ => CP437_HIGH[byte as usize - 0x80],
+        })
+        .collect()
+}
+
+/// Returns the code page 437 byte of `c`, or `None` if the code page has
+/// no such character.
+pub(crate) fn encode_cp437(c: char) -> Option<u8> {
+    if c.is_ascii() {
+        Some(c as u8)
+    } else {
+        CP437_HIGH.iter().position(|&high| high == c).map(|i| 0x80 + i as u8)
+    }
+}
+
+/// Decodes the UTF-16 code units `units`. An unpaired surrogate is decoded
+/// as U+FFFD REPLACEMENT CHARACTER.
+pub(crate) fn decode_utf16_lossy(units: &[u16]) -> String {
+    char::decode_utf16(units.iter().cloned())
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// Returns the uppercase form of `c` if it is a single character, and `c`
+/// otherwise. Unlike `char::to_uppercase`, `ß` stays `ß`.
+pub(crate) fn to_uppercase(c: char) -> char {
+    let mut upper = c.to_uppercase();
+    match (upper.next(), upper.next()) {
+        (Some(upper), None) => upper,
+        _ => c,
+    }
+}
+
+/// Maps the UTF-16 code unit `unit` to uppercase the way the upcase table of
+/// Windows does: characters of the BMP map to their single-character
+/// uppercase form if it is in the BMP too, and surrogates map to themselves.
+fn upcase(unit: u16) -> u16 {
+    match char::from_u32(unit as u32) {
+        Some(c) => {
+            let upper = to_uppercase(c) as u32;
+            if upper <= 0xFFFF { upper as u16 } else { unit }
+        },
+        None => unit,
+    }
+}
+
+/// Returns `true` if the names `a` and `b` are equal, ignoring the case of
+/// the characters of the BMP.
+pub(crate) fn eq_ignore_case(a: &str, b: &str) -> bool {
+    a.encode_utf16().map(upcase).eq(b.encode_utf16().map(upcase))
+}
@@ -1,6 +1,8 @@
 use core::fmt::Debug;
 use core::marker::PhantomData;
 
+use alloc::boxed::Box;
+use alloc::string::String;
 use alloc::vec::Vec;
 
 use shim::io;
@@ -9,12 +11,14 @@ use shim::newioerr;
 use shim::path;
 use shim::path::Path;
 
-use crate::mbr::MasterBootRecord;
 pub use crate::mbr::PartitionEntry;
-use crate::traits::{BlockDevice, FileSystem};
-use crate::util::SliceExt;
-use crate::vfat::{BiosParameterBlock, CachedPartition, Partition};
-use crate::vfat::{Cluster, Dir, Entry, Error, FatEntry, Status};
+use crate::partition::{partitions, PartitionInfo};
+use crate::traits::{BlockDevice, Clock, FileSystem};
+use crate::vfat::{BiosParameterBlock, CachedPartition, FsInfo, Partition, Timestamp};
+use crate::vfat::{Cluster, Dir, Entry, Error, FatEntry, FatType, Status};
+use crate::vfat::dir::{read_volume_label, volume_label_from_str, write_volume_label};
+use crate::vfat::ebpb::NO_LABEL;
+use crate::vfat::name;
 
 /// A generic trait that handles a critical section as a closure
 pub trait VFatHandle: Clone + Debug + Send + Sync {
@@ -29,59 +33,237 @@ pub struct VFat<HANDLE: VFatHandle> {
     bytes_per_sector: u32, // TODO: previously u16
     sectors_per_cluster: u8,
     sectors_per_fat: u32,
+    fat_num: u8,
+    active_fat: u8, // the FAT copy read from
     fat_start_sector: u64,
+    rootdir_start_sector: u64, // fixed root directory region of FAT12/16
+    rootdir_sectors: u64, // 0 for FAT32
     data_start_sector: u64,
     rootdir_cluster: Cluster,
+    clusters_num: u32, // number of data clusters
+    fat_type: FatType,
+    fsinfo_sector: Option<u64>,
+    free_clusters: Option<u32>, // None until counted or read from FSInfo
+    next_free: u32, // cluster to start the next allocation scan at
+    fsinfo_dirty: bool,
+    clock: Option<Box<dyn Clock>>, // timestamps are left alone without one
+    volume_dirty: bool, // the clean-shutdown bit is cleared on the disk
+    was_clean: bool,
+    had_hard_error: bool,
 }
 
-const FAT32_PARTITION_TYPE: [u8; 2] = [0xB, 0xC];
+/// Identification and usage of a mounted volume, as returned by
+/// `VFat::info()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VolumeInfo {
+    /// The volume label, or `None` if the volume has no label.
+    pub label: Option<String>,
+    /// The volume serial number, or `None` if the EBPB does not record one.
+    pub serial: Option<u32>,
+    /// The name of the tool that formatted the volume.
+    pub oem_name: String,
+    pub fat_type: FatType,
+    pub bytes_per_cluster: u64,
+    pub fat_num: u8,
+    /// The number of data clusters.
+    pub total_clusters: u32,
+    pub free_clusters: u32,
+}
+
+/// Options of `VFat::from_with_options()` and
+/// `VFat::from_partition_with_options()`.
+#[derive(Debug, Default)]
+pub struct MountOptions {
+    /// The clock to stamp the timestamps of entries with, as set by
+    /// `VFat::set_clock()`.
+    pub clock: Option<Box<dyn Clock>>,
+    /// Whether to compare the FAT copies at mount. A volume whose copies
+    /// differ fails to mount with `Error::FatMismatch`.
+    pub verify_fat_copies: bool,
+}
+
+/// Bit of the FAT32 extended flags that disables FAT mirroring.
+const EXT_FLAGS_NO_MIRRORING: u16 = 0x80;
+/// Bits of the FAT32 extended flags holding the active FAT copy if
+/// mirroring is disabled.
+const EXT_FLAGS_ACTIVE_FAT_MASK: u16 = 0x0F;
 
 impl<HANDLE: VFatHandle> VFat<HANDLE> {
-    pub fn from<T>(mut device: T) -> Result<HANDLE, Error>
+    /// Mounts the first FAT partition of `device`, found in its GPT or MBR.
+    pub fn from<T>(device: T) -> Result<HANDLE, Error>
     where
         T: BlockDevice + 'static,
     {
-        // data in partition_entry
-        let mut flag = false;
-        let mut partition_start_sector: u64 = 0;
-        let mut partition_physical_sectors_num: u64 = 0;
-        let mut bios_parameter_block: BiosParameterBlock = Default::default();
-
-        let master_boot_record = MasterBootRecord::from(&mut device)?;
-        for partition_entry in master_boot_record.partition_table.iter() {
-            // currently only able to handle fat32
-            if FAT32_PARTITION_TYPE.contains(&partition_entry.partition_type) {
-                flag = true;
-                partition_start_sector = partition_entry.relative_sector as u64;
-                partition_physical_sectors_num = partition_entry.total_sectors_in_partition as u64;
-                bios_parameter_block = BiosParameterBlock::from(&mut device, partition_entry.relative_sector as u64)?;
-                break;
-            }
-        }
+        VFat::from_with_options(device, MountOptions::default())
+    }
 
-        if !flag {
-            return Err(Error::Io(newioerr!(NotFound, "failed to find FAT32 format partition")));
-        }
+    /// Mounts the first FAT partition of `device` like `from()`, stamping the
+    /// timestamps of entries with the time of `clock`.
+    pub fn from_with_clock<T, C>(device: T, clock: C) -> Result<HANDLE, Error>
+    where
+        T: BlockDevice + 'static,
+        C: Clock + 'static,
+    {
+        let options = MountOptions { clock: Some(Box::new(clock)), ..Default::default() };
+        VFat::from_with_options(device, options)
+    }
+
+    /// Mounts the first FAT partition of `device` like `from()` with the
+    /// options `options`.
+    pub fn from_with_options<T>(mut device: T, options: MountOptions) -> Result<HANDLE, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        let partition = match partitions(&mut device)?.into_iter().find(PartitionInfo::is_fat) {
+            Some(partition) => partition,
+            None => return Err(Error::Io(newioerr!(NotFound, "failed to find FAT format partition"))),
+        };
+        VFat::from_partition_with_options(device, &partition, options)
+    }
+
+    /// Mounts the FAT volume in `partition` of `device`, as listed by
+    /// `fat32::partitions`.
+    pub fn from_partition<T>(device: T, partition: &PartitionInfo) -> Result<HANDLE, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        VFat::from_partition_with_options(device, partition, MountOptions::default())
+    }
+
+    /// Mounts the FAT volume in `partition` of `device` like
+    /// `from_partition()` with the options `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `FatMismatch` if `options.verify_fat_copies` is set and a FAT
+    /// copy differs from the active FAT.
+    pub fn from_partition_with_options<T>(
+        mut device: T,
+        partition: &PartitionInfo,
+        options: MountOptions,
+    ) -> Result<HANDLE, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        let partition_start_sector = partition.start;
+        let partition_physical_sectors_num = partition.num_sectors;
+        let bios_parameter_block = BiosParameterBlock::from(&mut device, partition_start_sector)?;
 
         let fat_start_sector = bios_parameter_block.reserved_sectors_num as u64;
         let fat_num = bios_parameter_block.fat_num as u64;
-        let sectors_per_fat = bios_parameter_block.sectors_per_fat_2;
+        let sectors_per_fat = bios_parameter_block.sectors_per_fat();
         let bytes_per_logical_sector = bios_parameter_block.bytes_per_sector as u32;
         let partition = Partition {
             start: partition_start_sector,
             num_sectors: partition_physical_sectors_num * 512 / (bytes_per_logical_sector as u64),
             sector_size: bytes_per_logical_sector as u64,
         };
-        Ok(HANDLE::new(VFat {
+        // FAT12/16 keep the root directory in a fixed region after the FATs
+        let rootdir_start_sector = fat_start_sector + fat_num * (sectors_per_fat as u64);
+        let rootdir_sectors = (bios_parameter_block.max_rootdir_entries as u64 * 32
+            + bytes_per_logical_sector as u64 - 1) / bytes_per_logical_sector as u64;
+        let data_start_sector = rootdir_start_sector + rootdir_sectors;
+        let clusters_num = (bios_parameter_block.total_sectors() as u64).saturating_sub(data_start_sector)
+            / bios_parameter_block.sectors_per_cluster as u64;
+        let fat_type = FatType::from_clusters_num(clusters_num.min(u32::max_value() as u64) as u32);
+        // the FAT may describe fewer clusters than the data region can hold
+        let clusters_num = clusters_num
+            .min((sectors_per_fat as u64 * bytes_per_logical_sector as u64 * 8 / fat_type.bits()).saturating_sub(2));
+        // a FAT32 volume may disable mirroring and keep a single FAT current
+        let ext_flags = bios_parameter_block.ext_flags;
+        let active_fat = match fat_type {
+            FatType::Fat32 if ext_flags & EXT_FLAGS_NO_MIRRORING != 0 => (ext_flags & EXT_FLAGS_ACTIVE_FAT_MASK) as u8,
+            _ => 0,
+        };
+        if active_fat >= bios_parameter_block.fat_num {
+            return Err(Error::Io(newioerr!(InvalidData, "active FAT does not exist")));
+        }
+        let rootdir_cluster = match fat_type {
+            FatType::Fat32 => Cluster::from(bios_parameter_block.rootdir_cluster),
+            _ => Cluster::from(0),
+        };
+        let mut vfat = VFat {
             phantom: PhantomData,
             device: CachedPartition::new(device, partition),
             bytes_per_sector: bytes_per_logical_sector,
             sectors_per_cluster: bios_parameter_block.sectors_per_cluster,
             sectors_per_fat: sectors_per_fat,
+            fat_num: bios_parameter_block.fat_num,
+            active_fat: active_fat,
             fat_start_sector: fat_start_sector,
-            data_start_sector: fat_start_sector + fat_num * (sectors_per_fat as u64),
-            rootdir_cluster: Cluster::from(bios_parameter_block.rootdir_cluster),
-        }))
+            rootdir_start_sector: rootdir_start_sector,
+            rootdir_sectors: rootdir_sectors,
+            data_start_sector: data_start_sector,
+            rootdir_cluster: rootdir_cluster,
+            clusters_num: clusters_num as u32,
+            fat_type: fat_type,
+            fsinfo_sector: None,
+            free_clusters: None,
+            next_free: 2,
+            fsinfo_dirty: false,
+            clock: options.clock,
+            volume_dirty: false,
+            was_clean: true,
+            had_hard_error: false,
+        };
+        // only FAT32 volumes have an FSInfo sector
+        if fat_type == FatType::Fat32 {
+            vfat.load_fsinfo(bios_parameter_block.fsinfo_sector as u64)?;
+        }
+        if let Some((clean, no_error)) = fat_type.volume_state_bits() {
+            let state = vfat.fat_copy_entry(active_fat, Cluster::from(1))?;
+            vfat.was_clean = state & clean != 0;
+            vfat.had_hard_error = state & no_error == 0;
+        }
+        if options.verify_fat_copies {
+            if let Some((copy, cluster)) = vfat.find_fat_mismatch()? {
+                return Err(Error::FatMismatch { copy, cluster: cluster.cluster_id() as u32 });
+            }
+        }
+        Ok(HANDLE::new(vfat))
+    }
+
+    /// Reads the free cluster count and the next free cluster hint from the
+    /// FSInfo sector `sector`. A missing or invalid FSInfo sector is ignored
+    /// and values that are out of range are treated as unknown.
+    fn load_fsinfo(&mut self, sector: u64) -> io::Result<()> {
+        // the FSInfo sector lies in the reserved region before the FAT
+        if sector == 0 || sector >= self.fat_start_sector {
+            return Ok(());
+        }
+        let fsinfo = match FsInfo::from_bytes(self.device.get(sector)?) {
+            Ok(fsinfo) => fsinfo,
+            Err(_) => return Ok(()),
+        };
+        self.fsinfo_sector = Some(sector);
+        if fsinfo.free_count <= self.clusters_num {
+            self.free_clusters = Some(fsinfo.free_count);
+        }
+        if fsinfo.next_free >= 2 && fsinfo.next_free < self.clusters_num + 2 {
+            self.next_free = fsinfo.next_free;
+        }
+        Ok(())
+    }
+
+    /// Writes the free cluster count and the next free cluster hint back to
+    /// the FSInfo sector if they have changed.
+    fn store_fsinfo(&mut self) -> io::Result<()> {
+        let sector = match self.fsinfo_sector {
+            Some(sector) if self.fsinfo_dirty => sector,
+            _ => return Ok(()),
+        };
+        let free_count = self.free_clusters.unwrap_or(FsInfo::UNKNOWN);
+        let next_free = self.next_free;
+        let sector_ptr = self.device.get_mut(sector)?;
+        let mut fsinfo = match FsInfo::from_bytes(sector_ptr) {
+            Ok(fsinfo) => fsinfo,
+            Err(_) => return ioerr!(InvalidData, "store_fsinfo: FSInfo signature is invalid"),
+        };
+        fsinfo.free_count = free_count;
+        fsinfo.next_free = next_free;
+        fsinfo.write_to(sector_ptr);
+        self.fsinfo_dirty = false;
+        Ok(())
     }
 
     /// Maps a cluster id to its start sector id
@@ -108,6 +290,9 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
 
     /// Read from an offset of a cluster into a buffer.
     pub fn read_cluster(&mut self, cluster: Cluster, offset: usize, buf: &mut [u8]) -> io::Result<usize> {
+        if self.is_rootdir_region(cluster) {
+            return self.read_rootdir_region(offset, buf);
+        }
         // get current cluster
         let cluster = self.cluster_by_offset(cluster, offset)?;
         let mut cluster = match cluster {
@@ -176,9 +361,44 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
         return Ok(buf_len - expected_read_size);
     }
 
+    /// Read from byte `offset` of the data beginning at `cluster` into `buf`,
+    /// assuming the clusters following `cluster` lie next to it on disk. Runs
+    /// of whole sectors are read straight into `buf`, bypassing the sector
+    /// cache; only partial sectors go through it.
+    pub fn read_contiguous(&mut self, cluster: Cluster, offset: usize, buf: &mut [u8]) -> io::Result<usize> {
+        let sector_size = self.bytes_per_sector as usize;
+        let mut sector = self.cluster_to_sector(cluster) + (offset / sector_size) as u64;
+        let mut offset_by_sector = offset % sector_size;
+        let mut read = 0;
+        while read < buf.len() {
+            let left = buf.len() - read;
+            if offset_by_sector == 0 && left >= sector_size {
+                let size = left - left % sector_size;
+                self.device.read_sectors(sector, &mut buf[read..read + size])?;
+                read += size;
+                sector += (size / sector_size) as u64;
+            } else {
+                let size = (sector_size - offset_by_sector).min(left);
+                let ptr = self.device.get(sector)?;
+                buf[read..read + size].copy_from_slice(&ptr[offset_by_sector..offset_by_sector + size]);
+                read += size;
+                sector += 1;
+                offset_by_sector = 0;
+            }
+        }
+        Ok(read)
+    }
+
     /// Read all of the clusters chained from a starting cluster
     /// into a vector.
     pub fn read_chain(&mut self, start: Cluster, buf: &mut Vec<u8>) -> io::Result<usize> {
+        if self.is_rootdir_region(start) {
+            for i in 0..self.rootdir_sectors {
+                let ptr = self.device.get(self.rootdir_start_sector + i)?;
+                buf.extend_from_slice(ptr);
+            }
+            return Ok((self.rootdir_sectors * self.bytes_per_sector as u64) as usize);
+        }
         let mut cluster = start;
         let mut read_size = 0;
         let cluster_size = self.bytes_per_cluster();
@@ -204,18 +424,460 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
         }
     }
 
-    /// Return a reference to a `FatEntry` for a cluster where the
-    /// reference points directly into a cached sector.
-    pub fn fat_entry(&mut self, cluster: Cluster) -> io::Result<&FatEntry> {
-        // calc logical sector of the fat entry
-        let sector = self.cluster_to_fat_entry_sector(cluster);
-        // calc fat_entry index
-        let index = self.cluster_to_fat_entry_sector_index(cluster);
-        // read corresponding sector of the fat entry
-        let sector_ptr = self.device.get(sector)?;
-        // cast &[u8] to &[FatEntry]
-        let sector_ptr: &[FatEntry] = unsafe { SliceExt::cast(sector_ptr) };
-        Ok(&sector_ptr[index as usize])
+    /// Write a buffer into the clusters chained from a starting cluster,
+    /// beginning at byte `offset` of the chain. New clusters are allocated
+    /// and linked into the chain when it is too short to hold the data.
+    pub fn write_cluster(&mut self, start: Cluster, offset: usize, buf: &[u8]) -> io::Result<usize> {
+        if self.is_rootdir_region(start) {
+            return self.write_rootdir_region(offset, buf);
+        }
+        let cluster_size = self.bytes_per_cluster() as usize;
+        let sector_size = self.bytes_per_sector as usize;
+
+        // forward to the cluster containing `offset`, growing the chain if needed
+        let mut cluster = start;
+        for _ in 0..offset / cluster_size {
+            cluster = self.next_or_alloc_cluster(cluster)?;
+        }
+
+        let mut offset_by_cluster = offset % cluster_size;
+        let mut written = 0;
+        while written < buf.len() {
+            if offset_by_cluster == cluster_size {
+                // current cluster is full, forward to next cluster
+                cluster = self.next_or_alloc_cluster(cluster)?;
+                offset_by_cluster = 0;
+            }
+            let sector = self.cluster_to_sector(cluster) + (offset_by_cluster / sector_size) as u64;
+            let offset_by_sector = offset_by_cluster % sector_size;
+            let size = (sector_size - offset_by_sector).min(buf.len() - written);
+            let ptr = self.sector_mut(sector)?;
+            ptr[offset_by_sector..offset_by_sector + size].copy_from_slice(&buf[written..written + size]);
+            written += size;
+            offset_by_cluster += size;
+        }
+        Ok(written)
+    }
+
+    /// Return the cluster following `cluster` in its chain, or `None` if
+    /// `cluster` is the last one.
+    pub fn next_cluster(&mut self, cluster: Cluster) -> io::Result<Option<Cluster>> {
+        match self.fat_entry(cluster)?.status() {
+            Status::Data(next_cluster) => Ok(Some(next_cluster)),
+            Status::Eoc(_) => Ok(None),
+            Status::Bad => ioerr!(InvalidData, "next_cluster: next cluster is bad"),
+            Status::Reserved => ioerr!(InvalidData, "next_cluster: next cluster is reserved"),
+            Status::Free => ioerr!(InvalidData, "next_cluster: next cluster is free"),
+        }
+    }
+
+    /// Return the cluster following `cluster`, appending a newly allocated
+    /// cluster to the chain if `cluster` is the last one.
+    fn next_or_alloc_cluster(&mut self, cluster: Cluster) -> io::Result<Cluster> {
+        match self.next_cluster(cluster)? {
+            Some(next_cluster) => Ok(next_cluster),
+            None => self.alloc_cluster(Some(cluster)),
+        }
+    }
+
+    /// Allocate a free cluster and mark it as the end of a chain. If `prev`
+    /// is given, the new cluster is linked after it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `Other` if there is no free cluster left.
+    pub fn alloc_cluster(&mut self, prev: Option<Cluster>) -> io::Result<Cluster> {
+        if self.free_clusters == Some(0) {
+            return ioerr!(Other, "alloc_cluster: no free cluster left");
+        }
+        // scan from the next free cluster hint, wrapping around once
+        let end = self.clusters_num + 2;
+        let mut free_cluster = None;
+        for id in (self.next_free..end).chain(2..self.next_free) {
+            let cluster = Cluster::from(id);
+            if self.fat_entry(cluster)?.status() == Status::Free {
+                free_cluster = Some(cluster);
+                break;
+            }
+        }
+        let cluster = match free_cluster {
+            Some(cluster) => cluster,
+            None => {
+                self.free_clusters = Some(0);
+                self.fsinfo_dirty = true;
+                return ioerr!(Other, "alloc_cluster: no free cluster left");
+            },
+        };
+
+        self.set_fat_status(cluster, Status::Eoc(0x0FFFFFFF))?;
+        if let Some(prev) = prev {
+            self.set_fat_status(prev, Status::Data(cluster))?;
+        }
+        self.free_clusters = self.free_clusters.map(|n| n.saturating_sub(1));
+        self.next_free = if cluster.cluster_id() as u32 + 1 < end {
+            cluster.cluster_id() as u32 + 1
+        } else {
+            2
+        };
+        self.fsinfo_dirty = true;
+        Ok(cluster)
+    }
+
+    /// Mark every cluster of the chain beginning at `start` as free.
+    pub fn free_chain(&mut self, start: Cluster) -> io::Result<()> {
+        let mut cluster = Some(start);
+        while let Some(cur) = cluster {
+            cluster = self.next_cluster(cur)?;
+            self.set_fat_status(cur, Status::Free)?;
+            self.free_clusters = self.free_clusters.map(|n| n + 1);
+            // prefer reusing the lowest freed cluster
+            self.next_free = self.next_free.min(cur.cluster_id() as u32);
+        }
+        self.fsinfo_dirty = true;
+        Ok(())
+    }
+
+    /// Mark `last` as the end of its chain and free the clusters that
+    /// followed it.
+    pub fn truncate_chain(&mut self, last: Cluster) -> io::Result<()> {
+        let next = self.next_cluster(last)?;
+        self.set_fat_status(last, Status::Eoc(0x0FFFFFFF))?;
+        if let Some(next) = next {
+            self.free_chain(next)?;
+        }
+        Ok(())
+    }
+
+    /// Return the number of free bytes in the volume. The free clusters are
+    /// counted once if the FSInfo sector does not provide their number.
+    pub fn free_space(&mut self) -> io::Result<u64> {
+        Ok(self.free_clusters()? as u64 * self.bytes_per_cluster())
+    }
+
+    /// Return the number of free data clusters, counted as in `free_space()`.
+    pub fn free_clusters(&mut self) -> io::Result<u32> {
+        let free_clusters = match self.free_clusters {
+            Some(free_clusters) => free_clusters,
+            None => {
+                let mut free_clusters = 0;
+                for id in 2..(self.clusters_num + 2) {
+                    if self.fat_entry(Cluster::from(id))?.status() == Status::Free {
+                        free_clusters += 1;
+                    }
+                }
+                self.free_clusters = Some(free_clusters);
+                self.fsinfo_dirty = true;
+                free_clusters
+            },
+        };
+        Ok(free_clusters)
+    }
+
+    /// Return the label, serial number, geometry and usage of the volume.
+    /// The label in the root directory takes precedence over the one in the
+    /// EBPB.
+    pub fn info(&mut self) -> io::Result<VolumeInfo> {
+        let ebpb = self.read_ebpb()?;
+        let label = match read_volume_label(self)? {
+            Some(label) => Some(label),
+            None => ebpb.volume_label(),
+        };
+        let label = label
+            .filter(|&label| label != NO_LABEL)
+            .map(|label| name::decode_cp437(&label).trim_end().into());
+        Ok(VolumeInfo {
+            label,
+            serial: ebpb.volume_id(),
+            oem_name: String::from_utf8_lossy(&ebpb.oem_name()).trim_end().into(),
+            fat_type: self.fat_type,
+            bytes_per_cluster: self.bytes_per_cluster(),
+            fat_num: self.fat_num,
+            total_clusters: self.clusters_num,
+            free_clusters: self.free_clusters()?,
+        })
+    }
+
+    /// Set the volume label to `label`, converted to uppercase, or remove
+    /// the label if `label` is empty. The label is written to the EBPB, its
+    /// backup and the volume-ID entry of the root directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `label` is longer than 11
+    /// characters or contains characters that a short name cannot hold.
+    pub fn set_label(&mut self, label: &str) -> io::Result<()> {
+        let label = volume_label_from_str(label)?;
+        let mut ebpb = self.read_ebpb()?;
+        if ebpb.set_volume_label(label.unwrap_or(NO_LABEL)) {
+            let backup_sector = ebpb.backup_boot_sector as u64;
+            self.sector_mut(0)?[..512].copy_from_slice(&ebpb.to_bytes());
+            if self.fat_type == FatType::Fat32 && backup_sector != 0 && backup_sector < self.fat_start_sector {
+                self.sector_mut(backup_sector)?[..512].copy_from_slice(&ebpb.to_bytes());
+            }
+        }
+        write_volume_label(self, label)
+    }
+
+    /// Reads the EBPB from the first sector of the volume.
+    fn read_ebpb(&mut self) -> io::Result<BiosParameterBlock> {
+        Ok(BiosParameterBlock::from_bytes(self.device.get(0)?))
+    }
+
+    /// Return the number of bytes in the data region of the volume.
+    pub fn total_space(&self) -> u64 {
+        self.clusters_num as u64 * self.bytes_per_cluster()
+    }
+
+    /// Write all of the modified sectors back to the disk, then mark the
+    /// volume as cleanly unmounted.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.store_fsinfo()?;
+        self.device.sync()?;
+        // the volume is only clean once everything else is on the disk
+        if self.volume_dirty {
+            self.set_clean_bit(true)?;
+            self.device.sync()?;
+            self.volume_dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Return a mutable reference to the cached sector `sector`, marking the
+    /// volume dirty first if this is the first modification since it was
+    /// mounted or flushed.
+    ///
+    /// If the dirty mark cannot be written, the volume is left as it was,
+    /// so that a device refusing writes can still be read.
+    fn sector_mut(&mut self, sector: u64) -> io::Result<&mut [u8]> {
+        if !self.volume_dirty {
+            // set first: clearing the bit modifies a sector too
+            self.volume_dirty = true;
+            let result = self.set_clean_bit(false).and_then(|_| self.device.sync());
+            if let Err(e) = result {
+                // nothing else is dirty before the first modification; a
+                // copy that did reach the disk stays marked dirty, which
+                // errs on the safe side
+                for copy in 0..self.fat_num {
+                    let (fat_sector, _) = self.fat_byte_position(copy, 0);
+                    let _ = self.device.revert(fat_sector);
+                }
+                self.volume_dirty = false;
+                return Err(e);
+            }
+        }
+        self.device.get_mut(sector)
+    }
+
+    /// Set or clear the clean-shutdown bit in FAT[1] of every FAT copy,
+    /// leaving the hard error bit alone. FAT12 has no such bit.
+    fn set_clean_bit(&mut self, clean: bool) -> io::Result<()> {
+        let bit = match self.fat_type.volume_state_bits() {
+            Some((bit, _)) => bit,
+            None => return Ok(()),
+        };
+        for copy in 0..self.fat_num {
+            let state = self.fat_copy_entry(copy, Cluster::from(1))?;
+            let state = if clean { state | bit } else { state & !bit };
+            self.set_fat_copy_entry(copy, Cluster::from(1), state)?;
+        }
+        Ok(())
+    }
+
+    /// Return whether the volume was cleanly unmounted, i.e. flushed after
+    /// its last modification, before it was mounted. A volume that was not
+    /// may need a consistency check. FAT12 volumes always report `true`.
+    pub fn was_clean(&self) -> bool {
+        self.was_clean
+    }
+
+    /// Return whether FAT[1] recorded a disk error when the volume was
+    /// mounted. FAT12 volumes always report `false`.
+    pub fn had_hard_error(&self) -> bool {
+        self.had_hard_error
+    }
+
+    /// Return the `FatEntry` of a cluster in the active FAT.
+    pub fn fat_entry(&mut self, cluster: Cluster) -> io::Result<FatEntry> {
+        Ok(FatEntry(self.fat_copy_entry(self.active_fat, cluster)?, self.fat_type))
+    }
+
+    /// Set the FAT entry of `cluster` to `status` in every copy of the FAT.
+    /// The copies are kept in step even if the volume disables mirroring.
+    pub fn set_fat_status(&mut self, cluster: Cluster, status: Status) -> io::Result<()> {
+        for i in 0..self.fat_num {
+            let mut fat_entry = FatEntry(self.fat_copy_entry(i, cluster)?, self.fat_type);
+            fat_entry.set_status(status);
+            self.set_fat_copy_entry(i, cluster, fat_entry.0)?;
+        }
+        Ok(())
+    }
+
+    /// Return the raw value of the FAT entry of `cluster` in the FAT copy
+    /// `copy`, where copy 0 is the first FAT.
+    pub(crate) fn fat_copy_entry(&mut self, copy: u8, cluster: Cluster) -> io::Result<u32> {
+        let id = cluster.cluster_id();
+        match self.fat_type {
+            FatType::Fat32 => self.read_fat_bytes(copy, id * 4, 4),
+            FatType::Fat16 => self.read_fat_bytes(copy, id * 2, 2),
+            // two 12-bit entries are packed into three bytes
+            FatType::Fat12 => {
+                let value = self.read_fat_bytes(copy, id + id / 2, 2)?;
+                Ok(if id % 2 == 1 { value >> 4 } else { value & 0xFFF })
+            }
+        }
+    }
+
+    /// Overwrite the FAT entry of `cluster` in the FAT copy `copy` with the
+    /// raw value `value`.
+    pub(crate) fn set_fat_copy_entry(&mut self, copy: u8, cluster: Cluster, value: u32) -> io::Result<()> {
+        let id = cluster.cluster_id();
+        match self.fat_type {
+            FatType::Fat32 => self.write_fat_bytes(copy, id * 4, 4, value),
+            FatType::Fat16 => self.write_fat_bytes(copy, id * 2, 2, value),
+            FatType::Fat12 => {
+                // keep the nibble shared with the neighbouring entry
+                let offset = id + id / 2;
+                let old = self.read_fat_bytes(copy, offset, 2)?;
+                let value = if id % 2 == 1 {
+                    (old & 0x000F) | ((value & 0xFFF) << 4)
+                } else {
+                    (old & 0xF000) | (value & 0xFFF)
+                };
+                self.write_fat_bytes(copy, offset, 2, value)
+            }
+        }
+    }
+
+    /// Return the first FAT copy and cluster whose entry differs from the one
+    /// in the active FAT, ignoring the reserved high bits of FAT32 entries.
+    /// Only the sectors that differ are compared entry by entry.
+    pub(crate) fn find_fat_mismatch(&mut self) -> io::Result<Option<(u8, Cluster)>> {
+        let sector_bits = self.bytes_per_sector as u64 * 8;
+        let bits = self.fat_type.bits();
+        let entries_end = self.clusters_num as u64 + 2;
+        let sectors_num = (entries_end * bits + sector_bits - 1) / sector_bits;
+        let mask = self.fat_type.mask();
+        let active_fat = self.active_fat;
+        for copy in (0..self.fat_num).filter(|&copy| copy != active_fat) {
+            for index in 0..sectors_num {
+                let active = self.fat_start_sector + active_fat as u64 * self.sectors_per_fat as u64 + index;
+                let other = self.fat_start_sector + copy as u64 * self.sectors_per_fat as u64 + index;
+                let active_sector = self.device.get(active)?.to_vec();
+                if active_sector[..] == self.device.get(other)?[..] {
+                    continue;
+                }
+                let first = index * sector_bits / bits;
+                let end = ((index + 1) * sector_bits + bits - 1) / bits;
+                for id in first..end.min(entries_end) {
+                    let cluster = Cluster::from(id as u32);
+                    let expected = self.fat_copy_entry(active_fat, cluster)?;
+                    if (expected ^ self.fat_copy_entry(copy, cluster)?) & mask != 0 {
+                        return Ok(Some((copy, cluster)));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Read `len` bytes at byte `offset` of the FAT copy `copy` as a little
+    /// endian integer. The bytes may span two sectors.
+    fn read_fat_bytes(&mut self, copy: u8, offset: u64, len: usize) -> io::Result<u32> {
+        let mut bytes = [0u8; 4];
+        for (i, byte) in bytes[..len].iter_mut().enumerate() {
+            let (sector, index) = self.fat_byte_position(copy, offset + i as u64);
+            *byte = self.device.get(sector)?[index];
+        }
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    /// Write the low `len` bytes of `value` at byte `offset` of the FAT copy
+    /// `copy` in little endian order.
+    fn write_fat_bytes(&mut self, copy: u8, offset: u64, len: usize, value: u32) -> io::Result<()> {
+        for (i, byte) in value.to_le_bytes()[..len].iter().enumerate() {
+            let (sector, index) = self.fat_byte_position(copy, offset + i as u64);
+            self.sector_mut(sector)?[index] = *byte;
+        }
+        Ok(())
+    }
+
+    /// Return the sector and the index in that sector of byte `offset` of
+    /// the FAT copy `copy`.
+    fn fat_byte_position(&self, copy: u8, offset: u64) -> (u64, usize) {
+        let sector = self.fat_start_sector
+            + copy as u64 * self.sectors_per_fat as u64
+            + offset / self.bytes_per_sector as u64;
+        (sector, (offset % self.bytes_per_sector as u64) as usize)
+    }
+
+    /// Return whether `cluster` refers to the fixed root directory region of
+    /// a FAT12/16 volume rather than to a cluster chain.
+    pub(crate) fn is_rootdir_region(&self, cluster: Cluster) -> bool {
+        self.rootdir_sectors > 0 && cluster.cluster_id() == 0
+    }
+
+    /// Return the size in bytes of the fixed root directory region, or 0 on
+    /// FAT32 where the root directory is a cluster chain.
+    pub(crate) fn rootdir_region_size(&self) -> u64 {
+        self.rootdir_sectors * self.bytes_per_sector as u64
+    }
+
+    /// Read from an offset of the fixed root directory region into a buffer.
+    fn read_rootdir_region(&mut self, offset: usize, buf: &mut [u8]) -> io::Result<usize> {
+        let region_size = self.rootdir_region_size() as usize;
+        let len = buf.len().min(region_size.saturating_sub(offset));
+        let sector_size = self.bytes_per_sector as usize;
+        let mut read = 0;
+        while read < len {
+            let sector = self.rootdir_start_sector + ((offset + read) / sector_size) as u64;
+            let offset_by_sector = (offset + read) % sector_size;
+            let size = (sector_size - offset_by_sector).min(len - read);
+            let ptr = self.device.get(sector)?;
+            buf[read..read + size].copy_from_slice(&ptr[offset_by_sector..offset_by_sector + size]);
+            read += size;
+        }
+        Ok(read)
+    }
+
+    /// Write a buffer at an offset of the fixed root directory region. The
+    /// region cannot grow, so writing past its end fails.
+    fn write_rootdir_region(&mut self, offset: usize, buf: &[u8]) -> io::Result<usize> {
+        if offset + buf.len() > self.rootdir_region_size() as usize {
+            return ioerr!(Other, "root directory is full");
+        }
+        let sector_size = self.bytes_per_sector as usize;
+        let mut written = 0;
+        while written < buf.len() {
+            let sector = self.rootdir_start_sector + ((offset + written) / sector_size) as u64;
+            let offset_by_sector = (offset + written) % sector_size;
+            let size = (sector_size - offset_by_sector).min(buf.len() - written);
+            let ptr = self.sector_mut(sector)?;
+            ptr[offset_by_sector..offset_by_sector + size].copy_from_slice(&buf[written..written + size]);
+            written += size;
+        }
+        Ok(written)
+    }
+
+    /// Forget the free cluster count and count the free clusters again.
+    pub(crate) fn recount_free_space(&mut self) -> io::Result<u64> {
+        self.free_clusters = None;
+        self.free_space()
+    }
+
+    /// Return the number of copies of the FAT
+    pub fn fat_num(&self) -> u8 {
+        self.fat_num
+    }
+
+    /// Return the FAT copy that is read from. It is the first copy unless a
+    /// FAT32 volume disables mirroring.
+    pub fn active_fat(&self) -> u8 {
+        self.active_fat
+    }
+
+    /// Return the number of data clusters, numbered from 2
+    pub fn clusters_num(&self) -> u32 {
+        self.clusters_num
     }
 
     /// Return bytes per cluster
@@ -228,14 +890,27 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
         self.rootdir_cluster
     }
 
-    /// Return cluster corresponding fat entry sector
-    pub fn cluster_to_fat_entry_sector(&self, cluster: Cluster) -> u64 {
-        self.fat_start_sector + (cluster.cluster_id() << 2) / (self.bytes_per_sector as u64)
+    /// Set the number of sectors read ahead on sequential cache misses
+    pub fn set_readahead(&mut self, sectors: u64) {
+        self.device.set_readahead(sectors);
+    }
+
+    /// Stamp the timestamps of entries with the time of `clock` from now on.
+    /// Created entries record their creation time, writes their modification
+    /// time and reads and writes their access date.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Some(Box::new(clock));
+    }
+
+    /// Return the current time of the clock and the hundredths of a second
+    /// it lies past it, or `None` if the volume has no clock.
+    pub(crate) fn now(&self) -> Option<(Timestamp, u8)> {
+        self.clock.as_ref().map(|clock| Timestamp::from_unix_precise(clock.now()))
     }
 
-    /// Return cluster corresponding fat entry index in its sector
-    pub fn cluster_to_fat_entry_sector_index(&self, cluster: Cluster) -> u64 {
-        cluster.cluster_id() % ((self.bytes_per_sector >> 2) as u64)
+    /// Return the FAT type of the volume
+    pub fn fat_type(&self) -> FatType {
+        self.fat_type
     }
 }
 
@@ -0,0 +1,115 @@
+//! The arguments and the environment a program is started with.
+//!
+//! The kernel starts a program, whether loaded by the kernel or through the
+//! `exec` system call, with the following state:
+//!
+//! - `x0` holds `argc`, the number of arguments.
+//! - `x1` holds `argv`, the address of an array of `argc` pointers to the
+//!   arguments followed by a null pointer. The first argument is the path of
+//!   the program.
+//! - `x2` holds `envp`, the address of a null terminated array of pointers
+//!   to the environment variables, each of the form `KEY=VALUE`.
+//! - `sp` is 16 byte aligned and points to `argc`, stored as a 64-bit value
+//!   right below `argv`, which is itself followed by `envp`.
+//!
+//! The strings are UTF-8 encoded, terminated by a NUL byte and stored above
+//! `envp`, at the top of the stack:
+//!
+//! ```text
+//!          +--------------------+  top of the stack
+//!          | strings            |
+//!          +--------------------+
+//!          | NULL               |
+//!          | envp[..]           |
+//!   envp ->| envp[0]            |
+//!          | NULL               |
+//!          | argv[argc - 1]     |
+//!          | argv[..]           |
+//!   argv ->| argv[0]            |
+//!     sp ->| argc               |
+//!          +--------------------+
+//! ```
+
+use core::slice;
+use core::str;
+
+/// An iterator over a null terminated array of pointers to NUL terminated
+/// strings, such as the arguments of a program.
+#[derive(Clone, Copy, Debug)]
+pub struct Args {
+    ptrs: *const *const u8,
+}
+
+impl Args {
+    /// Returns an iterator over the strings of the array at `ptrs`, which may
+    /// be null.
+    ///
+    /// # Safety
+    ///
+    /// `ptrs` must be null or point to a null terminated array of pointers
+    /// to NUL terminated strings that live as long as the program, such as
+    /// the `argv` and `envp` a program is started with.
+    pub unsafe fn from_raw(ptrs: *const *const u8) -> Args {
+        Args { ptrs }
+    }
+}
+
+impl Iterator for Args {
+    type Item = &'static str;
+
+    /// Returns the next string. A string that is not valid UTF-8 is returned
+    /// as an empty string.
+    fn next(&mut self) -> Option<&'static str> {
+        if self.ptrs.is_null() {
+            return None;
+        }
+        unsafe {
+            let ptr = *self.ptrs;
+            if ptr.is_null() {
+                return None;
+            }
+            self.ptrs = self.ptrs.add(1);
+            let mut len = 0;
+            while *ptr.add(len) != 0 {
+                len += 1;
+            }
+            Some(str::from_utf8(slice::from_raw_parts(ptr, len)).unwrap_or(""))
+        }
+    }
+}
+
+/// An iterator over the environment variables of a program, yielding the
+/// key and the value of each variable. A variable without `=` has an empty
+/// value.
+#[derive(Clone, Copy, Debug)]
+pub struct Vars {
+    vars: Args,
+}
+
+impl Vars {
+    /// Returns an iterator over the environment variables at `envp`.
+    ///
+    /// # Safety
+    ///
+    /// The same as for `Args::from_raw()`.
+    pub unsafe fn from_raw(envp: *const *const u8) -> Vars {
+        Vars { vars: Args::from_raw(envp) }
+    }
+
+    /// Returns the value of the variable `key`, if it is set.
+    pub fn get(self, key: &str) -> Option<&'static str> {
+        self.filter(|&(k, _)| k == key).map(|(_, value)| value).next()
+    }
+}
+
+impl Iterator for Vars {
+    type Item = (&'static str, &'static str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let var = self.vars.next()?;
+        Some(match var.find('=') {
+            Some(i) => (&var[..i], &var[i + 1..]),
+            None => (var, ""),
+        })
+    }
+}
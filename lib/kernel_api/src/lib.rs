@@ -12,6 +12,7 @@ use core::fmt;
 
 use shim::io;
 
+pub mod env;
 // TODO: #[cfg(feature = "user-space")]
 pub mod syscall;
 
@@ -40,7 +41,11 @@ pub enum OsError {
     IllegalSocketOperation = 201,
     InvalidPort = 202,
 
+    InvalidFile = 210,
+    TooManyOpenFiles = 211,
+
     IdOverflow = 300,
+    NoChild = 310,
 
     MailboxError = 400,
     MailboxFailed = 401,
@@ -67,7 +72,11 @@ impl core::convert::From<u64> for OsError {
             200 => OsError::InvalidSocket,
             201 => OsError::IllegalSocketOperation,
 
+            210 => OsError::InvalidFile,
+            211 => OsError::TooManyOpenFiles,
+
             300 => OsError::IdOverflow,
+            310 => OsError::NoChild,
             _ => OsError::Unknown,
         }
     }
@@ -81,6 +90,7 @@ impl core::convert::From<io::Error> for OsError {
             io::ErrorKind::InvalidInput => OsError::IoErrorInvalidInput,
             io::ErrorKind::TimedOut => OsError::IoErrorTimedOut,
             io::ErrorKind::NotFound => OsError::NoEntry,
+            io::ErrorKind::PermissionDenied => OsError::NoAccess,
             _ => OsError::IoError,
         }
     }
@@ -97,13 +107,16 @@ pub const NR_FORK: usize = 6;
 pub const NR_YIELD: usize = 7;
 pub const NR_READ: usize = 8;
 pub const NR_GETCWD: usize = 9;
-// TODO: unimplemented
 pub const NR_CHCWD: usize = 10;
 pub const NR_OPEN: usize = 11;
 pub const NR_GETDENTS: usize = 12;
 pub const NR_EXEC: usize = 13;
 pub const NR_WRITE_STR: usize = 14;
 pub const NR_GETPRIORITY: usize = 15;
+pub const NR_CLOSE: usize = 16;
+pub const NR_READFILE: usize = 17;
+pub const NR_WRITEFILE: usize = 18;
+pub const NR_LSEEK: usize = 19;
 // TODO: socket related
 pub const NR_SOCK_CREATE: usize = 20;
 pub const NR_SOCK_STATUS: usize = 21;
@@ -111,6 +124,181 @@ pub const NR_SOCK_CONNECT: usize = 22;
 pub const NR_SOCK_LISTEN: usize = 23;
 pub const NR_SOCK_SEND: usize = 24;
 pub const NR_SOCK_RECV: usize = 25;
+pub const NR_WAITPID: usize = 26;
+
+// flags of `open()`, one of the access modes or'ed with the other flags
+
+pub const O_RDONLY: u64 = 0;
+pub const O_WRONLY: u64 = 1;
+pub const O_RDWR: u64 = 2;
+pub const O_ACCMODE: u64 = 3;
+/// Creates the file if it does not exist.
+pub const O_CREAT: u64 = 0o100;
+/// Truncates a regular file opened for writing to length 0.
+pub const O_TRUNC: u64 = 0o1000;
+/// Moves to the end of the file before every write.
+pub const O_APPEND: u64 = 0o2000;
+
+/// Makes `waitpid()` return immediately if no matching child has exited.
+pub const WNOHANG: u64 = 1;
+
+// `whence` of the `lseek` system call
+
+pub const SEEK_SET: u64 = 0;
+pub const SEEK_CUR: u64 = 1;
+pub const SEEK_END: u64 = 2;
+
+#[derive(Clone, Copy, Debug)]
+pub struct FileDescriptor(u64);
+
+impl FileDescriptor {
+    pub fn raw(&self) -> u64 {
+        self.0
+    }
+}
+
+/// The maximum number of arguments, and of environment variables, passed to
+/// the `exec` system call.
+pub const EXEC_MAX_ARGS: usize = 32;
+
+/// The size of the fixed part of a `getdents` record.
+pub const DIRENT_SIZE: usize = 40;
+
+// `kind` of a `Dirent`
+
+pub const DIRENT_FILE: u8 = 1;
+pub const DIRENT_DIR: u8 = 2;
+
+// `flags` of a `Dirent`
+
+pub const DIRENT_READ_ONLY: u8 = 0x01;
+pub const DIRENT_HIDDEN: u8 = 0x02;
+
+/// The fixed part of a directory entry record written by the `getdents`
+/// system call. A record is this header followed by the `name_len` bytes of
+/// the UTF-8 name and zero padding up to `record_len`, a multiple of 8. The
+/// header is stored in little endian with this layout:
+///
+/// | offset | size | field        |
+/// |--------|------|--------------|
+/// | 0      | 2    | `record_len` |
+/// | 2      | 2    | `name_len`   |
+/// | 4      | 1    | `kind`       |
+/// | 5      | 1    | `flags`      |
+/// | 6      | 2    | reserved, 0  |
+/// | 8      | 8    | `size`       |
+/// | 16     | 8    | `created`    |
+/// | 24     | 8    | `modified`   |
+/// | 32     | 8    | `accessed`   |
+///
+/// Timestamps are in seconds since the Unix epoch.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Dirent {
+    pub record_len: u16,
+    pub name_len: u16,
+    /// `DIRENT_FILE` or `DIRENT_DIR`.
+    pub kind: u8,
+    /// `DIRENT_READ_ONLY` and `DIRENT_HIDDEN` or'ed together.
+    pub flags: u8,
+    /// The size of a file in bytes, 0 for a directory.
+    pub size: u64,
+    pub created: u64,
+    pub modified: u64,
+    pub accessed: u64,
+}
+
+impl Dirent {
+    /// Returns the header of the record of an entry named `name`, with
+    /// `record_len` and `name_len` set.
+    pub fn new(name: &str, kind: u8, flags: u8, size: u64) -> Dirent {
+        Dirent {
+            record_len: ((DIRENT_SIZE + name.len() + 7) & !7) as u16,
+            name_len: name.len() as u16,
+            kind,
+            flags,
+            size,
+            ..Default::default()
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; DIRENT_SIZE] {
+        let mut bytes = [0u8; DIRENT_SIZE];
+        bytes[0..2].copy_from_slice(&self.record_len.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.name_len.to_le_bytes());
+        bytes[4] = self.kind;
+        bytes[5] = self.flags;
+        bytes[8..16].copy_from_slice(&self.size.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.created.to_le_bytes());
+        bytes[24..32].copy_from_slice(&self.modified.to_le_bytes());
+        bytes[32..40].copy_from_slice(&self.accessed.to_le_bytes());
+        bytes
+    }
+
+    /// Reads a header from the first `DIRENT_SIZE` bytes of `bytes`. Returns
+    /// `None` if `bytes` is shorter.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Dirent> {
+        if bytes.len() < DIRENT_SIZE {
+            return None;
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let u64_at = |i: usize| {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(&bytes[i..i + 8]);
+            u64::from_le_bytes(buf)
+        };
+        Some(Dirent {
+            record_len: u16_at(0),
+            name_len: u16_at(2),
+            kind: bytes[4],
+            flags: bytes[5],
+            size: u64_at(8),
+            created: u64_at(16),
+            modified: u64_at(24),
+            accessed: u64_at(32),
+        })
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == DIRENT_DIR
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.flags & DIRENT_READ_ONLY != 0
+    }
+
+    pub fn hidden(&self) -> bool {
+        self.flags & DIRENT_HIDDEN != 0
+    }
+}
+
+/// An iterator over the records written to a buffer by `getdents`, yielding
+/// each header with its name.
+pub struct Dirents<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Dirents<'a> {
+    pub fn new(buf: &'a [u8]) -> Dirents<'a> {
+        Dirents { buf }
+    }
+}
+
+impl<'a> Iterator for Dirents<'a> {
+    type Item = (Dirent, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let dirent = Dirent::from_bytes(self.buf)?;
+        let record_len = (dirent.record_len as usize).min(self.buf.len());
+        if record_len < DIRENT_SIZE {
+            return None;
+        }
+        let name_end = (DIRENT_SIZE + dirent.name_len as usize).min(record_len);
+        let name = core::str::from_utf8(&self.buf[DIRENT_SIZE..name_end]).unwrap_or("");
+        self.buf = &self.buf[record_len..];
+        Some((dirent, name))
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct SocketDescriptor(u64);
 
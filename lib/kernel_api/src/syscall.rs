@@ -2,6 +2,8 @@ use core::fmt;
 use core::fmt::Write;
 use core::time::Duration;
 
+use shim::io::SeekFrom;
+
 use crate::*;
 
 macro_rules! err_or {
@@ -53,15 +55,46 @@ pub fn time() -> Duration {
     Duration::new(time_secs, subsec_nanos)
 }
 
-pub fn exit() -> ! {
+/// Terminates the process with the exit code `code`, which its parent
+/// receives from `waitpid()`.
+pub fn exit(code: i32) -> ! {
     unsafe {
-        asm!("svc $0"
-            :: "i"(NR_EXIT)
-            :: "volatile");
+        asm!("mov x0, $0
+              svc $1"
+            :: "r"(code as u64), "i"(NR_EXIT)
+            : "x0"
+            : "volatile");
     }
     unreachable!()
 }
 
+/// Waits for the child `pid` to exit, or for any child if `pid` is `None`,
+/// and returns its id and exit code. `options` is 0 or `WNOHANG`, with which
+/// `None` is returned right away if the matching children are all alive.
+///
+/// Returns `NoChild` if the process has no matching child. A child that
+/// exited is reaped by the first call returning it.
+pub fn waitpid(pid: Option<u64>, options: u64) -> OsResult<Option<(u64, i32)>> {
+    let id: u64;
+    let code: u64;
+    let reaped: u64;
+    let ecode: u64;
+    unsafe {
+        asm!("mov x0, $4
+              mov x1, $5
+              svc $6
+              mov $0, x0
+              mov $1, x1
+              mov $2, x2
+              mov $3, x7"
+            : "=r"(id), "=r"(code), "=r"(reaped), "=r"(ecode)
+            : "r"(pid.unwrap_or(core::u64::MAX)), "r"(options), "i"(NR_WAITPID)
+            : "x0", "x1", "x2", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, if reaped == 1 { Some((id, code as i32)) } else { None })
+}
+
 pub fn write(b: u8) {
     unsafe {
         asm!("mov x0, $0
@@ -134,15 +167,62 @@ pub fn r#yield() {
     }
 }
 
-pub fn getcwd(buf: &mut [u8], size: usize) {
+/// Writes the current working directory to `buf`, truncated to its length,
+/// and returns the full length of the working directory.
+pub fn getcwd(buf: &mut [u8]) -> OsResult<usize> {
+    let len: usize;
+    let ecode: u64;
     unsafe {
-        asm!("mov x0, $0
-              mov x1, $1
-              svc $2"
-            :: "r"(buf.as_ptr()), "r"(size), "i"(NR_GETCWD)
-            :  "x0", "x1"
-            :  "volatile")
+        asm!("mov x0, $2
+              mov x1, $3
+              svc $4
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(len), "=r"(ecode)
+            : "r"(buf.as_mut_ptr()), "r"(buf.len()), "i"(NR_GETCWD)
+            : "x0", "x1", "x7"
+            : "volatile");
     }
+    err_or!(ecode, len)
+}
+
+/// Changes the current working directory to the directory at `path`,
+/// relative to the current one unless it is absolute.
+pub fn chdir(path: &str) -> OsResult<()> {
+    let ecode: u64;
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              svc $3
+              mov $0, x7"
+            : "=r"(ecode)
+            : "r"(path.as_ptr()), "r"(path.len()), "i"(NR_CHCWD)
+            : "x0", "x1", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, ())
+}
+
+/// Reads the next entries of the directory `descriptor` into `buf` as
+/// records laid out as described by `Dirent`, and returns the number of
+/// bytes written: 0 once every entry has been read. `Dirents` iterates over
+/// the records.
+pub fn getdents(descriptor: FileDescriptor, buf: &mut [u8]) -> OsResult<usize> {
+    let written: usize;
+    let ecode: u64;
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(written), "=r"(ecode)
+            : "r"(descriptor.raw()), "r"(buf.as_mut_ptr()), "r"(buf.len()), "i"(NR_GETDENTS)
+            : "x0", "x1", "x2", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, written)
 }
 
 pub fn brk() {
@@ -163,6 +243,148 @@ pub fn write_str(msg: &str) {
     }
 }
 
+/// Replaces the running program with the program at `path`, relative to the
+/// current working directory unless it is absolute, started with the
+/// arguments `args` and the environment variables `env` as described in
+/// `env`. By convention, the first argument is the path of the program.
+///
+/// Open files and the working directory are kept. Only returns if the
+/// program cannot be started, with the error, which is `InvalidArgument` if
+/// there are more than `EXEC_MAX_ARGS` arguments or variables.
+pub fn exec(path: &str, args: &[&str], env: &[&str]) -> OsError {
+    // the strings are passed as arrays of (address, length) pairs
+    let mut raw_args = [[0u64; 2]; EXEC_MAX_ARGS];
+    let mut raw_env = [[0u64; 2]; EXEC_MAX_ARGS];
+    if args.len() > EXEC_MAX_ARGS || env.len() > EXEC_MAX_ARGS {
+        return OsError::InvalidArgument;
+    }
+    for (raw, s) in raw_args.iter_mut().zip(args).chain(raw_env.iter_mut().zip(env)) {
+        *raw = [s.as_ptr() as u64, s.len() as u64];
+    }
+
+    let ecode: u64;
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              mov x2, $3
+              mov x3, $4
+              mov x4, $5
+              mov x5, $6
+              svc $7
+              mov $0, x7"
+            : "=r"(ecode)
+            : "r"(path.as_ptr()), "r"(path.len()),
+              "r"(raw_args.as_ptr()), "r"(args.len()),
+              "r"(raw_env.as_ptr()), "r"(env.len()), "i"(NR_EXEC)
+            : "x0", "x1", "x2", "x3", "x4", "x5", "x7"
+            : "volatile");
+    }
+    OsError::from(ecode)
+}
+
+/// Opens the file or directory at `path`, relative to the current working
+/// directory unless it is absolute. `flags` is one of `O_RDONLY`, `O_WRONLY`
+/// and `O_RDWR`, or'ed with `O_CREAT`, `O_TRUNC` and `O_APPEND`. Directories
+/// can only be opened read-only.
+pub fn open(path: &str, flags: u64) -> OsResult<FileDescriptor> {
+    let fd: u64;
+    let ecode: u64;
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(fd), "=r"(ecode)
+            : "r"(path.as_ptr()), "r"(path.len()), "r"(flags), "i"(NR_OPEN)
+            : "x0", "x1", "x2", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, FileDescriptor(fd))
+}
+
+/// Reads from the file `descriptor` into `buf` at its current offset and
+/// returns the number of bytes read, which is 0 at the end of the file.
+pub fn readfile(descriptor: FileDescriptor, buf: &mut [u8]) -> OsResult<usize> {
+    let read: usize;
+    let ecode: u64;
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(read), "=r"(ecode)
+            : "r"(descriptor.raw()), "r"(buf.as_mut_ptr()), "r"(buf.len()), "i"(NR_READFILE)
+            : "x0", "x1", "x2", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, read)
+}
+
+/// Writes `buf` to the file `descriptor` at its current offset and returns
+/// the number of bytes written.
+pub fn writefile(descriptor: FileDescriptor, buf: &[u8]) -> OsResult<usize> {
+    let written: usize;
+    let ecode: u64;
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(written), "=r"(ecode)
+            : "r"(descriptor.raw()), "r"(buf.as_ptr()), "r"(buf.len()), "i"(NR_WRITEFILE)
+            : "x0", "x1", "x2", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, written)
+}
+
+/// Moves the offset of the file `descriptor` and returns the new offset from
+/// the start of the file.
+pub fn lseek(descriptor: FileDescriptor, pos: SeekFrom) -> OsResult<u64> {
+    let (offset, whence) = match pos {
+        SeekFrom::Start(offset) => (offset as i64, SEEK_SET),
+        SeekFrom::Current(offset) => (offset, SEEK_CUR),
+        SeekFrom::End(offset) => (offset, SEEK_END),
+    };
+    let new_offset: u64;
+    let ecode: u64;
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+            : "=r"(new_offset), "=r"(ecode)
+            : "r"(descriptor.raw()), "r"(offset), "r"(whence), "i"(NR_LSEEK)
+            : "x0", "x1", "x2", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, new_offset)
+}
+
+/// Closes the file `descriptor`, writing its changes to the disk. The
+/// descriptor is closed even if that fails.
+pub fn close(descriptor: FileDescriptor) -> OsResult<()> {
+    let ecode: u64;
+    unsafe {
+        asm!("mov x0, $1
+              svc $2
+              mov $0, x7"
+            : "=r"(ecode)
+            : "r"(descriptor.raw()), "i"(NR_CLOSE)
+            : "x0", "x7"
+            : "volatile");
+    }
+    err_or!(ecode, ())
+}
+
 pub fn sock_create() -> SocketDescriptor {
     // Lab 5 2.D
     unimplemented!("sock_create")
@@ -124,6 +124,7 @@ impl<V: Default + Debug> LRUCache<V> {
     pub fn clear(&mut self) {
         self.head = 0;
         self.tail = 0;
+        self.next_empty = 0;
         self.map.clear();
     }
 
@@ -133,21 +134,9 @@ impl<V: Default + Debug> LRUCache<V> {
 
     pub fn get(&mut self, key: u64) -> Option<&V> {
         match self.map.get(&key) {
-            Some(index) => {
-                if self.tail == *index {
-                    self.tail = self.entries[*index as usize].prev;
-                    self.head = *index;
-                    return Some(&self.entries[*index as usize].val);
-                }
-                let hit_entry = &self.entries[*index as usize];
-                let hit_entry_prev = hit_entry.prev;
-                let hit_entry_next = hit_entry.next;
-                self.entries[hit_entry_prev as usize].next = hit_entry_next;
-                self.entries[hit_entry_next as usize].prev = hit_entry_prev;
-                self.entries[*index as usize].prev = self.tail;
-                self.entries[*index as usize].next = self.head;
-                self.head = *index;
-                Some(&self.entries[*index as usize].val)
+            Some(&index) => {
+                self.ll_move_to_head(index);
+                Some(&self.entries[index as usize].val)
             },
             None => None
         }
@@ -155,26 +144,41 @@ impl<V: Default + Debug> LRUCache<V> {
 
     pub fn get_mut(&mut self, key: u64) -> Option<&mut V> {
         match self.map.get(&key) {
-            Some(index) => {
-                if self.tail == *index {
-                    self.tail = self.entries[*index as usize].prev;
-                    self.head = *index;
-                    return Some(&mut self.entries[*index as usize].val);
-                }
-                let hit_entry = &self.entries[*index as usize];
-                let hit_entry_prev = hit_entry.prev;
-                let hit_entry_next = hit_entry.next;
-                self.entries[hit_entry_prev as usize].next = hit_entry_next;
-                self.entries[hit_entry_next as usize].prev = hit_entry_prev;
-                self.entries[*index as usize].prev = self.tail;
-                self.entries[*index as usize].next = self.head;
-                self.head = *index;
-                Some(&mut self.entries[*index as usize].val)
+            Some(&index) => {
+                self.ll_move_to_head(index);
+                Some(&mut self.entries[index as usize].val)
             },
             None => None
         }
     }
 
+    /// Returns the value of `key` without marking it as recently used.
+    pub fn peek(&self, key: u64) -> Option<&V> {
+        self.map.get(&key).map(|&index| &self.entries[index as usize].val)
+    }
+
+    /// Returns the least recently used key-value pair, i.e. the entry `put`
+    /// evicts next once the cache is full. The recency order of the entries
+    /// is left untouched.
+    pub fn lru_mut(&mut self) -> Option<(u64, &mut V)> {
+        if self.is_empty() {
+            return None;
+        }
+        let entry = &mut self.entries[self.tail as usize];
+        Some((entry.key, &mut entry.val))
+    }
+
+    /// Returns an iterator over all cached key-value pairs. The recency order
+    /// of the entries is left untouched.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (u64, &mut V)> {
+        let map = &self.map;
+        self.entries
+            .iter_mut()
+            .enumerate()
+            .filter(move |(i, entry)| map.get(&entry.key) == Some(&(*i as u64)))
+            .map(|(_, entry)| (entry.key, &mut entry.val))
+    }
+
     pub fn put(&mut self, key: u64, val: V) {
         if self.map.contains_key(&key) { 
             // index = self.map.get(&key).unwrap();
@@ -190,6 +194,29 @@ impl<V: Default + Debug> LRUCache<V> {
         self.map.insert(key, self.head);
     }
 
+    #[inline(always)]
+    fn ll_move_to_head(&mut self, index: u64) {
+        if index == self.head {
+            return;
+        }
+        if index == self.tail {
+            // the list is a ring, so moving the tail is a rotation
+            self.tail = self.entries[index as usize].prev;
+            self.head = index;
+            return;
+        }
+        // unlink the entry and relink it between tail and head
+        let prev = self.entries[index as usize].prev;
+        let next = self.entries[index as usize].next;
+        self.entries[prev as usize].next = next;
+        self.entries[next as usize].prev = prev;
+        self.entries[index as usize].prev = self.tail;
+        self.entries[index as usize].next = self.head;
+        self.entries[self.head as usize].prev = index;
+        self.entries[self.tail as usize].next = index;
+        self.head = index;
+    }
+
     #[inline(always)]
     fn ll_push_full(&mut self, key: u64, val: V) {
         // when the ll is full, push according to tail
@@ -205,12 +232,12 @@ impl<V: Default + Debug> LRUCache<V> {
     fn ll_push_not_full(&mut self, key: u64, val: V) {
         // when the ll is not full, push according to head
         if self.is_empty() {
-            self.entries[self.next_empty as usize] = Entry::new(key, val, 0, 0);
-            self.head = 0;
-            self.tail = 0;
+            self.entries[self.next_empty as usize] = Entry::new(key, val, self.next_empty, self.next_empty);
+            self.head = self.next_empty;
+            self.tail = self.next_empty;
             self.next_empty += 1;
         } else {
-            self.entries[self.next_empty as usize] = Entry::new(key, val, 0, self.next_empty);
+            self.entries[self.next_empty as usize] = Entry::new(key, val, self.tail, self.head);
             self.entries[self.head as usize].prev = self.next_empty;
             self.entries[self.tail as usize].next = self.next_empty;
             self.head = self.next_empty;
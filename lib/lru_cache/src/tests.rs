@@ -89,3 +89,46 @@ fn test_get_before_full() {
     lru.put(4, 4);
     assert_eq!(*lru.get(2).unwrap(), 2);
 }
+
+#[test]
+fn test_lru_order() {
+    let mut lru = LRUCache::<u64>::new();
+    for i in 0..64 {
+        lru.put(i, i);
+    }
+    assert_eq!(lru.lru_mut().map(|(key, _)| key), Some(0));
+    // hits in the middle, at the head and at the tail of the list
+    lru.get(10);
+    lru.get(10);
+    lru.get(0);
+    assert_eq!(lru.lru_mut().map(|(key, _)| key), Some(1));
+    for i in 1..64 {
+        if i != 10 {
+            lru.get(i);
+        }
+    }
+    assert_eq!(lru.lru_mut().map(|(key, _)| key), Some(10));
+    lru.put(100, 100);
+    assert_eq!(lru.get(10), None);
+    assert_eq!(lru.lru_mut().map(|(key, _)| key), Some(0));
+
+    lru.clear();
+    assert!(lru.lru_mut().is_none());
+    lru.put(1, 1);
+    assert_eq!(lru.lru_mut().map(|(key, _)| key), Some(1));
+}
+
+#[test]
+fn test_peek() {
+    let mut lru = LRUCache::<u64>::new();
+    for i in 0..64 {
+        lru.put(i, i * 2);
+    }
+    assert_eq!(lru.peek(0), Some(&0));
+    assert_eq!(lru.peek(63), Some(&126));
+    assert_eq!(lru.peek(64), None);
+    // peeking leaves the recency order untouched
+    assert_eq!(lru.lru_mut().map(|(key, _)| key), Some(0));
+    lru.put(64, 128);
+    assert_eq!(lru.peek(0), None);
+}
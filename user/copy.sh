@@ -17,7 +17,7 @@ done
 (cd ../kern5; make)
 
 for d in ${PROGS[@]}; do
-    cp $d/build/$d.bin $CS3210_COPY/$d
+    cp $d/build/$d.elf $CS3210_COPY/$d
 done
 
 cp ../kern5/build/kernel.bin $CS3210_COPY/kernel.bin 
@@ -8,9 +8,10 @@ mod cr0;
 use core::time::Duration;
 
 use kernel_api::syscall::*;
+use kernel_api::env::{Args, Vars};
 use kernel_api::{print, println, OsResult};
 
-fn main() {
+fn main(_args: Args, _env: Vars) {
     let result = main_inner();
     if let Err(error) = result {
         println!("Terminating with error: {:?}", error);
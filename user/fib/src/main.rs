@@ -9,6 +9,7 @@ mod cr0;
 
 extern crate alloc;
 
+use kernel_api::env::{Args, Vars};
 use kernel_api::println;
 use kernel_api::syscall::{fork, getpid, time, exit};
 use allocator::allocator::Allocator;
@@ -25,11 +26,15 @@ fn fib(n: u64) -> u64 {
     }
 }
 
-fn main() {
+/// fib [n]
+///
+/// Computes the `n`th Fibonacci number, the 40th by default.
+fn main(mut args: Args, _env: Vars) {
+    let n = args.nth(1).and_then(|n| n.parse().ok()).unwrap_or(40);
     let beg = time();
     let pid = getpid();
     println!("[{:02}] Started: {:?}", pid, beg);
-    let rtn = fib(40);
+    let rtn = fib(n);
     let end = time();
     println!("[{:02}] Ended: {:?}", pid, end);
     println!("[{:02}] Result: {} ({:?})", pid, rtn, end - beg);
@@ -2,6 +2,8 @@ use core::mem::zeroed;
 use core::panic::PanicInfo;
 use core::ptr::write_volatile;
 
+use kernel_api::env::{Args, Vars};
+
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
     loop {}
@@ -24,10 +26,12 @@ unsafe fn zeros_bss() {
     }
 }
 
+/// The entry of the program. The kernel passes the arguments and the
+/// environment as described in `kernel_api::env`.
 #[no_mangle]
-pub unsafe extern "C" fn _start() -> ! {
+pub unsafe extern "C" fn _start(_argc: usize, argv: *const *const u8, envp: *const *const u8) -> ! {
     zeros_bss();
     ALLOCATOR.initialize();
-    crate::main();
-    kernel_api::syscall::exit();
+    crate::main(Args::from_raw(argv), Vars::from_raw(envp));
+    kernel_api::syscall::exit(0);
 }
@@ -9,8 +9,9 @@ mod cr0;
 
 extern crate alloc;
 
-use kernel_api::println;
-use kernel_api::syscall::{fork, getpid, time, exit, sleep};
+use kernel_api::env::{Args, Vars};
+use kernel_api::{println, OsError};
+use kernel_api::syscall::{fork, getpid, time, exit, sleep, waitpid};
 use allocator::allocator::Allocator;
 use alloc::string::String;
 
@@ -25,19 +26,29 @@ fn fib(n: u64) -> u64 {
     }
 }
 
-fn main() {
-    for _ in 0..3 {
+fn main(_args: Args, _env: Vars) {
+    for i in 0..3 {
         match fork() {
-            Ok(id) => {
-                let pid = getpid();
-                if id == 0 {
-                    println!("I am child process. My id is {}", getpid());
-                } else {
-                    println!("I am parent process. My id is {}", getpid());
-                }
+            Ok(0) => {
+                println!("I am child process {}. My id is {}", i, getpid());
+                // the first child exits last
+                let _ = sleep(core::time::Duration::from_millis(1000 * (3 - i)));
+                exit(i as i32);
             },
+            Ok(id) => println!("I am parent process. My id is {}, my child's is {}", getpid(), id),
             Err(e) => println!("Err: {:#?}", e)
         }
     }
-    sleep(core::time::Duration::from_millis(3000));
+
+    // reap the children in the order they exit
+    loop {
+        match waitpid(None, 0) {
+            Ok(Some((id, code))) => println!("Child {} exited with code {}", id, code),
+            Ok(None) | Err(OsError::NoChild) => break,
+            Err(e) => {
+                println!("Err: {:#?}", e);
+                break;
+            }
+        }
+    }
 }
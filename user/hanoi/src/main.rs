@@ -9,6 +9,7 @@ mod cr0;
 
 extern crate alloc;
 
+use kernel_api::env::{Args, Vars};
 use kernel_api::println;
 use kernel_api::syscall::{fork, getpid, time, exit, sleep};
 use allocator::allocator::Allocator;
@@ -25,12 +26,16 @@ pub fn hanoi(n: i32, from: i32, to: i32, via: i32, steps: &mut u64) {
     }
   }
 
-fn main() {
+/// hanoi [disks]
+///
+/// Solves the towers of Hanoi with `disks` disks, 25 by default.
+fn main(mut args: Args, _env: Vars) {
+    let disks = args.nth(1).and_then(|n| n.parse().ok()).unwrap_or(25);
     let beg = time();
     let pid = getpid();
     println!("[{:02}] Started: {:?}", pid, beg);
     let mut steps = 0;
-    hanoi(25, 1, 3, 2, &mut steps);
+    hanoi(disks, 1, 3, 2, &mut steps);
     let end = time();
     println!("[{:02}] Ended: {:?}", pid, end);
     println!("[{:02}] Result: {} ({:?})", pid, steps, end - beg);
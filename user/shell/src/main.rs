@@ -9,12 +9,16 @@ mod cr0;
 
 extern crate alloc;
 
+use alloc::format;
+use alloc::string::String;
+
 use kernel_api::{println, print};
+use kernel_api::env::{Args, Vars};
+use kernel_api::{Dirents, FileDescriptor, OsError, OsResult, O_RDONLY, WNOHANG};
 use allocator::allocator::Allocator;
 
 // use shim::io;
 use shim::path::PathBuf;
-use shim::path::Component::*;
 use core::str;
 use stack_vec::StackVec;
 
@@ -64,15 +68,16 @@ impl<'a> Command<'a> {
 
 /// Starts a shell using `prefix` as the prefix for each line. This function
 /// never returns.
-pub fn main() {
+pub fn main(_args: Args, _env: Vars) {
     let prefix = "> ";
     // Accept commands at most 512 bytes in length.
     let mut line_buf = [0u8;512];
     let mut line_buf = StackVec::new(&mut line_buf);
-    let mut cwd = PathBuf::from("/");
+    let mut cwd = working_dir();
     let mut exit = false;
 
     while !exit {
+        reap_orphans();
         // Clear input line buf.
         line_buf.truncate(0);
         // Prefix before user entering command.
@@ -85,7 +90,13 @@ pub fn main() {
         let cmd = str::from_utf8(&line_buf).unwrap();
         parse_and_run(&mut cwd, cmd, &mut exit);
     }
-    syscall::exit();
+    syscall::exit(0);
+}
+
+/// Reaps the zombie children that are not waited for by a command, such as
+/// the orphans the shell adopts as the first process.
+fn reap_orphans() {
+    while let Ok(Some(_)) = syscall::waitpid(None, WNOHANG) {}
 }
 
 fn read_command(buf: &mut StackVec<u8>) {
@@ -133,9 +144,10 @@ fn parse_and_run(cwd: &mut PathBuf, line: &str, exit: &mut bool) {
         "echo" => println!("{}", line[cmd.args[0].len()..].trim_start()),
         "yield" => syscall::r#yield(),
         "pwd" => cmd_pwd(),
-        // "cd" => cmd_cd(cwd, &cmd),
-        // "ls" => cmd_ls(cwd, &cmd),
-        // "cat" => cmd_cat(cwd, &cmd),
+        "cd" => cmd_cd(cwd, &cmd),
+        "ls" => cmd_ls(&cmd),
+        "cat" => cmd_cat(&cmd),
+        "exec" => cmd_exec(&cmd),
         "sleep" => cmd_sleep(cwd, &cmd),
         "time" => cmd_time(cwd),
         "getpid" => cmd_getpid(cwd),
@@ -144,15 +156,22 @@ fn parse_and_run(cwd: &mut PathBuf, line: &str, exit: &mut bool) {
         // "sp" => cmd_sp(cwd),
         "exit" => *exit = true,
         "getpriority" => cmd_getpriority(cwd),
-        _ => println!("unknown command: {}", cmd.path()),
+        _ => cmd_run(&cmd),
     }
 }
 
-// Print the working directory.
+/// Print the working directory.
 fn cmd_pwd() {
-    let mut buf = [0u8; 32];
-    syscall::getcwd(&mut buf, 32);
-    println!("{}", core::str::from_utf8(&buf).unwrap());
+    println!("{}", working_dir().to_str().unwrap());
+}
+
+/// Returns the working directory of the shell, as kept by the kernel.
+fn working_dir() -> PathBuf {
+    let mut buf = [0u8; 256];
+    match syscall::getcwd(&mut buf) {
+        Ok(len) if len > 0 => PathBuf::from(str::from_utf8(&buf[..len.min(buf.len())]).unwrap_or("/")),
+        _ => PathBuf::from("/"),
+    }
 }
 
 /// Change working directory.
@@ -162,152 +181,83 @@ fn cmd_pwd() {
 /// ***cd \<directory\>***  
 /// 
 /// If there are no argument, working directory will be set to root directory.
-// fn cmd_cd(cwd: &mut PathBuf, cmd: &Command) {
-//     let path: PathBuf = if cmd.args.len() > 2 {
-//         println!("sh: cd: too many arguments");
-//         return;
-//     } else if cmd.args.len() == 1 {
-//         cwd.push("/");
-//         return;
-//     } else {
-//         cmd.args[1].into()
-//     };
-
-//     // absolute directory
-//     let dir = match parse_input_path(cwd, &path) {
-//         Ok(dir) => dir,
-//         Err(e) => {
-//             println!("sh: cd: {}", e);
-//             return;
-//         }
-//     };
-//     match FILESYSTEM.open(&dir) {
-//         Ok(entry) => match entry {
-//             fat32::vfat::Entry::Dir(_) => cwd.push(dir),
-//             fat32::vfat::Entry::File(_) => println!("sh: cd: {}: Not a directory", dir.display()),
-//         },
-//         _ => println!("sh: cd: invalid input"),
-//     }
-// }
-
-// /// List the files in a directory.
-// ///
-// /// ## Format
-// /// 
-// /// ***ls [-a] [directory]***
-// ///
-// /// ## Options
-// ///
-// /// + `-a`: if passed in, hidden files are displayed, otherwise not displayed
-// /// + `directory`: if not passed in, current working directory is displayed.
-// ///
-// /// ## Notice
-// ///
-// /// The arguments may be used together, but `-a` must be provided before `directory`
-// fn cmd_ls(cwd: &PathBuf, cmd: &Command) {
-//     let mut show_hidden = false;
-//     let mut use_cwd = true;
-//     let mut path = PathBuf::new();
-
-//     // parse arguments
-//     match cmd.args.len() {
-//         3 => {
-//             if cmd.args[1] != "-a" {
-//                 println!("sh: ls: invalid option argument");
-//                 return;
-//             }
-//             show_hidden = true;
-//             use_cwd = false;
-//             path = match parse_input_path(cwd, &cmd.args[2].into()) {
-//                 Ok(path) => path,
-//                 Err(e) => {
-//                     println!("sh: ls: {}", e);
-//                     return;
-//                 } 
-//             }
-//         },
-//         2 => {
-//             show_hidden = if cmd.args[1] == "-a" { true } else { false };
-//             use_cwd = if show_hidden { true } else {
-//                 path = match parse_input_path(cwd, &cmd.args[1].into()) {
-//                     Ok(path) => path,
-//                     Err(e) => {
-//                         println!("sh: ls: {}", e);
-//                         return;
-//                     } 
-//                 };
-//                 false
-//             };
-//         },
-//         1 => {}, // use default value
-//         _ => {
-//             println!("sh: ls: too many arguments");
-//             return;
-//         },
-//     }
-
-//     // ls dir
-//     let path = if use_cwd { cwd } else { &path };
-//     match ls_path(path, show_hidden) {
-//         Err(e) => println!("sh: ls: {}", e),
-//         _ => {},
-//     }
-// }
-
-// fn parse_input_path<'a, 'b>(cwd: &'a PathBuf, path: &'b PathBuf) -> Result<PathBuf, &'b str> {
-//     // handle '.' and '..' in path
-//     let mut dir = PathBuf::new();
-//     for component in path.components() {
-//         match component {
-//             Prefix(_) => return Err("directory prefix not supported"),
-//             RootDir => dir.push("/"),
-//             CurDir => {},
-//             ParentDir => {
-//                 // precondition: if dir.file_name() is none,
-//                 // dir must be "(../)*" or "/"
-//                 if let None = dir.file_name() {
-//                     // 1. dir is "(../)*", push ".."
-//                     // 2. dir is root, do nothing
-//                     match dir.has_root() {
-//                         false => { dir.push(".."); },
-//                         true => {},
-//                     }
-//                 } else {
-//                     dir.pop();
-//                 }
-//             },
-//             Normal(name) => dir.push(name),
-//         }
-//     }
-
-//     // convert relative paths to absolute paths
-//     let mut cwd_back = cwd.to_path_buf();
-//     if !dir.has_root() {
-//         for component in dir.components() {
-//             match component {
-//                 ParentDir => { cwd_back.pop(); } ,
-//                 Normal(name) => cwd_back.push(name),
-//                 _ => return Err("sd: cd: parse failed, should not reach here"),
-//             };
-//         }
-//         dir = cwd_back;
-//     }
-
-//     Ok(dir)
-// }
-
-// fn ls_path<T: AsRef<Path>>(path: T, hidden: bool) -> io::Result<()> {
-//     match FILESYSTEM.open_dir(path) {
-//         Ok(dir) => {
-//             for entry in dir.entries()? {
-//                 if entry.is_hidden() && !hidden { continue; }
-//                 println!("{}", entry);
-//             }
-//             Ok(())
-//         },
-//         Err(e) => Err(e),
-//     }
-// }
+fn cmd_cd(cwd: &mut PathBuf, cmd: &Command) {
+    let path = match cmd.args.len() {
+        1 => "/",
+        2 => cmd.args[1],
+        _ => {
+            println!("sh: cd: too many arguments");
+            return;
+        }
+    };
+
+    match syscall::chdir(path) {
+        Ok(()) => *cwd = working_dir(),
+        Err(OsError::InvalidArgument) => println!("sh: cd: {}: Not a directory", path),
+        Err(e) => println!("sh: cd: {}: {:?}", path, e),
+    }
+}
+
+/// List the files in a directory.
+///
+/// ## Format
+/// 
+/// ***ls [-a] [directory]***
+///
+/// ## Options
+///
+/// + `-a`: if passed in, hidden files are displayed, otherwise not displayed
+/// + `directory`: if not passed in, current working directory is displayed.
+///
+/// ## Notice
+///
+/// The arguments may be used together, but `-a` must be provided before `directory`
+fn cmd_ls(cmd: &Command) {
+    let show_hidden = cmd.args.len() > 1 && cmd.args[1] == "-a";
+    let args = &cmd.args[if show_hidden { 2 } else { 1 }..];
+    let path = match args.len() {
+        0 => ".",
+        1 => args[0],
+        _ => {
+            println!("sh: ls: too many arguments");
+            return;
+        }
+    };
+
+    if let Err(e) = ls_path(path, show_hidden) {
+        println!("sh: ls: {}: {:?}", path, e);
+    }
+}
+
+fn ls_path(path: &str, show_hidden: bool) -> OsResult<()> {
+    let fd = syscall::open(path, O_RDONLY)?;
+    let result = print_entries(fd, show_hidden);
+    let closed = syscall::close(fd);
+    result.and(closed)
+}
+
+/// Prints a line for each entry of the open directory `fd`: its type, `d`
+/// or `f`, whether it is read-only, `r`, or writable, `w`, its size and its
+/// name.
+fn print_entries(fd: FileDescriptor, show_hidden: bool) -> OsResult<()> {
+    let mut buf = [0u8; 1024];
+    loop {
+        let written = syscall::getdents(fd, &mut buf)?;
+        if written == 0 {
+            return Ok(());
+        }
+        for (dirent, name) in Dirents::new(&buf[..written]) {
+            if dirent.hidden() && !show_hidden {
+                continue;
+            }
+            println!("{}{} {:>10} {}",
+                     if dirent.is_dir() { 'd' } else { 'f' },
+                     if dirent.read_only() { 'r' } else { 'w' },
+                     dirent.size,
+                     name);
+        }
+    }
+}
 
 /// Concatenate files.
 ///
@@ -317,47 +267,125 @@ fn cmd_pwd() {
 ///
 /// Prints the contents of the files at the provided paths, one after the other.
 /// At least one path argument is required.
-// fn cmd_cat(cwd: &PathBuf, cmd: &Command) {
-//     if cmd.args.len() == 1 {
-//         println!("sh: cat: too less arguments");
-//         return;
-//     }
-
-//     for arg in cmd.args[1..].iter() {
-//         let path = match parse_input_path(cwd, &PathBuf::from(*arg)) {
-//             Ok(path) => path,
-//             Err(e) => {
-//                 println!("sh: cat: {}", e);
-//                 continue;
-//             }
-//         };
-//         match print_file(&path) {
-//             Err(e) => println!("sh: cat: {}", e),
-//             _ => {},
-//         };
-//     }
-// }
-
-// fn print_file(path: &PathBuf) -> io::Result<()> {
-//     let mut file = FILESYSTEM.open_file(path)?;
-//     let mut buf = [0u8; 2048];
-
-//     loop {
-//         use shim::io::Read;
-//         use shim::ioerr;
-
-//         let read_size = file.read(&mut buf)?;
-//         if read_size == 0 { break; }
-
-//         let content = match str::from_utf8(&buf[..read_size]) {
-//             Ok(s) => s,
-//             Err(_) => return ioerr!(Other, "file contains invalid utf-8 character"),
-//         };
-//         print!("{}", content);
-//     }
-
-//     Ok(())
-// }
+fn cmd_cat(cmd: &Command) {
+    if cmd.args.len() == 1 {
+        println!("sh: cat: too less arguments");
+        return;
+    }
+
+    for path in cmd.args[1..].iter() {
+        if let Err(e) = print_file(path) {
+            println!("sh: cat: {}: {:?}", path, e);
+        }
+    }
+}
+
+fn print_file(path: &str) -> OsResult<()> {
+    let fd = syscall::open(path, O_RDONLY)?;
+    let result = print_contents(fd);
+    let closed = syscall::close(fd);
+    result.and(closed)
+}
+
+/// Prints the contents of the open file `fd` as UTF-8, replacing invalid
+/// bytes with U+FFFD. A character split between two reads is carried over
+/// to the next one.
+fn print_contents(fd: FileDescriptor) -> OsResult<()> {
+    let mut buf = [0u8; 2048];
+    // the bytes of an incomplete character at the start of `buf`
+    let mut pending = 0;
+    loop {
+        let read = syscall::readfile(fd, &mut buf[pending..])?;
+        let end = pending + read;
+        let mut bytes = &buf[..end];
+        pending = 0;
+        loop {
+            match str::from_utf8(bytes) {
+                Ok(s) => {
+                    print!("{}", s);
+                    break;
+                }
+                Err(e) => {
+                    let (valid, rest) = bytes.split_at(e.valid_up_to());
+                    print!("{}", str::from_utf8(valid).unwrap());
+                    match e.error_len() {
+                        Some(len) => {
+                            print!("\u{FFFD}");
+                            bytes = &rest[len..];
+                        }
+                        None if read == 0 => {
+                            print!("\u{FFFD}");
+                            break;
+                        }
+                        None => {
+                            pending = rest.len();
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        if read == 0 {
+            return Ok(());
+        }
+        for i in 0..pending {
+            buf[i] = buf[end - pending + i];
+        }
+    }
+}
+
+/// Replace the shell with a program.
+///
+/// ## Format
+///
+/// ***exec <path> [args..]***
+///
+/// The program is started with the path and the arguments as its arguments,
+/// and an empty environment. Only returns if the program cannot be started.
+fn cmd_exec(cmd: &Command) {
+    if cmd.args.len() == 1 {
+        println!("sh: exec: too less arguments");
+        return;
+    }
+
+    let e = syscall::exec(cmd.args[1], &cmd.args[1..], &[]);
+    println!("sh: exec: {}: {:?}", cmd.args[1], e);
+}
+
+/// Run a program in the foreground.
+///
+/// ## Format
+///
+/// ***<path> [args..]***
+///
+/// A path without `/` names a program in the root directory. The program is
+/// started in a child process with the path and the arguments as its
+/// arguments, and the shell waits for it to exit.
+fn cmd_run(cmd: &Command) {
+    let path = if cmd.path().contains('/') {
+        String::from(cmd.path())
+    } else {
+        format!("/{}", cmd.path())
+    };
+
+    match syscall::fork() {
+        Ok(0) => {
+            match syscall::exec(&path, &cmd.args, &[]) {
+                OsError::NoEntry => println!("unknown command: {}", cmd.path()),
+                e => println!("sh: {}: {:?}", cmd.path(), e),
+            }
+            syscall::exit(127);
+        },
+        Ok(child) => match syscall::waitpid(Some(child), 0) {
+            // 127: the child has reported why the program could not start
+            Ok(Some((_, 0))) | Ok(Some((_, 127))) => {},
+            Ok(Some((_, code))) => println!("sh: {}: exited with code {}", cmd.path(), code),
+            Ok(None) => unreachable!(),
+            Err(e) => println!("sh: {}: {:?}", cmd.path(), e),
+        },
+        Err(e) => println!("sh: fork: {:?}", e),
+    }
+}
 
 /// Sleep ms.
 ///
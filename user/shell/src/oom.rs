@@ -4,5 +4,5 @@ use core::alloc::Layout;
 pub fn oom(_layout: Layout) -> ! {
     loop {
     }
-    kernel_api::syscall::exit();
+    kernel_api::syscall::exit(1);
 }